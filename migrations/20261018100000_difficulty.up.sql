@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE blocks
+ADD COLUMN difficulty INTEGER NOT NULL DEFAULT 20;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- Timestamps become integer milliseconds
+ALTER TABLE blocks ADD COLUMN timestamp_ms INTEGER NOT NULL DEFAULT 0;
+UPDATE blocks SET timestamp_ms = CAST(timestamp * 1000 AS INTEGER);
+ALTER TABLE blocks DROP COLUMN timestamp;
+ALTER TABLE blocks RENAME COLUMN timestamp_ms TO timestamp;
+
+-- Blocks mined before the header existed are version 0
+ALTER TABLE blocks ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
+
+-- data was the Merkle root followed by the JSON of the transactions
+ALTER TABLE blocks ADD COLUMN merkle_root TEXT NOT NULL DEFAULT '';
+ALTER TABLE blocks ADD COLUMN body TEXT NOT NULL DEFAULT '[]';
+UPDATE blocks SET merkle_root = substr(data, 1, 64), body = substr(data, 65);
+ALTER TABLE blocks DROP COLUMN data;
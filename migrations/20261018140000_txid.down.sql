@@ -0,0 +1,5 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS transactions_txid;
+
+ALTER TABLE transactions
+DROP COLUMN txid;
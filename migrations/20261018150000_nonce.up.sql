@@ -0,0 +1,21 @@
+-- Add up migration script here
+ALTER TABLE transactions
+ADD COLUMN nonce INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE wallets
+ADD COLUMN nonce INTEGER NOT NULL DEFAULT 0;
+
+-- Existing transactions of an account are numbered in the order they were accepted
+UPDATE transactions
+SET nonce = (
+    SELECT COUNT(*) FROM transactions t
+    WHERE t.from_address = transactions.from_address AND t.rowid < transactions.rowid
+)
+WHERE from_address != '';
+
+UPDATE wallets
+SET nonce = (SELECT COUNT(*) FROM transactions t WHERE t.from_address = wallets.address);
+
+-- txids cover the nonce, the node recomputes them on boot
+UPDATE transactions
+SET txid = NULL;
@@ -0,0 +1,13 @@
+-- Add up migration script here
+-- Blocks of competing branches, off the main chain. Several can share an idx, so they are keyed by hash.
+CREATE TABLE IF NOT EXISTS side_blocks (
+    hash TEXT PRIMARY KEY,
+    idx INTEGER NOT NULL,
+    version INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    merkle_root TEXT NOT NULL,
+    body TEXT NOT NULL,
+    previous_hash TEXT NOT NULL,
+    nonce BIGINT NOT NULL,
+    difficulty INTEGER NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE side_blocks DROP COLUMN signature;
+ALTER TABLE blocks DROP COLUMN signature;
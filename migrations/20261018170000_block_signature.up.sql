@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- Seal of proof of authority blocks: the slot signer's signature over the block hash. NULL for proof of work.
+ALTER TABLE blocks ADD COLUMN signature TEXT;
+ALTER TABLE side_blocks ADD COLUMN signature TEXT;
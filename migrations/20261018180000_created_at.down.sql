@@ -0,0 +1,24 @@
+-- Add down migration script here
+CREATE TABLE transactions_new (
+    from_address TEXT NOT NULL,
+    to_address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    sig TEXT NOT NULL,
+    added_to_block INTEGER NOT NULL DEFAULT 0,
+    created_at REAL NOT NULL DEFAULT (strftime('%f','now') * 1000.0),
+    block_id INTEGER REFERENCES blocks(idx),
+    fee INTEGER NOT NULL DEFAULT 0,
+    txid TEXT,
+    nonce INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO transactions_new (rowid, from_address, to_address, amount, sig, added_to_block, created_at, block_id, fee, txid, nonce)
+SELECT rowid, from_address, to_address, amount, sig, added_to_block, created_at, block_id, fee, txid, nonce
+FROM transactions;
+
+DROP TABLE transactions;
+
+ALTER TABLE transactions_new
+RENAME TO transactions;
+
+CREATE UNIQUE INDEX IF NOT EXISTS transactions_txid ON transactions (txid);
@@ -0,0 +1,36 @@
+-- Add up migration script here
+-- created_at is in seconds since the unix epoch, as coinbases and the in-memory store set it.
+-- SQLite cannot change a column default, so the table is rebuilt with the same rowids.
+CREATE TABLE transactions_new (
+    from_address TEXT NOT NULL,
+    to_address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    sig TEXT NOT NULL,
+    added_to_block INTEGER NOT NULL DEFAULT 0,
+    created_at REAL NOT NULL DEFAULT ((julianday('now') - 2440587.5) * 86400.0),
+    block_id INTEGER REFERENCES blocks(idx),
+    fee INTEGER NOT NULL DEFAULT 0,
+    txid TEXT,
+    nonce INTEGER NOT NULL DEFAULT 0
+);
+
+-- The old default only kept the seconds within the minute: confirmed transactions take the time
+-- of their block instead, pending ones the time of the migration
+INSERT INTO transactions_new (rowid, from_address, to_address, amount, sig, added_to_block, created_at, block_id, fee, txid, nonce)
+SELECT rowid, from_address, to_address, amount, sig, added_to_block,
+    CASE
+        WHEN created_at >= 100000 THEN created_at
+        ELSE COALESCE(
+            (SELECT NULLIF(timestamp, 0) / 1000.0 FROM blocks WHERE blocks.idx = transactions.block_id),
+            (julianday('now') - 2440587.5) * 86400.0
+        )
+    END,
+    block_id, fee, txid, nonce
+FROM transactions;
+
+DROP TABLE transactions;
+
+ALTER TABLE transactions_new
+RENAME TO transactions;
+
+CREATE UNIQUE INDEX IF NOT EXISTS transactions_txid ON transactions (txid);
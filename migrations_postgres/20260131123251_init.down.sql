@@ -0,0 +1,2 @@
+-- Add down migration script here
+DROP TABLE blocks;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS blocks (
+  idx SERIAL PRIMARY KEY,
+  timestamp DOUBLE PRECISION NOT NULL DEFAULT (EXTRACT(EPOCH FROM now()) * 1000),
+  data TEXT   NOT NULL,
+  previous_hash TEXT   NOT NULL,
+  hash TEXT   NOT NULL,
+  nonce BIGINT NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- rowid numbers the rows in insertion order, like the implicit rowid of SQLite
+CREATE TABLE IF NOT EXISTS transactions (
+    rowid BIGSERIAL PRIMARY KEY,
+    from_address TEXT NOT NULL,
+    to_address TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    sig TEXT NOT NULL,
+    added_to_block BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DOUBLE PRECISION NOT NULL DEFAULT (EXTRACT(EPOCH FROM now()) * 1000)
+);
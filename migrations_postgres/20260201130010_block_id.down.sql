@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE transactions
+DROP COLUMN block_id;
\ No newline at end of file
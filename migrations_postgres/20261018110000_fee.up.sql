@@ -0,0 +1,3 @@
+-- Add up migration script here
+ALTER TABLE transactions
+ADD COLUMN fee INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+ALTER TABLE wallets
+ADD COLUMN initial_balance INTEGER NOT NULL DEFAULT 0;
+
+-- Existing wallets were funded outside of the chain: whatever their transactions do not explain
+UPDATE wallets
+SET initial_balance = balance
+    - COALESCE((SELECT SUM(t.amount) FROM transactions t WHERE t.to_address = wallets.address), 0)
+    + COALESCE((SELECT SUM(t.amount + t.fee) FROM transactions t WHERE t.from_address = wallets.address), 0);
@@ -0,0 +1,11 @@
+-- Add down migration script here
+ALTER TABLE blocks ADD COLUMN data TEXT NOT NULL DEFAULT '';
+UPDATE blocks SET data = merkle_root || CAST(body AS TEXT);
+ALTER TABLE blocks DROP COLUMN body;
+ALTER TABLE blocks DROP COLUMN merkle_root;
+ALTER TABLE blocks DROP COLUMN version;
+
+ALTER TABLE blocks ADD COLUMN timestamp_s DOUBLE PRECISION NOT NULL DEFAULT 0;
+UPDATE blocks SET timestamp_s = timestamp / 1000.0;
+ALTER TABLE blocks DROP COLUMN timestamp;
+ALTER TABLE blocks RENAME COLUMN timestamp_s TO timestamp;
@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- Timestamps become integer milliseconds
+ALTER TABLE blocks ADD COLUMN timestamp_ms BIGINT NOT NULL DEFAULT 0;
+UPDATE blocks SET timestamp_ms = CAST(timestamp * 1000 AS BIGINT);
+ALTER TABLE blocks DROP COLUMN timestamp;
+ALTER TABLE blocks RENAME COLUMN timestamp_ms TO timestamp;
+
+-- Blocks mined before the header existed are version 0. BIGINT holds every u32.
+ALTER TABLE blocks ADD COLUMN version BIGINT NOT NULL DEFAULT 0;
+
+-- data was the Merkle root followed by the JSON of the transactions
+ALTER TABLE blocks ADD COLUMN merkle_root TEXT NOT NULL DEFAULT '';
+ALTER TABLE blocks ADD COLUMN body JSONB NOT NULL DEFAULT '[]';
+UPDATE blocks SET merkle_root = substr(data, 1, 64), body = CAST(substr(data, 65) AS JSONB);
+ALTER TABLE blocks DROP COLUMN data;
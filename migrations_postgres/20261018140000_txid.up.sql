@@ -0,0 +1,6 @@
+-- Add up migration script here
+ALTER TABLE transactions
+ADD COLUMN txid TEXT;
+
+-- Existing rows are backfilled by the node on boot, txids are SHA-256 hashes computed in Rust
+CREATE UNIQUE INDEX IF NOT EXISTS transactions_txid ON transactions (txid);
@@ -0,0 +1,10 @@
+-- Add down migration script here
+ALTER TABLE wallets
+DROP COLUMN nonce;
+
+ALTER TABLE transactions
+DROP COLUMN nonce;
+
+-- txids no longer cover the nonce, the node recomputes them on boot
+UPDATE transactions
+SET txid = NULL;
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE transactions ALTER COLUMN created_at SET DEFAULT (EXTRACT(EPOCH FROM now()) * 1000);
@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- created_at is in seconds since the unix epoch, like SQLite and the in-memory store
+ALTER TABLE transactions ALTER COLUMN created_at SET DEFAULT EXTRACT(EPOCH FROM now());
+
+-- Milliseconds from the previous default, no time in seconds is that far off yet
+UPDATE transactions
+SET created_at = created_at / 1000
+WHERE created_at > 100000000000;
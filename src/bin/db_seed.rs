@@ -1,42 +1,30 @@
-use rocket_db_pools::sqlx;
-use my_rust_blockchain::utils::*;
-use rand::{distr::Alphanumeric, Rng};
+use my_rust_blockchain::crypto::{address_from_public_key, generate_keypair};
+use my_rust_blockchain::store;
+use my_rust_blockchain::transactions::Wallet;
+use rand::Rng;
 use rocket::error;
 
 
 #[rocket::tokio::main]
-async fn main() -> Result<(), sqlx::Error> {
-    let pool = db_pool().await;
+async fn main() {
+    let store = store::connect().await.unwrap_or_else(|e| {
+        error!("failed to open the chain store: {}", e);
+        panic!("failed to open the chain store");
+    });
 
     for _i in 1..20 {
-        let address: String = rand::rng()
-            .sample_iter(&Alphanumeric)
-            .take(20) // Length of the string
-            .map(char::from)
-            .collect();
-        let pub_key: String = rand::rng()
-            .sample_iter(&Alphanumeric)
-            .take(20) // Length of the string
-            .map(char::from)
-            .collect();
+        let (secret_key, pub_key) = generate_keypair();
+        let address = address_from_public_key(&pub_key).expect("generated public key is valid");
         let mut rng = rand::rng();
+        let balance = rng.random_range(1..10000);
 
-        let _result = sqlx::query(
-            r#"
-            INSERT INTO wallets (address, balance, pub_key)
-            VALUES (?, ?, ?);
-            "#,
-        )
-        .bind(address)
-        .bind(rng.random_range(1..10000))
-        .bind(pub_key)
-        .execute(&pool)
-        .await
-        .unwrap_or_else(|e| {
+        let wallet = Wallet { address: address.clone(), balance, pub_key, initial_balance: balance, nonce: 0 };
+        store.put_wallet(&wallet).await.unwrap_or_else(|e| {
             error!("failed to insert wallet: {}", e);
             panic!("failed to insert wallet");
         });
+
+        // The secret keys are only printed, never stored, so keep them to sign test transactions
+        println!("{} {}", address, secret_key);
     }
-  
-    Ok(())
 }
@@ -0,0 +1,169 @@
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use my_rust_blockchain::crypto::{address_from_public_key, generate_keypair};
+use my_rust_blockchain::keystore::{decrypt_keystore, encrypt_keystore, read_keystore, write_keystore};
+use my_rust_blockchain::transactions::{Transaction, Wallet};
+use serde::Deserialize;
+
+
+#[derive(Parser)]
+#[command(name = "wallet", about = "Manage keys and submit transactions to a node")]
+struct Cli {
+    /// Base URL of the node
+    #[arg(long, env = "NODE_URL", default_value = "http://127.0.0.1:8000")]
+    node: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a new keypair and store it in a passphrase encrypted keystore file
+    New {
+        #[arg(long)]
+        keystore: PathBuf,
+    },
+    /// Print the address of a keystore
+    Address {
+        #[arg(long)]
+        keystore: PathBuf,
+    },
+    /// Build and sign a transaction, printing it as JSON without submitting it
+    Sign {
+        #[command(flatten)]
+        tx: TxArgs,
+    },
+    /// Build, sign and submit a transaction to the node's POST /tx
+    Send {
+        #[command(flatten)]
+        tx: TxArgs,
+    },
+    /// Query the balance of an address
+    Balance {
+        address: String,
+    },
+}
+
+#[derive(clap::Args)]
+struct TxArgs {
+    #[arg(long)]
+    keystore: PathBuf,
+    /// Recipient address
+    #[arg(long)]
+    to: String,
+    #[arg(long)]
+    amount: i32,
+    /// Fee paid to the miner of the block including the transaction
+    #[arg(long, default_value_t = 0)]
+    fee: i32,
+    /// Account nonce, fetched from the node when omitted
+    #[arg(long)]
+    nonce: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+
+#[rocket::tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    if let Err(e) = run(cli).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), String> {
+    match cli.command {
+        Command::New { keystore } => {
+            let passphrase = read_passphrase()?;
+            let (secret_key, pub_key) = generate_keypair();
+            let address = address_from_public_key(&pub_key)?;
+            let encrypted = encrypt_keystore(&secret_key, &pub_key, &address, &passphrase)?;
+            write_keystore(&keystore, &encrypted)?;
+            println!("{}", address);
+        }
+        Command::Address { keystore } => {
+            println!("{}", read_keystore(&keystore)?.address);
+        }
+        Command::Sign { tx } => {
+            let transaction = build_transaction(&cli.node, &tx).await?;
+            println!("{}", transaction.to_json());
+        }
+        Command::Send { tx } => {
+            let transaction = build_transaction(&cli.node, &tx).await?;
+            let response = reqwest::Client::new()
+                .post(format!("{}/tx", cli.node.trim_end_matches('/')))
+                .json(&transaction)
+                .send()
+                .await
+                .map_err(|e| format!("failed to submit transaction: {}", e))?;
+            let accepted: Transaction = parse_response(response).await?;
+            println!("{}", accepted.to_json());
+        }
+        Command::Balance { address } => {
+            println!("{}", get_wallet(&cli.node, &address).await?.balance);
+        }
+    }
+
+    Ok(())
+}
+
+async fn build_transaction(node: &str, args: &TxArgs) -> Result<Transaction, String> {
+    let keystore = read_keystore(&args.keystore)?;
+    let nonce = match args.nonce {
+        Some(nonce) => nonce,
+        None => get_wallet(node, &keystore.address).await?.nonce,
+    };
+    let passphrase = read_passphrase()?;
+    let secret_key = decrypt_keystore(&keystore, &passphrase)?;
+
+    let mut transaction = Transaction::new(keystore.address, args.to.clone(), args.amount, args.fee, nonce);
+    transaction.sign(&secret_key)?;
+    Ok(transaction)
+}
+
+async fn get_wallet(node: &str, address: &str) -> Result<Wallet, String> {
+    let response = reqwest::get(format!("{}/wallet/{}", node.trim_end_matches('/'), address))
+        .await
+        .map_err(|e| format!("failed to query wallet: {}", e))?;
+    parse_response(response).await
+}
+
+async fn parse_response<T: for<'de> Deserialize<'de>>(response: reqwest::Response) -> Result<T, String> {
+    let status = response.status();
+    if status.is_success() {
+        return response
+            .json::<T>()
+            .await
+            .map_err(|e| format!("failed to parse node response: {}", e));
+    }
+
+    match response.json::<ErrorBody>().await {
+        Ok(body) => Err(format!("node returned {}: {}", status, body.message)),
+        Err(_) => Err(format!("node returned {}", status)),
+    }
+}
+
+/// Passphrase from `WALLET_PASSPHRASE`, or read from stdin.
+fn read_passphrase() -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var("WALLET_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+
+    eprint!("passphrase: ");
+    io::stderr().flush().ok();
+    let mut passphrase = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut passphrase)
+        .map_err(|e| format!("failed to read passphrase: {}", e))?;
+    Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+}
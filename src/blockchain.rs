@@ -2,323 +2,970 @@ use sha2::{Digest, Sha256};
 use std::time::{SystemTime, UNIX_EPOCH};
 use sqlx::FromRow;
 use crate::utils::*;
-use rocket::{error, get, serde::json::Json, routes};
+use rocket::{debug, error, get, info, post, serde::json::Json, routes, warn, State};
+use rocket::tokio::{runtime::Handle, task};
 use serde::{Deserialize, Serialize};
-use crate::transactions::Transaction;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use crate::consensus::{ConsensusEngine, SharedConsensus};
+use crate::crypto;
+use crate::mempool::{max_block_size, max_block_txs, mempool_expiry, mempool_max_txs, SharedMempool};
+use crate::merkle;
+use crate::miner::{Miner, SharedMiner};
+use crate::p2p::{InvItem, SharedNetwork};
+use crate::store::{ChainStore, SharedStore};
+use crate::transactions::{accept_transaction, promote_queued, submit_transaction, Transaction};
+
+
+pub const BLOCK_VERSION: u32 = 2; // Version of the block header encoding, 2 since the Merkle root is domain separated
+// Versions only found at the start of chains stored by older nodes, checked with the rules they were mined with
+pub const HEADERLESS_BLOCK_VERSION: u32 = 0; // Hashed the float timestamp and the JSON body the header migration rewrote
+pub const JSON_MERKLE_BLOCK_VERSION: u32 = 1; // Current header, but Merkle leaves hashed the JSON of the transactions
+const INITIAL_DIFFICULTY: i32 = 20; // Number of leading zero bits required in the hash of the first blocks
+const MIN_DIFFICULTY: i32 = 1;
+const MAX_DIFFICULTY_STEP: i32 = 4; // Max number of bits difficulty can move in a single retarget
+const DEFAULT_TARGET_BLOCK_TIME: f64 = 10.0; // seconds
+const DEFAULT_RETARGET_INTERVAL: i64 = 10; // blocks
+const INITIAL_BLOCK_SUBSIDY: i32 = 50; // Coins created by the coinbase of the first blocks
+const HALVING_INTERVAL: i64 = 210; // Number of blocks after which the block subsidy is halved
+const WORK_PAGE_SIZE: i64 = 2000; // Headers read at once when adding up the work of the chain
+const MAX_FUTURE_BLOCK_TIME: i64 = 2 * 60 * 1000; // ms a block timestamp may be ahead of the local clock
+
+// Canonical genesis block every node starts from: empty body, mined once with INITIAL_DIFFICULTY
+const GENESIS_VERSION: u32 = 2;
+const GENESIS_TIMESTAMP: i64 = 1_792_281_600_000; // 2026-10-18T00:00:00Z
+const GENESIS_NONCE: u32 = 985_277;
+pub const GENESIS_HASH: &str = "000008e55459b8e6e283e6ba880e56335d10f282e6dd2b2ecb3a187e71961fed";
 
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_chain_height, get_block_by_hash, get_block_transactions, get_head_block, healthcheck, mine_new_block, verify_chain]
+}
 
-const DIFFICULTY: usize = 5; // Number of leading zeros required in the hash
+/// Blockchain shared between the background miner and the request handlers that produce blocks.
+pub type SharedBlockchain = Arc<Mutex<Blockchain>>;
+
+/// How the node produces blocks, configurable with `MINING_MODE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MiningMode {
+    /// Mine blocks in a loop whether or not there are pending transactions (default)
+    Continuous,
+    /// Only mine blocks in the background when there are pending transactions
+    WhenPending,
+    /// Never mine in the background, blocks are only produced through `POST /mine`
+    OnDemand,
+}
 
-pub fn routes() -> Vec<rocket::Route> {
-    routes![get_chain_height, get_block_by_hash, get_block_transactions, get_head_block, healthcheck]
+impl MiningMode {
+    pub fn from_env() -> Self {
+        Self::from_name(&std::env::var("MINING_MODE").unwrap_or_default())
+    }
+
+    /// Mode called `name`, `Continuous` for an unknown name.
+    pub fn from_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "on_demand" | "on-demand" | "manual" => MiningMode::OnDemand,
+            "when_pending" | "when-pending" | "mempool" => MiningMode::WhenPending,
+            _ => MiningMode::Continuous,
+        }
+    }
+
+    /// Whether the background miner waits instead of mining a block while `pending`
+    /// transactions are in the mempool.
+    pub fn is_idle(self, pending: i64) -> bool {
+        match self {
+            MiningMode::Continuous => false,
+            MiningMode::WhenPending => pending == 0,
+            MiningMode::OnDemand => true,
+        }
+    }
 }
 
-#[derive(Clone, FromRow, Serialize, Deserialize)]
-pub struct Block {
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MinedBlock {
+    pub block: Block,
+    pub transactions: Vec<Transaction>,
+}
+
+/// Fields committed to by the block hash. Hashing always goes through `encode`, a fixed size
+/// big endian layout, so the hash does not depend on the platform or on formatting.
+#[derive(Clone, Debug, FromRow, Serialize, Deserialize)]
+pub struct BlockHeader {
+    // Read as i64, Postgres has no unsigned integers
+    #[sqlx(try_from = "i64")]
+    pub version: u32,
     pub idx: i32,
-    pub timestamp: f64,
-    pub data: String,
     pub previous_hash: String,
+    pub merkle_root: String,
+    pub timestamp: i64, // milliseconds since the unix epoch
+    pub difficulty: i32,
+    #[sqlx(try_from = "i64")]
+    pub nonce: u32,
+    // Proof of authority seal over the hash, so not part of it. None for proof of work blocks
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+impl BlockHeader {
+    /// version (4) | idx (4) | previous hash (32) | merkle root (32) | timestamp (8) | difficulty (4) | nonce (4)
+    pub const ENCODED_LEN: usize = 88;
+    pub const NONCE_OFFSET: usize = Self::ENCODED_LEN - 4;
+
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0u8; Self::ENCODED_LEN];
+        bytes[0..4].copy_from_slice(&self.version.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.idx.to_be_bytes());
+        bytes[8..40].copy_from_slice(&hash_bytes(&self.previous_hash));
+        bytes[40..72].copy_from_slice(&hash_bytes(&self.merkle_root));
+        bytes[72..80].copy_from_slice(&self.timestamp.to_be_bytes());
+        bytes[80..84].copy_from_slice(&self.difficulty.to_be_bytes());
+        bytes[Self::NONCE_OFFSET..].copy_from_slice(&self.nonce.to_be_bytes());
+        bytes
+    }
+
+    pub fn hash(&self) -> String {
+        hex::encode(Sha256::digest(self.encode()))
+    }
+}
+
+/// Milliseconds since the unix epoch.
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as i64
+}
+
+/// Raw bytes of a hex encoded SHA-256 hash, all zeros for the genesis block's empty parent.
+fn hash_bytes(hash: &str) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    if let Ok(decoded) = hex::decode(hash)
+        && decoded.len() == 32
+    {
+        bytes.copy_from_slice(&decoded);
+    }
+    bytes
+}
+
+#[derive(Clone, FromRow, Serialize, Deserialize)]
+pub struct Block {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub header: BlockHeader,
     pub hash: String,
-    pub nonce: i32,
+    // Transactions committed to by the header's Merkle root, the coinbase (if any) first
+    #[sqlx(json)]
+    pub body: Vec<Transaction>,
 }
 
 
 impl Block {
-    pub async fn new(idx: i32, previous_hash: String) -> Self {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_secs_f64();
+    /// Unmined block at `idx` including the `pending` transactions, in that order.
+    pub fn new(idx: i32, previous_hash: String, pending: Vec<Transaction>) -> Self {
         let mut block = Block {
-            idx,
-            timestamp,
-            data: "".to_string(),
-            previous_hash,
+            header: BlockHeader {
+                version: BLOCK_VERSION,
+                idx,
+                previous_hash,
+                merkle_root: String::new(),
+                timestamp: now_millis(),
+                difficulty: INITIAL_DIFFICULTY,
+                nonce: 0,
+                signature: None,
+            },
             hash: String::new(),
-            nonce: 0,
+            body: Vec::new(),
         };
 
-        block.prepare_unmined_block().await;
+        block.prepare_unmined_block(pending);
         block
     }
 
     pub fn calculate_hash(&self) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(self.idx.to_string());
-        hasher.update(self.timestamp.to_string());
-        hasher.update(&self.data);
-        hasher.update(&self.previous_hash);
-        hasher.update(self.nonce.to_string());
-        format!("{:x}", hasher.finalize())
-    }
-
-    pub fn mine(&mut self) {
-        while !self.hash.starts_with(&"0".repeat(DIFFICULTY)) {
-            self.nonce += 1;
-            self.hash = self.calculate_hash();
+        self.header.hash()
+    }
+
+    /// Seal the block following `parent` with `consensus`, returning false if sealing was
+    /// cancelled. `tip` is the miner's tip counter read when `parent` was the head.
+    pub fn seal(&mut self, consensus: &dyn ConsensusEngine, parent: &BlockHeader, miner: &Miner, tip: u64) -> bool {
+        match consensus.seal(&self.header, parent, miner, tip) {
+            Some((header, hash)) => {
+                self.header = header;
+                self.hash = hash;
+                true
+            }
+            None => false,
         }
-        println!("Block mined: {}", self.hash);
     }
 
-    pub async fn prepare_unmined_block(&mut self) {
-        let pool = db_pool().await;
+    pub fn prepare_unmined_block(&mut self, pending: Vec<Transaction>) {
+        // The coinbase pays the subsidy plus the fees of every included transaction to the miner
+        let mut transactions = Vec::with_capacity(pending.len() + 1);
+        if let Some(miner) = miner_address() {
+            let height = self.header.idx - 1;
+            // Capped, the fees of a full block may not fit in an amount
+            let fees: i64 = pending.iter().map(|tx| tx.fee as i64).sum();
+            let reward = (block_subsidy(height as i64) as i64 + fees).min(i32::MAX as i64) as i32;
+            let created_at = self.header.timestamp as f64 / 1000.0;
+            transactions.push(Transaction::coinbase(miner, reward, height, created_at));
+        }
+        transactions.extend(pending);
 
-        // Blocks will be either one or zero. Do not fetch them all as this will may cause out of memory issues
-        let transactions = sqlx::query_as::<_, Transaction>(
-            r#"
-            SELECT *
-            FROM transactions
-            WHERE block_id IS NULL OR block_id = '';
-            "#,
-        )
-        .fetch_all(&pool)
-        .await
-        .unwrap_or_else(|e| {
-            error!("failed to get block: {}", e);
-            panic!("failed to get block");
-        });
+        self.header.merkle_root = Block::merkle_root(&transactions);
+        self.body = transactions;
+    }
 
-        let json_data = serde_json::to_string(&transactions).unwrap();
-        let merkle_root = self.merkle_root(transactions);
-        self.data = format!("{}{}", merkle_root, json_data);
+    pub async fn get_transactions(&self, store: &dyn ChainStore) -> Result<Vec<Transaction>, ApiError> {
+        store.block_transactions(self.header.idx).await
     }
 
-    pub async fn get_transactions(&mut self) -> Vec<Transaction> {
-        let pool = db_pool().await;
+    pub fn merkle_root(transactions: &[Transaction]) -> String {
+        let leaves: Vec<String> = transactions.iter().map(merkle::leaf_hash).collect();
+        merkle::merkle_root(&leaves)
+    }
+}
 
-        let transactions = sqlx::query_as::<_, Transaction>(
-            r#"
-            SELECT *
-            FROM transactions
-            WHERE block_id = ?;
-            "#,
-        )
-        .bind(self.idx)
-        .fetch_all(&pool)
-        .await
-        .unwrap_or_else(|e| {
-            error!("failed to get block: {}", e);
-            panic!("failed to get block");
-        });
 
-        transactions
-    }
+/// Target number of seconds between blocks, configurable with `TARGET_BLOCK_TIME`.
+pub fn target_block_time() -> f64 {
+    std::env::var("TARGET_BLOCK_TIME")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|v| *v > 0.0)
+        .unwrap_or(DEFAULT_TARGET_BLOCK_TIME)
+}
+
+/// Number of blocks between difficulty adjustments, configurable with `DIFFICULTY_RETARGET_INTERVAL`.
+pub fn retarget_interval() -> i64 {
+    std::env::var("DIFFICULTY_RETARGET_INTERVAL")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_RETARGET_INTERVAL)
+}
 
-    pub fn merkle_root(&mut self, transactions: Vec<Transaction>) -> String {
-        if transactions.is_empty() {
-            let mut hasher = Sha256::new();
-            hasher.update("");
-            return format!("{:x}", hasher.finalize());
+/// Address credited with the coinbase of mined blocks, configurable with `MINER_ADDRESS` or
+/// derived from `MINER_PUB_KEY`. Blocks mined without one carry no coinbase.
+pub fn miner_address() -> Option<String> {
+    std::env::var("MINER_ADDRESS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .or_else(|| miner_pub_key().and_then(|key| crypto::address_from_public_key(&key).ok()))
+}
+
+/// Public key (hex) of the miner, configurable with `MINER_PUB_KEY`. Used when the first reward
+/// creates the miner's wallet so that the miner can later sign transactions spending it.
+pub fn miner_pub_key() -> Option<String> {
+    std::env::var("MINER_PUB_KEY").ok().filter(|v| !v.is_empty())
+}
+
+/// Check that the configured miner address is well formed and matches the miner's public key.
+pub fn check_miner_config() -> Result<(), String> {
+    if let Some(pub_key) = miner_pub_key() {
+        let derived = crypto::address_from_public_key(&pub_key)
+            .map_err(|e| format!("invalid MINER_PUB_KEY: {}", e))?;
+        if miner_address().as_ref() != Some(&derived) {
+            return Err(format!("MINER_ADDRESS is not derived from MINER_PUB_KEY, expected '{}'", derived));
         }
+    }
+    if let Some(address) = miner_address() {
+        crypto::validate_address(&address).map_err(|e| format!("invalid MINER_ADDRESS: {}", e))?;
+    }
+    Ok(())
+}
 
-        let mut hashes: Vec<String> = transactions
-            .into_iter()
-            .map(|tx| {
-                let mut hasher = Sha256::new();
-                let json = tx.to_json();
-                hasher.update(json);
-                format!("{:x}", hasher.finalize())
-            })
-            .collect();
+/// The canonical genesis block, the same on every node.
+pub fn genesis_block() -> Block {
+    Block {
+        header: BlockHeader {
+            version: GENESIS_VERSION,
+            idx: 1,
+            previous_hash: String::new(),
+            merkle_root: Block::merkle_root(&[]),
+            timestamp: GENESIS_TIMESTAMP,
+            difficulty: INITIAL_DIFFICULTY,
+            nonce: GENESIS_NONCE,
+            signature: None,
+        },
+        hash: GENESIS_HASH.to_string(),
+        body: Vec::new(),
+    }
+}
 
-        while hashes.len() > 1 {
-            if hashes.len() % 2 == 1 {
-                let last = hashes.last().cloned().unwrap();
-                hashes.push(last);
-            }
+/// Coins created by the coinbase of the block at `height` (0 for the genesis block).
+pub fn block_subsidy(height: i64) -> i32 {
+    let halvings = height.max(0) / HALVING_INTERVAL;
+    if halvings >= 31 {
+        return 0;
+    }
+    INITIAL_BLOCK_SUBSIDY >> halvings
+}
 
-            let mut next = Vec::with_capacity(hashes.len() / 2);
-            for pair in hashes.chunks(2) {
-                let mut hasher = Sha256::new();
-                hasher.update(&pair[0]);
-                hasher.update(&pair[1]);
-                next.push(format!("{:x}", hasher.finalize()));
-            }
-            hashes = next;
+/// Reward rule of the block stored at `idx`: at most one coinbase, paying neither less than
+/// nothing nor more than the subsidy plus the `fees` of the block's other transactions.
+pub fn check_coinbase(idx: i64, coinbase_count: i64, coinbase_amount: i64, fees: i64) -> Result<(), String> {
+    if coinbase_count > 1 {
+        return Err(format!(
+            "block at idx {} has {} coinbase transactions, expected at most 1",
+            idx, coinbase_count
+        ));
+    }
+
+    if coinbase_amount < 0 {
+        return Err(format!("coinbase at idx {} pays a negative amount of {}", idx, coinbase_amount));
+    }
+    let max_reward = block_subsidy(idx - 1) as i64 + fees;
+    if coinbase_amount > max_reward {
+        return Err(format!(
+            "coinbase at idx {} pays {}, more than the allowed reward of {}",
+            idx, coinbase_amount, max_reward
+        ));
+    }
+
+    Ok(())
+}
+
+/// Time rule of the block stored at `idx`: after its parent's timestamp, so that a retarget
+/// window cannot be stretched by back-dating its first block, and not further than
+/// MAX_FUTURE_BLOCK_TIME ahead of `now`, so that its last block cannot be post-dated either.
+pub fn check_timestamp(idx: i64, timestamp: i64, parent_timestamp: Option<i64>, now: i64) -> Result<(), String> {
+    if let Some(parent_timestamp) = parent_timestamp.filter(|parent_timestamp| timestamp <= *parent_timestamp) {
+        return Err(format!(
+            "block at idx {} has timestamp {}, not after its parent's {}",
+            idx, timestamp, parent_timestamp
+        ));
+    }
+    if timestamp > now + MAX_FUTURE_BLOCK_TIME {
+        return Err(format!(
+            "block at idx {} has timestamp {}, more than {} ms ahead of {}",
+            idx, timestamp, MAX_FUTURE_BLOCK_TIME, now
+        ));
+    }
+    Ok(())
+}
+
+/// Version rule of the block stored at `idx`: a known version, never lower than its parent's, so
+/// that the legacy versions can only make up the start of the chain.
+pub fn check_version(idx: i64, version: u32, parent_version: Option<u32>) -> Result<(), String> {
+    if version > BLOCK_VERSION {
+        return Err(format!("unknown block version {} at idx {}", version, idx));
+    }
+    if let Some(parent_version) = parent_version.filter(|parent_version| version < *parent_version) {
+        return Err(format!("block version at idx {} goes back from {} to {}", idx, parent_version, version));
+    }
+    Ok(())
+}
+
+/// Whether the block stored at `idx` is the first one of a new difficulty period.
+pub fn is_retarget_height(idx: i64) -> bool {
+    idx > 1 && (idx - 1) % retarget_interval() == 0
+}
+
+/// Index of the oldest block whose timestamp is used when retargeting at `idx`.
+pub fn retarget_window_start(idx: i64) -> i64 {
+    (idx - 1 - retarget_interval()).max(1)
+}
+
+/// Adjust `difficulty` by one bit for every doubling between the observed and the expected
+/// time span of the last retarget window.
+pub fn retarget(difficulty: i32, actual_span: f64, expected_span: f64) -> i32 {
+    let mut next = difficulty;
+    let mut span = actual_span.max(0.0);
+
+    while span * 2.0 <= expected_span && next < difficulty + MAX_DIFFICULTY_STEP {
+        next += 1;
+        span *= 2.0;
+    }
+    while span >= expected_span * 2.0 && next > difficulty - MAX_DIFFICULTY_STEP {
+        next -= 1;
+        span /= 2.0;
+    }
+
+    next.max(MIN_DIFFICULTY)
+}
+
+pub fn leading_zero_bits(hash: &str) -> u32 {
+    let mut bits = 0;
+    for c in hash.chars() {
+        match c.to_digit(16) {
+            Some(0) => bits += 4,
+            Some(d) => return bits + (d as u8).leading_zeros() - 4,
+            None => return bits,
         }
+    }
+    bits
+}
 
-        hashes.pop().unwrap()
+/// Same as `leading_zero_bits` but on a raw digest, used in the mining loop.
+pub fn leading_zero_bits_of(digest: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in digest {
+        if *byte != 0 {
+            return bits + byte.leading_zeros();
+        }
+        bits += 8;
     }
+    bits
+}
+
+pub fn meets_difficulty(hash: &str, difficulty: i32) -> bool {
+    !hash.is_empty() && leading_zero_bits(hash) as i64 >= difficulty as i64
 }
 
 
 pub struct Blockchain {
     // pub blocks: Vec<Block>, // those are instead stored in the database
     // Keep only the blockchain head, becasu all other blocks (which are many and can cause memory issues)
-    // are stored in the sqlite database
-    pub blockchain_head: Block
+    // are stored in the chain store
+    pub blockchain_head: Block,
+    // Cumulative weight of the main chain, a side branch with more becomes the main chain
+    pub head_work: u128,
+    pub store: SharedStore,
+    pub consensus: SharedConsensus,
+    pub miner: SharedMiner,
+    pub mempool: SharedMempool,
+    pub network: SharedNetwork,
 }
 
 impl Blockchain {
-    pub async fn new() -> Self {
-        let pool = db_pool().await;
-
-        // Blocks will be either one or zero. Do not fetch them all as this will may cause out of memory issues
-        let blocks = sqlx::query_as::<_, Block>(
-            r#"
-            SELECT idx, timestamp, data, previous_hash, hash, nonce
-            FROM blocks
-            ORDER BY idx DESC
-            LIMIT 1;
-            "#,
-        )
-        .fetch_all(&pool)
-        .await
-        .unwrap_or_else(|e| {
-            error!("failed to get block: {}", e);
-            panic!("failed to get block");
-        });
-
-        // initiliaze blockchain but undo if there are records in the datbase
-        let genesis_block = Block::new(0, "".to_string()).await;
-        let mut blockchain = Blockchain { blockchain_head: genesis_block.clone() };
-        if blocks.is_empty() {
-            blockchain.add_block(genesis_block).await;
-        } else if let Some(last_block) = blocks.last() {
-            blockchain.blockchain_head = last_block.clone();
+    pub async fn new(
+        store: SharedStore,
+        consensus: SharedConsensus,
+        miner: SharedMiner,
+        mempool: SharedMempool,
+        network: SharedNetwork,
+    ) -> Result<Self, ApiError> {
+        let head = store.head().await?;
+
+        let mut blockchain =
+            Blockchain { blockchain_head: genesis_block(), head_work: 0, store, consensus, miner, mempool, network };
+        match head {
+            // An empty chain starts from the canonical genesis, so that it can sync with peers
+            None => {
+                blockchain.add_block(genesis_block()).await?;
+            }
+            Some(head) => {
+                let genesis = block_by_idx(blockchain.store.as_ref(), 1).await?;
+                if genesis.hash != GENESIS_HASH {
+                    warn!("stored genesis block {} is not the canonical one, peers will refuse this chain", genesis.hash);
+                }
+                blockchain.blockchain_head = head;
+                blockchain.head_work = work_above(blockchain.store.as_ref(), blockchain.consensus.as_ref(), 0).await?;
+            }
         }
-        blockchain
+        Ok(blockchain)
     }
 
-    pub async fn add_block(&mut self, mut block: Block) {
-        block.mine();
-        block.previous_hash = self.blockchain_head.clone().hash;
+    /// Block on top of the current head from the mempool's best paying transactions. Every
+    /// header field but the seal is final, so the seal commits to the parent and the index.
+    pub async fn block_template(&self) -> Result<Block, ApiError> {
+        let height = self.get_height().await?;
+        let previous_hash = if height == 0 { String::new() } else { self.blockchain_head.hash.clone() };
 
-        let pool = db_pool().await;
+        self.mempool.evict(self.store.as_ref(), mempool_max_txs(), mempool_expiry()).await?;
+        let pending = self
+            .mempool
+            .select(max_block_size(), max_block_txs())
+            .into_iter()
+            .map(|entry| entry.transaction)
+            .collect();
 
-        let _result = sqlx::query(
-            r#"
-            INSERT INTO blocks (data, previous_hash, hash, nonce)
-            VALUES (?, ?, ?, ?)
-            "#,
-        )
-        .bind(&block.data)
-        .bind(&block.previous_hash)
-        .bind(&block.hash)
-        .bind(block.nonce)
-        .execute(&pool)
-        .await
-        .unwrap_or_else(|e| {
-            error!("failed to insert block: {}", e);
-            panic!("failed to insert block");
-        });
-
-        self.blockchain_head = block.clone();
-
-        let _transactions = sqlx::query_as::<_, Transaction>(
-            r#"
-            UPDATE transactions
-            SET block_id = ?
-            WHERE block_id IS NULL OR block_id = '';
-            "#,
-        )
-        .bind(block.idx)
-        .fetch_all(&pool)
-        .await
-        .unwrap_or_else(|e| {
-            error!("failed to get block: {}", e);
-            panic!("failed to get block");
-        });
+        let mut block = Block::new(height + 1, previous_hash, pending);
+        if height > 0 {
+            block.header.timestamp = block.header.timestamp.max(self.blockchain_head.header.timestamp + 1);
+        }
+        block.header.difficulty = self.next_difficulty().await?;
+        Ok(block)
     }
 
-    pub async fn get_height(&mut self) -> i32 {
-        let pool = db_pool().await;
+    /// Check that `block` extends the current head with a valid seal, commits to its body, pays
+    /// no more than its reward and stays within the block limits.
+    pub async fn check_block(&self, block: &Block) -> Result<(), ApiError> {
+        let height = self.get_height().await?;
+        let parent_hash = if height == 0 { "" } else { self.blockchain_head.hash.as_str() };
+        if block.header.idx != height + 1 || block.header.previous_hash != parent_hash {
+            return Err(ApiError::Conflict(format!(
+                "block {} does not extend the chain head at idx {}",
+                block.header.idx, height
+            )));
+        }
+
+        let difficulty = self.next_difficulty().await?;
+        if block.header.difficulty != difficulty {
+            return Err(ApiError::InvalidInput(format!(
+                "block difficulty is {}, expected {}",
+                block.header.difficulty, difficulty
+            )));
+        }
+        let parent = (height > 0).then_some(&self.blockchain_head.header);
+        check_block_contents(self.consensus.as_ref(), block, parent)
+    }
+
+    /// Add a block received from a peer. A block extending the head is appended, any other one
+    /// is stored on its side branch, which becomes the main chain if it has more cumulative work.
+    pub async fn import_block(&mut self, block: Block) -> Result<Block, ApiError> {
+        if block.header.previous_hash != self.blockchain_head.hash {
+            return self.import_side_block(block).await;
+        }
+        self.connect_block(block).await
+    }
+
+    /// Append a block extending the head. Its transactions are validated and accepted first, in
+    /// block order and against the confirmed state, as if they had been submitted to POST /tx.
+    /// The pending transactions are submitted again on top of it, the ones it conflicts with dropped.
+    async fn connect_block(&mut self, block: Block) -> Result<Block, ApiError> {
+        // Cheap checks first, a block that cannot be appended must not move any funds
+        self.check_block(&block).await?;
+
+        // Pending transactions moved funds and used nonces the block's transactions may need
+        let pending = self.mempool.revert_all(self.store.as_ref()).await?;
+        let result = self.confirm_block(block).await;
+        let confirmed: HashSet<String> = match &result {
+            Ok(stored_block) => stored_block.body.iter().map(|tx| tx.txid()).collect(),
+            Err(_) => {
+                // The block transactions accepted before the failure
+                if let Err(e) = self.mempool.revert_all(self.store.as_ref()).await {
+                    error!("could not revert the transactions of the invalid block: {}", e.message());
+                }
+                HashSet::new()
+            }
+        };
+        for transaction in pending.into_iter().filter(|tx| !confirmed.contains(&tx.txid())) {
+            let txid = transaction.txid();
+            if let Err(e) = submit_transaction(self.store.as_ref(), &self.mempool, &self.network, transaction).await {
+                debug!("transaction {} left out by the new block: {}", txid, e.message());
+            }
+        }
+        let stored_block = result?;
+
+        // Transactions queued behind the ones the block confirmed may be accepted now
+        let mut senders: Vec<&str> = stored_block.body.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.from_address.as_str()).collect();
+        senders.sort_unstable();
+        senders.dedup();
+        for sender in senders {
+            for promoted in promote_queued(self.store.as_ref(), &self.mempool, sender).await? {
+                self.network.announce(InvItem::tx(&promoted.txid()));
+            }
+        }
+
+        Ok(stored_block)
+    }
+
+    /// Accept the transactions of `block` with nothing else pending, then store it.
+    async fn confirm_block(&mut self, block: Block) -> Result<Block, ApiError> {
+        for transaction in block.body.iter().filter(|tx| !tx.is_coinbase()) {
+            if !transaction.is_valid(self.store.as_ref()).await? {
+                return Err(ApiError::InvalidInput(format!("block transaction {} is not valid", transaction.txid())));
+            }
+            accept_transaction(self.store.as_ref(), &self.mempool, transaction.clone()).await?;
+        }
+        self.add_block(block).await
+    }
+
+    /// Store a block that does not extend the head. Its transactions depend on the state of the
+    /// branch and are only checked if the branch is connected.
+    async fn import_side_block(&mut self, block: Block) -> Result<Block, ApiError> {
+        if find_block(self.store.as_ref(), &block.hash).await?.is_some() {
+            return Err(ApiError::Conflict(format!("block {} is already stored", block.hash)));
+        }
+        let parent = find_block(self.store.as_ref(), &block.header.previous_hash).await?.ok_or_else(|| {
+            ApiError::Conflict(format!("parent {} of block {} is unknown", block.header.previous_hash, block.hash))
+        })?;
+        if block.header.idx != parent.header.idx + 1 {
+            return Err(ApiError::InvalidInput(format!(
+                "block {} has idx {}, its parent idx {}",
+                block.hash, block.header.idx, parent.header.idx
+            )));
+        }
+        // Retargeted along the block's own branch, so cheap blocks cannot fill the side branches
+        let difficulty = difficulty_after(self.store.as_ref(), self.consensus.as_ref(), &parent).await?;
+        if block.header.difficulty != difficulty {
+            return Err(ApiError::InvalidInput(format!(
+                "block difficulty is {}, expected {}",
+                block.header.difficulty, difficulty
+            )));
+        }
+        check_block_contents(self.consensus.as_ref(), &block, Some(&parent.header))?;
+        self.store.put_side_block(&block).await?;
+
+        // Walk the side branch back to the main chain
+        let mut branch = vec![block.clone()];
+        let fork = loop {
+            let previous_hash = &branch.last().expect("branch is not empty").header.previous_hash;
+            if let Some(fork) = self.store.block_by_hash(previous_hash).await? {
+                break fork;
+            }
+            let side_block = self
+                .store
+                .side_block(previous_hash)
+                .await?
+                .ok_or_else(|| ApiError::Internal(format!("side block {} is not stored", previous_hash)))?;
+            branch.push(side_block);
+        };
+        branch.reverse();
+
+        // On a tie the branch seen first stays the main chain
+        let branch_work = self.head_work - work_above(self.store.as_ref(), self.consensus.as_ref(), fork.header.idx).await?
+            + branch.iter().map(|block| self.consensus.block_weight(&block.header)).sum::<u128>();
+        if branch_work <= self.head_work {
+            info!("stored side block {} at idx {}, its branch weighs less than the main chain", block.hash, block.header.idx);
+            return Ok(block);
+        }
+        self.reorganize(fork, branch).await
+    }
+
+    /// Switch the main chain to `branch`, the side blocks following `fork` in order. The main
+    /// blocks after the fork are disconnected newest first, then the branch blocks are connected
+    /// as if they had just been received. If one of them is invalid the previous chain is
+    /// connected back. Either way, the transactions left out of the chain are submitted again.
+    async fn reorganize(&mut self, fork: Block, branch: Vec<Block>) -> Result<Block, ApiError> {
+        let tip = branch.last().expect("branch is not empty");
+        info!(
+            "reorganizing from {} at idx {} to {} at idx {}, forking at idx {}",
+            self.blockchain_head.hash, self.blockchain_head.header.idx, tip.hash, tip.header.idx, fork.header.idx
+        );
+
+        // Pending transactions moved funds on top of the current head, take them out first
+        let mut pending = self.mempool.revert_all(self.store.as_ref()).await?;
+        let mut disconnected = Vec::new();
+        while self.blockchain_head.header.idx > fork.header.idx {
+            disconnected.push(self.disconnect_head().await?);
+        }
+        disconnected.reverse();
+
+        let mut connected = 0;
+        let mut result = Ok(());
+        for block in &branch {
+            if let Err(e) = self.connect_block(block.clone()).await {
+                result = Err(e);
+                break;
+            }
+            connected += 1;
+        }
+
+        if let Err(e) = &result {
+            warn!("block {} of the new branch is invalid, restoring the previous chain: {}", branch[connected].hash, e.message());
+            // Neither the invalid block nor the ones built on it can ever be connected
+            for block in &branch[connected..] {
+                if let Err(e) = self.store.delete_side_block(&block.hash).await {
+                    error!("could not delete invalid block {}: {}", block.hash, e.message());
+                }
+            }
+            // The invalid block may have left some of its transactions pending
+            match self.mempool.revert_all(self.store.as_ref()).await {
+                Ok(reverted) => pending.extend(reverted),
+                Err(e) => error!("could not revert the transactions of the invalid branch: {}", e.message()),
+            }
+            // A failure leaves the head short of the fork, or of the previous head, but the
+            // returned transactions are still resubmitted on top of whatever it is
+            while self.blockchain_head.header.idx > fork.header.idx {
+                if let Err(e) = self.disconnect_head().await {
+                    error!("could not disconnect block {} of the new branch: {}", self.blockchain_head.hash, e.message());
+                    break;
+                }
+            }
+            for block in &disconnected {
+                if let Err(e) = self.connect_block(block.clone()).await {
+                    error!("could not reconnect block {} at idx {}: {}", block.hash, block.header.idx, e.message());
+                    break;
+                }
+            }
+        }
+
+        // Transactions of the disconnected blocks not confirmed again, then the pending ones
+        let returned = disconnected
+            .iter()
+            .flat_map(|block| block.body.iter().filter(|tx| !tx.is_coinbase()).cloned())
+            .chain(pending);
+        for transaction in returned {
+            let txid = transaction.txid();
+            if let Err(e) = submit_transaction(self.store.as_ref(), &self.mempool, &self.network, transaction).await {
+                debug!("transaction {} left out by the reorg: {}", txid, e.message());
+            }
+        }
+
+        result.map(|()| self.blockchain_head.clone())
+    }
+
+    /// Move the head block off the main chain into the side blocks, reverting its transactions
+    /// and coinbase, latest first. The pending transactions must have been reverted already.
+    async fn disconnect_head(&mut self) -> Result<Block, ApiError> {
+        let block = self.blockchain_head.clone();
+        if block.header.idx <= 1 {
+            return Err(ApiError::Internal("the genesis block cannot be disconnected".to_string()));
+        }
+
+        self.store.disconnect_block(&block).await?;
+
+        self.blockchain_head = block_by_idx(self.store.as_ref(), block.header.idx - 1).await?;
+        self.head_work -= self.consensus.block_weight(&block.header);
+        self.miner.tip_changed();
+        Ok(block)
+    }
+
+    /// Append a mined `block` to the chain and persist it, returning the block as stored, and
+    /// announce it to the connected peers. The block must pass `check_block` and its
+    /// transactions must all be pending.
+    pub async fn add_block(&mut self, block: Block) -> Result<Block, ApiError> {
+        self.check_block(&block).await?;
+        let txids: Vec<String> = block.body.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.txid()).collect();
+
+        // The block, its coinbase and the links to its transactions are stored together: a
+        // transaction evicted or linked elsewhere in the meantime rolls the whole block back.
+        // Only this node's own miner key is known, blocks from peers can pay any address.
+        let coinbase_pub_key = block
+            .body
+            .iter()
+            .find(|tx| tx.is_coinbase())
+            .and_then(|coinbase| miner_address().filter(|address| *address == coinbase.to_address))
+            .and(miner_pub_key())
+            .unwrap_or_default();
+        let stored_block = self.store.connect_block(&block, &coinbase_pub_key).await?;
+
+        self.blockchain_head = stored_block.clone();
+        self.head_work += self.consensus.block_weight(&stored_block.header);
+        self.miner.tip_changed();
+        self.mempool.remove(&txids);
+        self.network.announce(InvItem::block(&stored_block.hash));
+
+        Ok(stored_block)
+    }
 
-        sqlx::query_scalar::<_, i32>(
-        r#"
-            SELECT COUNT(*) FROM blocks;        
-            "#,
+    /// Difficulty the next block on top of the stored chain must be mined with.
+    pub async fn next_difficulty(&self) -> Result<i32, ApiError> {
+        match self.store.head().await? {
+            Some(parent) => difficulty_after(self.store.as_ref(), self.consensus.as_ref(), &parent).await,
+            None => Ok(INITIAL_DIFFICULTY),
+        }
+    }
+
+    pub async fn get_height(&self) -> Result<i32, ApiError> {
+        self.store.height().await
+    }
+}
+
+/// Proof of work of a block: the expected number of hashes to find it, 2^difficulty.
+pub fn block_work(difficulty: i32) -> u128 {
+    1u128 << difficulty.clamp(0, 127)
+}
+
+/// Check the parts of a block that only depend on its `parent`: it is sealed as `consensus`
+/// requires, it commits to its body, its coinbase follows the reward rule and it stays within
+/// the block limits. The genesis block has no parent and keeps the proof of work it was created
+/// with, whatever the engine.
+fn check_block_contents(consensus: &dyn ConsensusEngine, block: &Block, parent: Option<&BlockHeader>) -> Result<(), ApiError> {
+    // Legacy versions are only verified in the store they were mined into, never accepted anew
+    if block.header.version != BLOCK_VERSION {
+        return Err(ApiError::InvalidInput(format!(
+            "block version {} is not supported, expected {}",
+            block.header.version, BLOCK_VERSION
+        )));
+    }
+    if block.calculate_hash() != block.hash {
+        return Err(ApiError::InvalidInput("block hash does not match its header".to_string()));
+    }
+    match parent {
+        Some(parent) => consensus
+            .verify_seal(&block.header, &block.hash, parent)
+            .map_err(|e| ApiError::InvalidInput(format!("block seal is invalid: {}", e)))?,
+        None if !meets_difficulty(&block.hash, block.header.difficulty) => {
+            return Err(ApiError::InvalidInput("block hash is not a valid proof of work".to_string()));
+        }
+        None => {}
+    }
+    check_timestamp(block.header.idx as i64, block.header.timestamp, parent.map(|parent| parent.timestamp), now_millis())
+        .map_err(ApiError::InvalidInput)?;
+
+    if Block::merkle_root(&block.body) != block.header.merkle_root {
+        return Err(ApiError::InvalidInput("block body does not match its merkle root".to_string()));
+    }
+
+    // The store credits the coinbase as it is, it may only pay the subsidy and the block's fees
+    let included: Vec<&Transaction> = block.body.iter().filter(|tx| !tx.is_coinbase()).collect();
+    if block.body.iter().skip(1).any(|tx| tx.is_coinbase()) {
+        return Err(ApiError::InvalidInput("block coinbase is not its first transaction".to_string()));
+    }
+    let fees: i64 = included.iter().map(|tx| tx.fee as i64).sum();
+    let coinbase_amount: i64 = block.body.iter().filter(|tx| tx.is_coinbase()).map(|tx| tx.amount as i64).sum();
+    let coinbase_count = block.body.len() - included.len();
+    check_coinbase(block.header.idx as i64, coinbase_count as i64, coinbase_amount, fees).map_err(ApiError::InvalidInput)?;
+    // Connecting the block creates the recipient's wallet, it must be an address one can spend from
+    if let Some(coinbase) = block.body.first().filter(|tx| tx.is_coinbase()) {
+        crypto::validate_address(&coinbase.to_address)
+            .map_err(|e| ApiError::InvalidInput(format!("block coinbase pays an invalid address: {}", e)))?;
+    }
+
+    let size: usize = included.iter().map(|tx| tx.size()).sum();
+    if included.len() > max_block_txs() || size > max_block_size() {
+        return Err(ApiError::InvalidInput(format!(
+            "block has {} transactions of {} bytes, more than the limits of {} and {} bytes",
+            included.len(), size, max_block_txs(), max_block_size()
+        )));
+    }
+    Ok(())
+}
+
+/// Difficulty the block following `parent` must be mined with, `parent` being on the main chain
+/// or on a side branch. At retarget heights the window is read back along the parent's branch.
+async fn difficulty_after(store: &dyn ChainStore, consensus: &dyn ConsensusEngine, parent: &Block) -> Result<i32, ApiError> {
+    let (parent_idx, parent_timestamp, parent_difficulty) =
+        (parent.header.idx as i64, parent.header.timestamp, parent.header.difficulty);
+
+    let idx = parent_idx + 1;
+    if !consensus.retargets() || !is_retarget_height(idx) {
+        return Ok(parent_difficulty);
+    }
+
+    // Side blocks up to the fork, then the rest of the window is on the main chain
+    let window_start = retarget_window_start(idx);
+    let mut hash = parent.hash.clone();
+    let window_start_timestamp = loop {
+        if store.block_by_hash(&hash).await?.is_some() {
+            break block_by_idx(store, window_start as i32).await?.header.timestamp;
+        }
+        let side_block = store
+            .side_block(&hash)
+            .await?
+            .ok_or_else(|| ApiError::Internal(format!("side block {} is not stored", hash)))?;
+        if side_block.header.idx as i64 == window_start {
+            break side_block.header.timestamp;
+        }
+        hash = side_block.header.previous_hash;
+    };
+
+    Ok(retarget(
+        parent_difficulty,
+        (parent_timestamp - window_start_timestamp) as f64 / 1000.0,
+        (parent_idx - window_start) as f64 * target_block_time(),
+    ))
+}
+
+/// Cumulative weight of the main chain blocks after `idx`. The headers are read a page at a
+/// time, so a fork close to the head only reads the few blocks above it.
+async fn work_above(store: &dyn ChainStore, consensus: &dyn ConsensusEngine, idx: i32) -> Result<u128, ApiError> {
+    let mut work = 0;
+    let mut after = idx;
+    loop {
+        let headers = store.headers_after(after, WORK_PAGE_SIZE).await?;
+        work += headers.iter().map(|entry| consensus.block_weight(&entry.header)).sum::<u128>();
+        match headers.last() {
+            Some(entry) if headers.len() as i64 == WORK_PAGE_SIZE => after = entry.header.idx,
+            _ => return Ok(work),
+        }
+    }
+}
+
+/// Assemble a block from the pending transactions, seal it and append it to the chain.
+///
+/// The lock is only held to build the block template and to store the mined block, so a block
+/// appended in the meantime cancels this one instead of waiting for it: the miner's tip counter
+/// is read with the template, before the lock is released. Blocks the calling thread, run it
+/// with `spawn_blocking`.
+pub fn mine_block(blockchain: &SharedBlockchain) -> Result<MinedBlock, ApiError> {
+    let handle = Handle::current();
+    let lock = || blockchain.lock().map_err(|e| ApiError::internal("failed to lock blockchain", e));
+
+    let (mut block, parent, consensus, miner, tip) = {
+        let blockchain = lock()?;
+        if !blockchain.consensus.can_seal() {
+            return Err(ApiError::Conflict(format!("this node cannot seal {} blocks", blockchain.consensus.name())));
+        }
+        (
+            handle.block_on(blockchain.block_template())?,
+            blockchain.blockchain_head.header.clone(),
+            Arc::clone(&blockchain.consensus),
+            Arc::clone(&blockchain.miner),
+            blockchain.miner.tip(),
         )
-        .fetch_one(&pool)
-        .await
-        .unwrap_or_else(|e| {
-            error!("failed to get chain size: {}", e);
-            panic!("failed to get chain size");
-        })
+    };
+    if !block.seal(consensus.as_ref(), &parent, &miner, tip) {
+        return Err(ApiError::Conflict("mining was cancelled".to_string()));
+    }
 
+    let mut blockchain = lock()?;
+    let block = handle.block_on(blockchain.add_block(block))?;
+    let transactions = handle.block_on(block.get_transactions(blockchain.store.as_ref()))?;
+    Ok(MinedBlock { block, transactions })
+}
+
+/// Append a block received from a peer, see `Blockchain::import_block`. Blocks the calling
+/// thread, run it with `spawn_blocking`.
+pub fn import_block(blockchain: &SharedBlockchain, block: Block) -> Result<Block, ApiError> {
+    let mut blockchain = blockchain.lock().map_err(|e| ApiError::internal("failed to lock blockchain", e))?;
+    Handle::current().block_on(blockchain.import_block(block))
+}
+
+/// Block stored at `idx`, or `NotFound`.
+pub async fn block_by_idx(store: &dyn ChainStore, idx: i32) -> Result<Block, ApiError> {
+    store
+        .block_by_idx(idx)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("block {} not found", idx)))
+}
+
+/// Block with `hash`, on the main chain or a side branch.
+pub async fn find_block(store: &dyn ChainStore, hash: &str) -> Result<Option<Block>, ApiError> {
+    match store.block_by_hash(hash).await? {
+        Some(block) => Ok(Some(block)),
+        None => store.side_block(hash).await,
     }
 }
 
 
 #[get("/chain/height")]
-async fn get_chain_height() -> ApiResult<DataBody<i32>> {
-    let blockchain = Blockchain::new();
-    let height = DataBody { data: blockchain.await.get_height().await };
+async fn get_chain_height(store: &State<SharedStore>) -> ApiResult<DataBody<i32>> {
+    let height = DataBody { data: store.height().await? };
 
     Ok(Json(height))
 }
 
 
 #[get("/chain/<id>")]
-async fn get_block_by_hash(id: i32) -> ApiResult<Block> {
-    let pool = db_pool().await;
-
-    let block: Block = sqlx::query_as::<_, Block>(
-        r#"
-        SELECT * FROM blocks
-        WHERE idx = ?;
-        "#
-    )
-    .bind(id)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or_else(|e| {
-        error!("failed to get block: {}", e);
-        panic!("failed to get block");
-    });
+async fn get_block_by_hash(store: &State<SharedStore>, id: i32) -> ApiResult<Block> {
+    let block = block_by_idx(store.as_ref(), id).await?;
 
     Ok(Json(block))
 }
 
 #[get("/chain/head")]
-async fn get_head_block() -> ApiResult<Block> {
-    let pool = db_pool().await;
-
-    let block: Block = sqlx::query_as::<_, Block>(
-        r#"
-        SELECT * FROM blocks
-        ORDER BY idx DESC
-        LIMIT 1;
-        "#
-    )
-    .fetch_one(&pool)
-    .await
-    .unwrap_or_else(|e| {
-        error!("failed to get block: {}", e);
-        panic!("failed to get block");
-    });
+async fn get_head_block(store: &State<SharedStore>) -> ApiResult<Block> {
+    let block = store.head().await?.ok_or_else(|| ApiError::NotFound("chain is empty".to_string()))?;
 
     Ok(Json(block))
 }
 
 
 #[get("/chain/<id>/txs")]
-async fn get_block_transactions(id: i32) -> ApiResult<Vec<Transaction>> {
-    let pool = db_pool().await;
-
-    let mut block: Block = sqlx::query_as::<_, Block>(
-        r#"
-        SELECT * FROM blocks
-        WHERE idx = ?;
-        "#
-    )
-    .bind(id)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or_else(|e| {
-        error!("failed to get block: {}", e);
-        panic!("failed to get block");
-    });
-
-    let transactions = block.get_transactions().await;
+async fn get_block_transactions(store: &State<SharedStore>, id: i32) -> ApiResult<Vec<Transaction>> {
+    let block = block_by_idx(store.as_ref(), id).await?;
+    let transactions = block.get_transactions(store.as_ref()).await?;
 
     Ok(Json(transactions))
 }
 
+#[post("/mine")]
+async fn mine_new_block(blockchain: &State<SharedBlockchain>) -> ApiResult<MinedBlock> {
+    let blockchain = Arc::clone(blockchain.inner());
+
+    // Mining is CPU bound, run it on the blocking thread pool like the background miner does
+    let mined = task::spawn_blocking(move || mine_block(&blockchain))
+        .await
+        .map_err(|e| ApiError::internal("failed to mine block", e))??;
+
+    Ok(Json(mined))
+}
+
+#[get("/chain/verify")]
+async fn verify_chain(store: &State<SharedStore>, consensus: &State<SharedConsensus>) -> ApiResult<VerificationReport> {
+    let report = verify_chain_deep(store.as_ref(), consensus.as_ref())
+        .await
+        .map_err(|e| ApiError::internal("failed to verify chain", e))?;
+
+    Ok(Json(report))
+}
+
 #[get("/health")]
-async fn healthcheck() -> ApiResult<DataBody<bool>> {
-    if let Err(_e) = verify_db_state_streaming().await {
+async fn healthcheck(store: &State<SharedStore>, consensus: &State<SharedConsensus>) -> ApiResult<DataBody<bool>> {
+    if let Err(_e) = verify_db_state_streaming(store.as_ref(), consensus.as_ref()).await {
         let health = DataBody { data: false};
         Ok(Json(health))
     } else {
@@ -0,0 +1,207 @@
+use rocket::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::blockchain::{block_work, meets_difficulty, now_millis, BlockHeader};
+use crate::crypto;
+use crate::miner::Miner;
+
+
+const DEFAULT_POA_PERIOD: f64 = 2.0; // seconds per signer slot
+const SLOT_WAIT_STEP: Duration = Duration::from_millis(50); // Between two checks of the cancel signals while waiting for a slot
+
+/// Consensus engine shared between the blockchain, the peer connections and the chain checks.
+pub type SharedConsensus = Arc<dyn ConsensusEngine>;
+
+/// Rules deciding who may add a block and which branch is the main chain.
+///
+/// The canonical genesis block is fixed and never goes through the engine.
+pub trait ConsensusEngine: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether this node can seal blocks at all, a node that cannot does not run the background miner.
+    fn can_seal(&self) -> bool {
+        true
+    }
+
+    /// Whether the difficulty is retargeted every `retarget_interval()` blocks. Otherwise every
+    /// block carries its parent's difficulty.
+    fn retargets(&self) -> bool;
+
+    /// Seal `header`, the header of the block following `parent` with every field but the seal
+    /// final, built when the miner's tip counter was `tip`. Blocks the calling thread until the
+    /// block may be added to the chain, returns the sealed header and its hash, or `None` if
+    /// `miner` cancelled sealing.
+    fn seal(&self, header: &BlockHeader, parent: &BlockHeader, miner: &Miner, tip: u64) -> Option<(BlockHeader, String)>;
+
+    /// Check the seal of the block with header `header` and hash `hash` following `parent`.
+    fn verify_seal(&self, header: &BlockHeader, hash: &str, parent: &BlockHeader) -> Result<(), String>;
+
+    /// Weight of a block in fork choice: the main chain is the branch with the highest total
+    /// weight, the branch seen first on a tie.
+    fn block_weight(&self, header: &BlockHeader) -> u128;
+}
+
+/// Engine from `CONSENSUS`: `pow` (default) or `poa`, configured with `POA_SIGNERS`,
+/// `POA_SECRET_KEY` and `POA_PERIOD`.
+pub fn from_env() -> Result<SharedConsensus, String> {
+    match std::env::var("CONSENSUS").unwrap_or_default().to_lowercase().as_str() {
+        "" | "pow" => Ok(Arc::new(ProofOfWork)),
+        "poa" => {
+            let signers = std::env::var("POA_SIGNERS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|signer| signer.trim().to_string())
+                .filter(|signer| !signer.is_empty())
+                .collect();
+            let secret_key = std::env::var("POA_SECRET_KEY").ok().filter(|v| !v.is_empty());
+            let period = std::env::var("POA_PERIOD")
+                .ok()
+                .and_then(|v| v.parse::<f64>().ok())
+                .filter(|v| *v > 0.0)
+                .unwrap_or(DEFAULT_POA_PERIOD);
+            Ok(Arc::new(ProofOfAuthority::new(signers, secret_key, period)?))
+        }
+        other => Err(format!("unknown CONSENSUS '{}', expected 'pow' or 'poa'", other)),
+    }
+}
+
+/// SHA-256 proof of work: a block is sealed by finding a nonce giving its header hash
+/// `difficulty` leading zero bits, and the branch with the most expected hashes wins.
+pub struct ProofOfWork;
+
+impl ConsensusEngine for ProofOfWork {
+    fn name(&self) -> &'static str {
+        "pow"
+    }
+
+    fn retargets(&self) -> bool {
+        true
+    }
+
+    fn seal(&self, header: &BlockHeader, _parent: &BlockHeader, miner: &Miner, tip: u64) -> Option<(BlockHeader, String)> {
+        miner.mine(header, tip)
+    }
+
+    fn verify_seal(&self, header: &BlockHeader, hash: &str, _parent: &BlockHeader) -> Result<(), String> {
+        if !meets_difficulty(hash, header.difficulty) {
+            return Err(format!("hash does not meet difficulty {}: '{}'", header.difficulty, hash));
+        }
+        Ok(())
+    }
+
+    fn block_weight(&self, header: &BlockHeader) -> u128 {
+        block_work(header.difficulty)
+    }
+}
+
+/// Proof of authority for private networks: time is cut in slots of `period` seconds, each
+/// assigned in turn to one of the `signers` public keys, and a block is sealed by the signature
+/// of its slot's signer over the block hash. Every block weighs the same, the longest chain wins.
+pub struct ProofOfAuthority {
+    signers: Vec<String>,
+    // This node's signing key and its public key, if it is one of the signers
+    key: Option<(String, String)>,
+    period_ms: i64,
+}
+
+impl ProofOfAuthority {
+    pub fn new(signers: Vec<String>, secret_key: Option<String>, period: f64) -> Result<Self, String> {
+        if signers.is_empty() {
+            return Err("POA_SIGNERS lists no signer public key".to_string());
+        }
+        for signer in &signers {
+            crypto::address_from_public_key(signer).map_err(|e| format!("invalid POA_SIGNERS key '{}': {}", signer, e))?;
+        }
+
+        let key = match secret_key {
+            Some(secret_key) => {
+                let public_key = crypto::public_key(&secret_key).map_err(|e| format!("invalid POA_SECRET_KEY: {}", e))?;
+                if !signers.contains(&public_key) {
+                    return Err(format!("POA_SECRET_KEY belongs to {}, which is not in POA_SIGNERS", public_key));
+                }
+                Some((secret_key, public_key))
+            }
+            None => None,
+        };
+
+        let period_ms = ((period * 1000.0) as i64).max(1);
+        Ok(ProofOfAuthority { signers, key, period_ms })
+    }
+
+    fn slot(&self, timestamp: i64) -> i64 {
+        timestamp.div_euclid(self.period_ms)
+    }
+
+    /// Public key of the signer whose turn `slot` is.
+    pub fn signer_of(&self, slot: i64) -> &str {
+        &self.signers[slot.rem_euclid(self.signers.len() as i64) as usize]
+    }
+}
+
+impl ConsensusEngine for ProofOfAuthority {
+    fn name(&self) -> &'static str {
+        "poa"
+    }
+
+    fn can_seal(&self) -> bool {
+        self.key.is_some()
+    }
+
+    fn retargets(&self) -> bool {
+        false
+    }
+
+    fn seal(&self, header: &BlockHeader, parent: &BlockHeader, miner: &Miner, tip: u64) -> Option<(BlockHeader, String)> {
+        let Some((secret_key, public_key)) = &self.key else {
+            warn!("this node is not a proof of authority signer, it cannot seal blocks");
+            return None;
+        };
+
+        // First slot of ours after the parent's, not in the past
+        let mut slot = (self.slot(parent.timestamp) + 1).max(self.slot(now_millis()));
+        while self.signer_of(slot) != public_key {
+            slot += 1;
+        }
+        let start = slot * self.period_ms;
+        loop {
+            if miner.is_cancelled(tip) {
+                return None;
+            }
+            let now = now_millis();
+            if now >= start {
+                break;
+            }
+            std::thread::sleep(SLOT_WAIT_STEP.min(Duration::from_millis((start - now) as u64)));
+        }
+
+        let mut header = header.clone();
+        header.timestamp = now_millis().clamp(start, start + self.period_ms - 1);
+        header.nonce = 0;
+        header.signature = None;
+        let hash = header.hash();
+        let digest = hex::decode(&hash).expect("header hashes are hex encoded");
+        header.signature = Some(crypto::sign(secret_key, &digest).ok()?);
+        info!("sealed block at idx {} in slot {}", header.idx, slot);
+        Some((header, hash))
+    }
+
+    fn verify_seal(&self, header: &BlockHeader, hash: &str, parent: &BlockHeader) -> Result<(), String> {
+        let slot = self.slot(header.timestamp);
+        if slot <= self.slot(parent.timestamp) {
+            return Err(format!("slot {} does not follow the parent's slot {}", slot, self.slot(parent.timestamp)));
+        }
+        // Signers must not seal ahead of time, one slot of clock drift is tolerated
+        if slot > self.slot(now_millis()) + 1 {
+            return Err(format!("slot {} has not started yet", slot));
+        }
+
+        let signature = header.signature.as_deref().ok_or_else(|| "block is not signed".to_string())?;
+        let digest = hex::decode(hash).map_err(|e| format!("invalid block hash: {}", e))?;
+        let signer = self.signer_of(slot);
+        crypto::verify(signer, &digest, signature).map_err(|e| format!("not signed by {}, the signer of slot {}: {}", signer, slot, e))
+    }
+
+    fn block_weight(&self, _header: &BlockHeader) -> u128 {
+        1
+    }
+}
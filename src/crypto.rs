@@ -0,0 +1,98 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+
+const ADDRESS_VERSION: u8 = 0x00; // First byte of every address payload
+const ADDRESS_HASH_LEN: usize = 20; // Bytes of the public key hash kept in the address
+const CHECKSUM_LEN: usize = 4;
+
+
+/// Generate a new ed25519 keypair, returned as hex encoded (secret key, public key).
+pub fn generate_keypair() -> (String, String) {
+    let signing_key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+    let verifying_key = signing_key.verifying_key();
+    (hex::encode(signing_key.to_bytes()), hex::encode(verifying_key.to_bytes()))
+}
+
+/// Derive the hex encoded public key of a hex encoded secret key.
+pub fn public_key(secret_key: &str) -> Result<String, String> {
+    let signing_key = signing_key(secret_key)?;
+    Ok(hex::encode(signing_key.verifying_key().to_bytes()))
+}
+
+/// Sign `message` with a hex encoded secret key, returning the hex encoded signature.
+pub fn sign(secret_key: &str, message: &[u8]) -> Result<String, String> {
+    let signing_key = signing_key(secret_key)?;
+    Ok(hex::encode(signing_key.sign(message).to_bytes()))
+}
+
+/// Check that `signature` (hex) was produced over `message` by the owner of `public_key` (hex).
+/// The signature must be lowercase hex, as `sign` encodes it: transaction ids hash it as written,
+/// so no other spelling of the same bytes may be accepted.
+pub fn verify(public_key: &str, message: &[u8], signature: &str) -> Result<(), String> {
+    if signature.bytes().any(|b| b.is_ascii_uppercase()) {
+        return Err("invalid signature: not lowercase hex".to_string());
+    }
+    let key_bytes: [u8; 32] = decode_fixed(public_key).map_err(|e| format!("invalid public key: {}", e))?;
+    let verifying_key =
+        VerifyingKey::from_bytes(&key_bytes).map_err(|e| format!("invalid public key: {}", e))?;
+
+    let sig_bytes: [u8; 64] = decode_fixed(signature).map_err(|e| format!("invalid signature: {}", e))?;
+    let signature = Signature::from_bytes(&sig_bytes);
+
+    verifying_key
+        .verify(message, &signature)
+        .map_err(|_| "signature does not match the sender's public key".to_string())
+}
+
+/// Derive the Base58Check address of a hex encoded public key:
+/// base58(version || sha256(pub_key)[..20] || sha256(sha256(version || hash))[..4]).
+pub fn address_from_public_key(public_key: &str) -> Result<String, String> {
+    let key_bytes: [u8; 32] = decode_fixed(public_key).map_err(|e| format!("invalid public key: {}", e))?;
+
+    let mut payload = vec![ADDRESS_VERSION];
+    payload.extend_from_slice(&Sha256::digest(key_bytes)[..ADDRESS_HASH_LEN]);
+    let checksum = checksum(&payload);
+    payload.extend_from_slice(&checksum);
+
+    Ok(bs58::encode(payload).into_string())
+}
+
+/// Check that `address` is well formed: valid base58, known version byte and matching checksum.
+pub fn validate_address(address: &str) -> Result<(), String> {
+    let bytes = bs58::decode(address)
+        .into_vec()
+        .map_err(|e| format!("invalid address '{}': {}", address, e))?;
+    if bytes.len() != 1 + ADDRESS_HASH_LEN + CHECKSUM_LEN {
+        return Err(format!("invalid address '{}': wrong length", address));
+    }
+    if bytes[0] != ADDRESS_VERSION {
+        return Err(format!("invalid address '{}': unknown version {}", address, bytes[0]));
+    }
+
+    let (payload, expected) = bytes.split_at(1 + ADDRESS_HASH_LEN);
+    if checksum(payload) != expected {
+        return Err(format!("invalid address '{}': checksum mismatch", address));
+    }
+
+    Ok(())
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    let mut checksum = [0u8; CHECKSUM_LEN];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LEN]);
+    checksum
+}
+
+fn signing_key(secret_key: &str) -> Result<SigningKey, String> {
+    let bytes: [u8; 32] = decode_fixed(secret_key).map_err(|e| format!("invalid secret key: {}", e))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+fn decode_fixed<const N: usize>(value: &str) -> Result<[u8; N], String> {
+    let bytes = hex::decode(value).map_err(|e| e.to_string())?;
+    bytes
+        .try_into()
+        .map_err(|b: Vec<u8>| format!("expected {} bytes, got {}", N, b.len()))
+}
@@ -0,0 +1,96 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use crate::crypto::public_key;
+
+
+const KDF: &str = "argon2id";
+const CIPHER: &str = "chacha20poly1305";
+
+/// Keystore file: the secret key is encrypted with a key derived from the passphrase.
+#[derive(Serialize, Deserialize)]
+pub struct Keystore {
+    pub address: String,
+    pub pub_key: String,
+    pub kdf: String,
+    pub cipher: String,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32], String> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| format!("failed to derive keystore key: {}", e))?;
+    Ok(key)
+}
+
+pub fn encrypt_keystore(secret_key: &str, pub_key: &str, address: &str, passphrase: &str) -> Result<Keystore, String> {
+    let salt = rand::random::<[u8; 16]>();
+    let nonce = rand::random::<[u8; 12]>();
+    let key = derive_key(passphrase, &salt)?;
+    let secret = hex::decode(secret_key).map_err(|e| format!("invalid secret key: {}", e))?;
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), secret.as_ref())
+        .map_err(|e| format!("failed to encrypt keystore: {}", e))?;
+
+    Ok(Keystore {
+        address: address.to_string(),
+        pub_key: pub_key.to_string(),
+        kdf: KDF.to_string(),
+        cipher: CIPHER.to_string(),
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })
+}
+
+pub fn decrypt_keystore(keystore: &Keystore, passphrase: &str) -> Result<String, String> {
+    if keystore.kdf != KDF || keystore.cipher != CIPHER {
+        return Err(format!("unsupported keystore format {}/{}", keystore.kdf, keystore.cipher));
+    }
+
+    let salt = hex::decode(&keystore.salt).map_err(|e| format!("invalid keystore salt: {}", e))?;
+    let nonce = hex::decode(&keystore.nonce).map_err(|e| format!("invalid keystore nonce: {}", e))?;
+    let ciphertext = hex::decode(&keystore.ciphertext).map_err(|e| format!("invalid keystore ciphertext: {}", e))?;
+    if nonce.len() != 12 {
+        return Err("invalid keystore nonce length".to_string());
+    }
+    let key = derive_key(passphrase, &salt)?;
+
+    let secret = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| "wrong passphrase or corrupted keystore".to_string())?;
+    let secret_key = hex::encode(secret);
+
+    // Guard against a keystore whose public fields were edited
+    if public_key(&secret_key)? != keystore.pub_key {
+        return Err("keystore public key does not match its secret key".to_string());
+    }
+    Ok(secret_key)
+}
+
+pub fn read_keystore(path: &Path) -> Result<Keystore, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read keystore {}: {}", path.display(), e))?;
+    serde_json::from_str(&contents).map_err(|e| format!("invalid keystore {}: {}", path.display(), e))
+}
+
+pub fn write_keystore(path: &Path, keystore: &Keystore) -> Result<(), String> {
+    // Never overwrite an existing keystore, that would lose its key
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| format!("failed to create keystore {}: {}", path.display(), e))?;
+    let json = serde_json::to_string_pretty(keystore).map_err(|e| e.to_string())?;
+    file.write_all(json.as_bytes())
+        .map_err(|e| format!("failed to write keystore {}: {}", path.display(), e))
+}
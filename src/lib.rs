@@ -1,3 +1,12 @@
 pub mod blockchain;
+pub mod mempool;
+pub mod merkle;
+pub mod miner;
+pub mod p2p;
+pub mod sync;
 pub mod utils;
-pub mod transactions;
\ No newline at end of file
+pub mod transactions;
+pub mod crypto;
+pub mod keystore;
+pub mod consensus;
+pub mod store;
@@ -4,48 +4,81 @@ use rocket::fairing::AdHoc;
 use rocket::tokio::{self, task};
 use rocket::Shutdown;
 use std::sync::{Arc, Mutex};
-use sqlx::sqlite::SqlitePoolOptions;
+use std::time::Duration;
 use rocket::error as rocket_error;
 
-mod blockchain;
-mod utils;
-mod transactions;
-use blockchain::{Block, Blockchain};
+use my_rust_blockchain::{blockchain, consensus, mempool, miner, p2p, store, sync, transactions, utils};
+use blockchain::{Blockchain, MiningMode, SharedBlockchain};
+use consensus::SharedConsensus;
+use utils::ApiError;
+use mempool::{Mempool, SharedMempool};
+use miner::{Miner, SharedMiner};
+use p2p::{Network, Node, SharedNetwork};
+use store::SharedStore;
+use sync::{ChainSync, SharedSync};
 
-use crate::utils::verify_db_state_streaming;
+use my_rust_blockchain::utils::{verify_chain_deep, verify_db_state_streaming};
 
 
 #[get("/")]
 fn index() -> &'static str { "ok" }
 
-async fn cpu_worker(mut shutdown: Shutdown) {
-    let blockchain = Arc::new(Mutex::new(Blockchain::new().await));
+async fn cpu_worker(store: SharedStore, blockchain: SharedBlockchain, sync: SharedSync, mode: MiningMode, mut shutdown: Shutdown) {
+    // Blocks mined on a stale chain would only be orphaned, catch up with the peers first
+    if !sync.is_synced() {
+        info!("waiting for the chain to sync before mining");
+        tokio::select! {
+            _ = &mut shutdown => return,
+            _ = sync.wait_synced() => {}
+        }
+    }
 
     loop {
+        // Errors are already logged by the library, retry after the idle delay
+        let idle = store.pending_count().await.map_or(true, |count| mode.is_idle(count));
+        if idle {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = tokio::time::sleep(Duration::from_secs(1)) => continue,
+            }
+        }
+
+        // Run CPU work on a dedicated blocking thread pool
+        let blockchain = Arc::clone(&blockchain);
         tokio::select! {
             _ = &mut shutdown => break,
-            _ = async {
-                // Run CPU work on a dedicated blocking thread pool:
-                let blockchain = Arc::clone(&blockchain);
-                task::spawn_blocking(move || {
-                    let mut blockchain = blockchain.lock().expect("blockchain lock");
-                    rocket::tokio::runtime::Handle::current().block_on(async {
-                        blockchain_operations(&mut *blockchain).await;
-                    });
-                }).await.expect("spawn_blocking failed");
-            } => {}
+            result = task::spawn_blocking(move || blockchain_operations(&blockchain)) => {
+                result.expect("spawn_blocking failed");
+            }
         }
     }
 }
 
-async fn blockchain_operations(blockchain: &mut Blockchain) {
-    let index = blockchain.get_height().await;
-    let new_block = Block::new(index, String::new()).await;
-    blockchain.add_block(new_block.clone()).await;
+fn blockchain_operations(blockchain: &SharedBlockchain) {
+    let new_block = match blockchain::mine_block(blockchain) {
+        Ok(mined) => mined.block,
+        // Another block was appended while mining or the node is shutting down
+        Err(ApiError::Conflict(msg)) => {
+            info!("stopped mining block: {}", msg);
+            return;
+        }
+        Err(e) => {
+            rocket_error!("failed to mine block: {}", e);
+            // Do not spin on a persistent failure such as a locked database
+            std::thread::sleep(Duration::from_secs(1));
+            return;
+        }
+    };
 
     println!(
-        "Index: {}, Timestamp: {}, Data: {}, Previous Hash: {}, Hash: {}, Nonce: {}",
-        new_block.idx, new_block.timestamp, new_block.data, new_block.previous_hash, new_block.hash, new_block.nonce
+        "Index: {}, Timestamp: {}, Merkle Root: {}, Transactions: {}, Previous Hash: {}, Hash: {}, Nonce: {}",
+        new_block.header.idx,
+        new_block.header.timestamp,
+        new_block.header.merkle_root,
+        new_block.body.len(),
+        new_block.header.previous_hash,
+        new_block.hash,
+        new_block.header.nonce
     );
 }
 
@@ -53,39 +86,109 @@ async fn blockchain_operations(blockchain: &mut Blockchain) {
 async fn rocket() -> _ {
     dotenvy::dotenv().ok();
     
-    // For local dev this could be e.g. "sqlite://app.db"
-    // Or "sqlite::memory:" for in-memory testing.
-    let database_url =
-        std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://database.sqlite".to_string());
-
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await
-        .unwrap_or_else(|e| {
-            error!("failed to connect to SQLite at {}: {}", database_url, e);
-            panic!("failed to connect to SQLite");
-        });
+    // DATABASE_URL for local dev could be e.g. "sqlite://app.db"
+    // The store is shared by every request handler and the cpu worker.
+    let store: SharedStore = store::connect().await.unwrap_or_else(|e| {
+        rocket_error!("failed to open the chain store: {}", e);
+        panic!("failed to open the chain store: {}", e);
+    });
 
-    sqlx::migrate!("./migrations")
-        .run(&pool)
-        .await
-        .expect("migrations failed");
+    let consensus: SharedConsensus = consensus::from_env().unwrap_or_else(|e| {
+        rocket_error!("invalid consensus configuration: {}", e);
+        panic!("invalid consensus configuration: {}", e);
+    });
+    info!("consensus engine is {}", consensus.name());
 
     // New: verify DB health and application-level chain consistency on boot.
-    if let Err(e) = verify_db_state_streaming().await {
+    if let Err(e) = verify_db_state_streaming(store.as_ref(), consensus.as_ref()).await {
         // Fail fast — do not start the server with a corrupted DB.
         rocket_error!("database verification failed on boot: {}", e);
         panic!("database verification failed on boot: {}", e);
     }
 
+    // Optionally recompute every hash, Merkle root and balance as well (slow on large chains).
+    if std::env::var("VERIFY_ON_BOOT").is_ok_and(|v| v == "deep") {
+        let report = verify_chain_deep(store.as_ref(), consensus.as_ref()).await.unwrap_or_else(|e| {
+            rocket_error!("deep chain verification failed on boot: {}", e);
+            panic!("deep chain verification failed on boot: {}", e);
+        });
+        if !report.is_valid() {
+            let report = serde_json::to_string(&report).unwrap_or_default();
+            rocket_error!("deep chain verification failed on boot: {}", report);
+            panic!("deep chain verification failed on boot: {}", report);
+        }
+    }
+
+    if let Err(e) = blockchain::check_miner_config() {
+        rocket_error!("invalid miner configuration: {}", e);
+        panic!("invalid miner configuration: {}", e);
+    }
+
+    let mempool: SharedMempool = Arc::new(Mempool::load(store.as_ref()).await.unwrap_or_else(|e| {
+        rocket_error!("failed to load mempool: {}", e);
+        panic!("failed to load mempool: {}", e);
+    }));
+    let miner: SharedMiner = Arc::new(Miner::from_env());
+    let network: SharedNetwork = Arc::new(Network::from_env());
+    let blockchain = Blockchain::new(Arc::clone(&store), Arc::clone(&consensus), Arc::clone(&miner), Arc::clone(&mempool), Arc::clone(&network))
+        .await
+        .unwrap_or_else(|e| {
+            rocket_error!("failed to load blockchain: {}", e);
+            panic!("failed to load blockchain: {}", e);
+        });
+    let blockchain: SharedBlockchain = Arc::new(Mutex::new(blockchain));
+
+    let node = Node {
+        store: Arc::clone(&store),
+        consensus: Arc::clone(&consensus),
+        mempool: Arc::clone(&mempool),
+        blockchain: Arc::clone(&blockchain),
+    };
+    if let Err(e) = network.start(node.clone()).await {
+        rocket_error!("failed to start the peer to peer network: {}", e);
+        panic!("failed to start the peer to peer network: {}", e);
+    }
+    let sync: SharedSync = Arc::new(ChainSync::new());
+    tokio::spawn(Arc::clone(&sync).run(node, Arc::clone(&network)));
+    let mining_mode = MiningMode::from_env();
+    let worker_blockchain = Arc::clone(&blockchain);
+    let worker_store = Arc::clone(&store);
+    let shutdown_miner = Arc::clone(&miner);
+    let worker_sync = Arc::clone(&sync);
+    let can_seal = consensus.can_seal();
+
     rocket::build()
+        .manage(store)
+        .manage(blockchain)
+        .manage(consensus)
+        .manage(miner)
+        .manage(mempool)
+        .manage(network)
+        .manage(sync)
+        .register("/", utils::catchers())
         .mount("/", routes![index])
         .mount("/", blockchain::routes())
         .mount("/", transactions::routes())
-        .attach(AdHoc::on_liftoff("spawn cpu worker", |rocket| {
+        .mount("/", miner::routes())
+        .mount("/", mempool::routes())
+        .mount("/", p2p::routes())
+        .mount("/", sync::routes())
+        .attach(AdHoc::on_liftoff("spawn cpu worker", move |rocket| {
             Box::pin(async move {
-                tokio::spawn(cpu_worker(rocket.shutdown()));
+                // Interrupt a block being mined as soon as shutdown starts instead of after it is found
+                let shutdown = rocket.shutdown();
+                tokio::spawn(async move {
+                    shutdown.await;
+                    shutdown_miner.shutdown();
+                });
+
+                if !can_seal {
+                    info!("this node is not a block signer, it only follows the chain");
+                } else if mining_mode == MiningMode::OnDemand {
+                    info!("mining mode is on demand, blocks are only produced through POST /mine");
+                } else {
+                    tokio::spawn(cpu_worker(worker_store, worker_blockchain, worker_sync, mining_mode, rocket.shutdown()));
+                }
             })
         }))
 }
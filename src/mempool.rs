@@ -0,0 +1,376 @@
+use rocket::{get, serde::json::Json, routes, State};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use crate::blockchain::now_millis;
+use crate::store::ChainStore;
+use crate::transactions::Transaction;
+use crate::utils::*;
+
+
+const DEFAULT_MAX_BLOCK_TXS: usize = 1000;
+const DEFAULT_MAX_BLOCK_SIZE: usize = 1_000_000; // bytes
+const DEFAULT_MEMPOOL_MAX_TXS: usize = 10_000;
+const DEFAULT_MEMPOOL_EXPIRY: i64 = 24 * 60 * 60; // seconds
+const MAX_QUEUED_PER_ACCOUNT: usize = 64; // Transactions with a future nonce kept for a single sender
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_mempool, get_mempool_entry]
+}
+
+/// Maximum number of transactions in a block besides the coinbase, configurable with `MAX_BLOCK_TXS`.
+pub fn max_block_txs() -> usize {
+    env_limit("MAX_BLOCK_TXS", DEFAULT_MAX_BLOCK_TXS)
+}
+
+/// Maximum total size in bytes of the transactions in a block besides the coinbase, configurable
+/// with `MAX_BLOCK_SIZE`.
+pub fn max_block_size() -> usize {
+    env_limit("MAX_BLOCK_SIZE", DEFAULT_MAX_BLOCK_SIZE)
+}
+
+/// Number of transactions kept in the mempool before the lowest fee rates are evicted,
+/// configurable with `MEMPOOL_MAX_TXS`.
+pub fn mempool_max_txs() -> usize {
+    env_limit("MEMPOOL_MAX_TXS", DEFAULT_MEMPOOL_MAX_TXS)
+}
+
+/// Seconds a transaction can wait in the mempool before it is evicted, configurable with `MEMPOOL_EXPIRY`.
+pub fn mempool_expiry() -> i64 {
+    std::env::var("MEMPOOL_EXPIRY")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_MEMPOOL_EXPIRY)
+}
+
+fn env_limit(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(default)
+}
+
+/// Mempool shared between the blockchain and the request handlers.
+pub type SharedMempool = Arc<Mempool>;
+
+/// A transaction waiting to be included in a block.
+#[derive(Clone, Serialize)]
+pub struct MempoolEntry {
+    // Row of the transaction in the `transactions` table
+    #[serde(skip)]
+    pub rowid: i64,
+    pub txid: String,
+    pub transaction: Transaction,
+    pub size: usize,
+    /// Fee paid per byte of the transaction
+    pub fee_rate: f64,
+    /// Milliseconds since the unix epoch at which the node added the transaction to its mempool
+    pub added_at: i64,
+}
+
+/// A transaction whose nonce is ahead of its sender's next nonce. It has not moved any funds
+/// yet and is only kept in memory until the transactions before it are accepted.
+#[derive(Clone, Serialize)]
+pub struct QueuedEntry {
+    pub txid: String,
+    pub transaction: Transaction,
+    pub added_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct MempoolInfo {
+    pub count: usize,
+    pub size: usize,
+    pub fees: i64,
+    /// Highest fee rate first, the order in which transactions are picked for the next block
+    pub transactions: Vec<MempoolEntry>,
+    /// Transactions waiting for a lower nonce of their sender, by sender and nonce
+    pub queued: Vec<QueuedEntry>,
+}
+
+/// In-memory index of the pending transactions, the stored transactions without a block.
+///
+/// Accepted transactions already moved funds between wallets, so block assembly must keep a
+/// transaction together with the pending transactions that funded its sender, and evicting a
+/// transaction gives the funds back.
+pub struct Mempool {
+    entries: Mutex<HashMap<String, MempoolEntry>>,
+    // Future nonce transactions by sender and nonce
+    queued: Mutex<HashMap<String, BTreeMap<i64, QueuedEntry>>>,
+}
+
+impl MempoolEntry {
+    pub fn new(rowid: i64, transaction: Transaction) -> Self {
+        let size = transaction.size();
+        MempoolEntry {
+            rowid,
+            txid: transaction.txid(),
+            fee_rate: transaction.fee as f64 / size as f64,
+            size,
+            transaction,
+            added_at: now_millis(),
+        }
+    }
+}
+
+impl Mempool {
+    /// Mempool indexing the pending transactions of `store`.
+    pub async fn load(store: &dyn ChainStore) -> Result<Self, ApiError> {
+        let pending = store.pending_transactions().await?;
+
+        let mempool = Mempool { entries: Mutex::new(HashMap::new()), queued: Mutex::new(HashMap::new()) };
+        for (rowid, transaction) in pending {
+            mempool.insert(rowid, transaction);
+        }
+        Ok(mempool)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, MempoolEntry>> {
+        self.entries.lock().expect("mempool lock")
+    }
+
+    fn lock_queued(&self) -> std::sync::MutexGuard<'_, HashMap<String, BTreeMap<i64, QueuedEntry>>> {
+        self.queued.lock().expect("mempool queue lock")
+    }
+
+    /// Index the transaction stored at `rowid`, returning its txid.
+    pub fn insert(&self, rowid: i64, transaction: Transaction) -> String {
+        // Its nonce is used now, a queued transaction with the same one can never be accepted
+        self.take_queued(&transaction.from_address, transaction.nonce);
+
+        let entry = MempoolEntry::new(rowid, transaction);
+        let txid = entry.txid.clone();
+        self.lock().insert(txid.clone(), entry);
+        txid
+    }
+
+    pub fn get(&self, txid: &str) -> Option<MempoolEntry> {
+        self.lock().get(txid).cloned()
+    }
+
+    pub fn contains(&self, txid: &str) -> bool {
+        self.lock().contains_key(txid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// Forget transactions that were included in a block.
+    pub fn remove(&self, txids: &[String]) {
+        let mut entries = self.lock();
+        for txid in txids {
+            entries.remove(txid);
+        }
+    }
+
+    /// Keep a transaction with a future nonce until its sender's earlier transactions are accepted.
+    pub fn queue(&self, transaction: Transaction) -> Result<(), ApiError> {
+        let mut queued = self.lock_queued();
+        let account = queued.entry(transaction.from_address.clone()).or_default();
+
+        let txid = transaction.txid();
+        match account.get(&transaction.nonce) {
+            Some(existing) if existing.txid == txid => return Ok(()),
+            Some(_) => {
+                return Err(ApiError::Conflict(format!(
+                    "another transaction with nonce {} from {} is already queued",
+                    transaction.nonce, transaction.from_address
+                )));
+            }
+            None if account.len() >= MAX_QUEUED_PER_ACCOUNT => {
+                return Err(ApiError::Conflict(format!(
+                    "{} already has {} transactions waiting for earlier nonces",
+                    transaction.from_address, MAX_QUEUED_PER_ACCOUNT
+                )));
+            }
+            None => {}
+        }
+
+        account.insert(transaction.nonce, QueuedEntry { txid, transaction, added_at: now_millis() });
+        Ok(())
+    }
+
+    /// Remove and return the queued transaction of `address` with `nonce`.
+    pub fn take_queued(&self, address: &str, nonce: i64) -> Option<Transaction> {
+        let mut queued = self.lock_queued();
+        let account = queued.get_mut(address)?;
+        let entry = account.remove(&nonce);
+        if account.is_empty() {
+            queued.remove(address);
+        }
+        entry.map(|entry| entry.transaction)
+    }
+
+    /// Queued transaction with `txid`, if any.
+    pub fn queued(&self, txid: &str) -> Option<Transaction> {
+        self.lock_queued()
+            .values()
+            .flat_map(|account| account.values())
+            .find(|entry| entry.txid == txid)
+            .map(|entry| entry.transaction.clone())
+    }
+
+    pub fn info(&self) -> MempoolInfo {
+        let mut transactions: Vec<MempoolEntry> = self.lock().values().cloned().collect();
+        transactions.sort_by(by_fee_rate);
+        let mut queued: Vec<QueuedEntry> = self.lock_queued().values().flat_map(|account| account.values().cloned()).collect();
+        queued.sort_by(|a, b| {
+            (&a.transaction.from_address, a.transaction.nonce).cmp(&(&b.transaction.from_address, b.transaction.nonce))
+        });
+        MempoolInfo {
+            count: transactions.len(),
+            size: transactions.iter().map(|entry| entry.size).sum(),
+            fees: transactions.iter().map(|entry| entry.transaction.fee as i64).sum(),
+            transactions,
+            queued,
+        }
+    }
+
+    /// Transactions for the next block, picked by decreasing fee rate within `max_size` bytes
+    /// and `max_txs` transactions, and returned in the order they were accepted.
+    ///
+    /// A transaction is only picked together with the earlier pending transactions crediting
+    /// its sender, it may have been accepted thanks to those funds, and the earlier pending
+    /// transactions of its sender, to keep its nonces in sequence (and theirs, recursively).
+    pub fn select(&self, max_size: usize, max_txs: usize) -> Vec<MempoolEntry> {
+        let entries = self.lock();
+        let mut candidates: Vec<&MempoolEntry> = entries.values().collect();
+        candidates.sort_by(|a, b| by_fee_rate(a, b));
+
+        // Pending transactions touching an address, as recipient or as sender
+        let mut related: HashMap<&str, Vec<&MempoolEntry>> = HashMap::new();
+        for entry in entries.values() {
+            related.entry(entry.transaction.to_address.as_str()).or_default().push(entry);
+            related.entry(entry.transaction.from_address.as_str()).or_default().push(entry);
+        }
+
+        let mut selected: HashMap<i64, &MempoolEntry> = HashMap::new();
+        let mut size = 0;
+        for candidate in candidates {
+            if selected.contains_key(&candidate.rowid) {
+                continue;
+            }
+
+            // The candidate and its ancestors that are not in the block yet
+            let mut package: HashMap<i64, &MempoolEntry> = HashMap::new();
+            let mut stack = vec![candidate];
+            while let Some(entry) = stack.pop() {
+                if selected.contains_key(&entry.rowid) || package.insert(entry.rowid, entry).is_some() {
+                    continue;
+                }
+                let ancestors = related.get(entry.transaction.from_address.as_str());
+                stack.extend(ancestors.into_iter().flatten().filter(|ancestor| ancestor.rowid < entry.rowid));
+            }
+
+            let package_size: usize = package.values().map(|entry| entry.size).sum();
+            if selected.len() + package.len() > max_txs || size + package_size > max_size {
+                continue;
+            }
+            size += package_size;
+            selected.extend(package);
+        }
+
+        let mut block: Vec<MempoolEntry> = selected.into_values().cloned().collect();
+        block.sort_by_key(|entry| entry.rowid);
+        block
+    }
+
+    /// Evict transactions older than `expiry` seconds, then the lowest fee rates until at most
+    /// `max_txs` remain, reverting their balance changes and their sender's nonce.
+    /// Transactions followed by a later pending transaction of their sender, or of their
+    /// recipient which could be spending the funds, are kept. Stale queued transactions are
+    /// dropped as well. Returns the txids of the evicted pending transactions.
+    pub async fn evict(&self, store: &dyn ChainStore, max_txs: usize, expiry: i64) -> Result<Vec<String>, ApiError> {
+        let expired_before = now_millis() - expiry * 1000;
+        self.lock_queued().retain(|_, account| {
+            account.retain(|_, entry| entry.added_at >= expired_before);
+            !account.is_empty()
+        });
+
+        let candidates = {
+            let entries = self.lock();
+            // Latest pending spend of every sender
+            let mut last_spend: HashMap<&str, i64> = HashMap::new();
+            for entry in entries.values() {
+                let rowid = last_spend.entry(entry.transaction.from_address.as_str()).or_insert(entry.rowid);
+                *rowid = (*rowid).max(entry.rowid);
+            }
+            let has_descendants = |entry: &MempoolEntry| {
+                [&entry.transaction.to_address, &entry.transaction.from_address]
+                    .iter()
+                    .any(|address| last_spend.get(address.as_str()).is_some_and(|rowid| *rowid > entry.rowid))
+            };
+
+            // Lowest fee rate first
+            let mut by_fee_rate_asc: Vec<&MempoolEntry> = entries.values().collect();
+            by_fee_rate_asc.sort_by(|a, b| by_fee_rate(b, a));
+
+            let mut remaining = entries.len();
+            let mut candidates = Vec::new();
+            for entry in by_fee_rate_asc {
+                let stale = entry.added_at < expired_before;
+                if (stale || remaining > max_txs) && !has_descendants(entry) {
+                    candidates.push(entry.clone());
+                    remaining -= 1;
+                }
+            }
+            candidates
+        };
+
+        let mut evicted = Vec::new();
+        for entry in candidates {
+            if store.revert_pending(entry.rowid, &entry.transaction).await? {
+                evicted.push(entry.txid);
+            }
+        }
+        self.remove(&evicted);
+        Ok(evicted)
+    }
+
+    /// Revert every pending transaction, latest first so that each one's sender and recipient
+    /// are back where it left them, and return them in the order they were accepted. Used to
+    /// unwind the chain during a reorg.
+    pub async fn revert_all(&self, store: &dyn ChainStore) -> Result<Vec<Transaction>, ApiError> {
+        let mut entries: Vec<MempoolEntry> = self.lock().values().cloned().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.rowid));
+
+        let mut reverted = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if !store.revert_pending(entry.rowid, &entry.transaction).await? {
+                return Err(ApiError::Internal(format!("pending transaction {} could not be reverted", entry.txid)));
+            }
+            self.remove(std::slice::from_ref(&entry.txid));
+            reverted.push(entry.transaction);
+        }
+        reverted.reverse();
+        Ok(reverted)
+    }
+}
+
+/// Highest fee rate first, the oldest first among equal fee rates.
+fn by_fee_rate(a: &MempoolEntry, b: &MempoolEntry) -> Ordering {
+    b.fee_rate.total_cmp(&a.fee_rate).then(a.rowid.cmp(&b.rowid))
+}
+
+
+#[get("/mempool")]
+async fn get_mempool(mempool: &State<SharedMempool>) -> ApiResult<MempoolInfo> {
+    Ok(Json(mempool.info()))
+}
+
+#[get("/mempool/<txid>")]
+async fn get_mempool_entry(mempool: &State<SharedMempool>, txid: String) -> ApiResult<MempoolEntry> {
+    let entry = mempool
+        .get(&txid)
+        .ok_or_else(|| ApiError::NotFound(format!("transaction {} is not in the mempool", txid)))?;
+
+    Ok(Json(entry))
+}
@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::transactions::Transaction;
+
+
+// Prefixes keeping leaf and inner node preimages apart, so an inner node can never be passed
+// off as a leaf (second preimage) and the other way around
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Side of the running hash a sibling is concatenated on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Position {
+    Left,
+    Right,
+}
+
+/// One level of a Merkle branch: the sibling hash and its position relative to the node being proven.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleStep {
+    pub hash: String,
+    pub position: Position,
+}
+
+type Hash = [u8; 32];
+
+/// Leaf of the Merkle tree for a transaction: SHA-256(0x00 || raw txid), so a client holding
+/// only the txid can recompute it.
+pub fn leaf_hash(transaction: &Transaction) -> String {
+    let txid = Sha256::digest(transaction.signed_payload());
+    hex::encode(Sha256::new().chain_update([LEAF_PREFIX]).chain_update(txid).finalize())
+}
+
+/// Inner node: SHA-256(0x01 || raw left || raw right).
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    Sha256::new()
+        .chain_update([NODE_PREFIX])
+        .chain_update(left)
+        .chain_update(right)
+        .finalize()
+        .into()
+}
+
+fn decode(hash: &str) -> Option<Hash> {
+    hex::decode(hash).ok()?.try_into().ok()
+}
+
+/// Parent level of `level`. The last hash of an odd level has no sibling and moves up unchanged:
+/// pairing it with itself would give `[a, b, c]` and `[a, b, c, c]` the same root.
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            _ => pair[0],
+        })
+        .collect()
+}
+
+fn decode_leaves(leaves: &[String]) -> Option<Vec<Hash>> {
+    leaves.iter().map(|leaf| decode(leaf)).collect()
+}
+
+/// Root of the tree over `leaves`, as produced by `leaf_hash`. The root of no leaves is the
+/// SHA-256 of no input.
+pub fn merkle_root(leaves: &[String]) -> String {
+    let mut level = decode_leaves(leaves).expect("merkle leaves are hex encoded SHA-256 hashes");
+    if level.is_empty() {
+        return hex::encode(Sha256::digest([]));
+    }
+
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    hex::encode(level[0])
+}
+
+/// Sibling hashes from the leaf at `index` up to the root, `None` if `index` is out of range or a
+/// leaf is not a hex encoded SHA-256 hash. Levels where the node has no sibling add no step.
+pub fn merkle_branch(leaves: &[String], mut index: usize) -> Option<Vec<MerkleStep>> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut branch = Vec::new();
+    let mut level = decode_leaves(leaves)?;
+    while level.len() > 1 {
+        if index.is_multiple_of(2) {
+            if let Some(sibling) = level.get(index + 1) {
+                branch.push(MerkleStep { hash: hex::encode(sibling), position: Position::Right });
+            }
+        } else {
+            branch.push(MerkleStep { hash: hex::encode(level[index - 1]), position: Position::Left });
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    Some(branch)
+}
+
+/// Check that `leaf` is included under `merkle_root` by folding the `branch` into it. Only the
+/// block header is needed to get the root, so light clients can prove inclusion of a transaction
+/// without the block body.
+pub fn verify_merkle_proof(leaf: &str, branch: &[MerkleStep], merkle_root: &str) -> bool {
+    let Some(mut hash) = decode(leaf) else {
+        return false;
+    };
+    for step in branch {
+        let Some(sibling) = decode(&step.hash) else {
+            return false;
+        };
+        hash = match step.position {
+            Position::Left => node_hash(&sibling, &hash),
+            Position::Right => node_hash(&hash, &sibling),
+        };
+    }
+    decode(merkle_root) == Some(hash)
+}
@@ -0,0 +1,196 @@
+use rocket::{get, serde::json::Json, routes, State};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use crate::blockchain::{leading_zero_bits_of, now_millis, BlockHeader};
+use crate::utils::ApiResult;
+
+
+const CANCEL_CHECK_INTERVAL: u32 = 4096; // Nonces tried between two checks of the cancel signals
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_mining_stats]
+}
+
+/// Miner shared between the blockchain, the background worker and the request handlers.
+pub type SharedMiner = Arc<Miner>;
+
+/// Proof of work miner splitting the nonce space across `threads` worker threads.
+///
+/// Mining is cancelled when the chain tip changes (see `tip_changed`) or the node shuts down.
+pub struct Miner {
+    threads: u32,
+    tip: AtomicU64,
+    shutdown: AtomicBool,
+    hashes: AtomicU64,
+    hashrate: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct MiningStats {
+    pub threads: u32,
+    /// Hashes per second measured while mining the last block
+    pub hashrate: u64,
+    /// Hashes computed since the node started
+    pub hashes: u64,
+}
+
+impl Miner {
+    pub fn new(threads: u32) -> Self {
+        Miner {
+            threads: threads.max(1),
+            tip: AtomicU64::new(0),
+            shutdown: AtomicBool::new(false),
+            hashes: AtomicU64::new(0),
+            hashrate: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of threads from `MINING_THREADS`, defaulting to the available parallelism.
+    pub fn from_env() -> Self {
+        let threads = std::env::var("MINING_THREADS")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get() as u32));
+        Miner::new(threads)
+    }
+
+    /// Signal that `Blockchain::blockchain_head` changed, cancelling the block being mined.
+    pub fn tip_changed(&self) {
+        self.tip.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Cancel the block being mined and refuse to mine any further block.
+    pub fn shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Tip counter to pass to `mine` and `is_cancelled`, read along with the chain head the
+    /// block being sealed builds on, while the blockchain is locked.
+    pub fn tip(&self) -> u64 {
+        self.tip.load(Ordering::SeqCst)
+    }
+
+    /// Whether the chain tip changed since `tip` was read or the node shut down, so the block
+    /// being sealed must be dropped.
+    pub fn is_cancelled(&self, tip: u64) -> bool {
+        self.is_shutdown() || self.tip() != tip
+    }
+
+    pub fn stats(&self) -> MiningStats {
+        MiningStats {
+            threads: self.threads,
+            hashrate: self.hashrate.load(Ordering::Relaxed),
+            hashes: self.hashes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Search for a nonce giving `header` a hash with `header.difficulty` leading zero bits.
+    ///
+    /// Thread `i` tries the nonces congruent to `i` modulo the number of threads. When a thread
+    /// exhausts its share of the 32-bit nonce space it rolls the timestamp forward and starts
+    /// over, so no two threads ever hash the same header. Returns the solved header and its
+    /// hash, or `None` if mining was cancelled: the tip moved past `tip` or the node shut down.
+    pub fn mine(&self, header: &BlockHeader, tip: u64) -> Option<(BlockHeader, String)> {
+        if self.is_cancelled(tip) {
+            return None;
+        }
+        let found = AtomicBool::new(false);
+        let solution: Mutex<Option<(BlockHeader, String)>> = Mutex::new(None);
+        let started = Instant::now();
+
+        let hashes: u64 = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads)
+                .map(|offset| {
+                    let mut header = header.clone();
+                    let (found, solution) = (&found, &solution);
+                    scope.spawn(move || {
+                        let cancelled = || {
+                            found.load(Ordering::Relaxed)
+                                || self.shutdown.load(Ordering::Relaxed)
+                                || self.tip.load(Ordering::Relaxed) != tip
+                        };
+                        self.search(&mut header, offset, cancelled, |header, hash| {
+                            if !found.swap(true, Ordering::SeqCst) {
+                                *solution.lock().expect("mining solution lock") = Some((header.clone(), hash));
+                            }
+                        })
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().expect("mining thread panicked")).sum()
+        });
+
+        let elapsed = started.elapsed().as_secs_f64();
+        self.hashes.fetch_add(hashes, Ordering::Relaxed);
+        if elapsed > 0.0 {
+            self.hashrate.store((hashes as f64 / elapsed) as u64, Ordering::Relaxed);
+        }
+
+        let solution = solution.into_inner().expect("mining solution lock");
+        match &solution {
+            Some((_, hash)) => println!(
+                "Block mined: {} ({} hashes in {:.2}s, {} H/s on {} threads)",
+                hash, hashes, elapsed, self.hashrate.load(Ordering::Relaxed), self.threads
+            ),
+            None => println!("Mining cancelled after {} hashes", hashes),
+        }
+        solution
+    }
+
+    /// Hash the share of the nonce space starting at `offset` until a solution is found or
+    /// `cancelled` returns true. Returns the number of hashes computed.
+    fn search(
+        &self,
+        header: &mut BlockHeader,
+        offset: u32,
+        cancelled: impl Fn() -> bool,
+        on_solution: impl FnOnce(&BlockHeader, String),
+    ) -> u64 {
+        // Only the nonce changes between attempts, patch it in place instead of re-encoding
+        header.nonce = offset;
+        let mut bytes = header.encode();
+        let mut hashes = 0u64;
+
+        loop {
+            for _ in 0..CANCEL_CHECK_INTERVAL {
+                let digest = Sha256::digest(bytes);
+                hashes += 1;
+                if leading_zero_bits_of(&digest) as i64 >= header.difficulty as i64 {
+                    on_solution(header, hex::encode(digest));
+                    return hashes;
+                }
+
+                match header.nonce.checked_add(self.threads) {
+                    Some(nonce) => {
+                        header.nonce = nonce;
+                        bytes[BlockHeader::NONCE_OFFSET..].copy_from_slice(&nonce.to_be_bytes());
+                    }
+                    None => {
+                        // Share of the nonce space exhausted, roll the timestamp to get fresh headers
+                        header.timestamp = now_millis().max(header.timestamp + 1);
+                        header.nonce = offset;
+                        bytes = header.encode();
+                    }
+                }
+            }
+
+            if cancelled() {
+                return hashes;
+            }
+        }
+    }
+}
+
+
+#[get("/mining")]
+async fn get_mining_stats(miner: &State<SharedMiner>) -> ApiResult<MiningStats> {
+    Ok(Json(miner.stats()))
+}
@@ -0,0 +1,591 @@
+use rocket::tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use rocket::tokio::net::{TcpListener, TcpStream};
+use rocket::tokio::sync::{mpsc, oneshot, Notify};
+use rocket::tokio::{self, task, time};
+use rocket::{debug, get, info, routes, serde::json::Json, warn, State};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::blockchain::{self, block_by_idx, Block, BlockHeader, SharedBlockchain};
+use crate::consensus::SharedConsensus;
+use crate::mempool::SharedMempool;
+use crate::store::{ChainStore, SharedStore};
+use crate::transactions::{self, Transaction};
+use crate::utils::{ApiError, ApiResult};
+
+
+pub const PROTOCOL_VERSION: u32 = 2; // 2 added the genesis hash to the handshake and header sync
+const MIN_PROTOCOL_VERSION: u32 = 2; // Oldest peer version still understood
+const MAGIC: [u8; 4] = *b"MRBC"; // First bytes of every message, rejects connections from other protocols
+const MAX_MESSAGE_SIZE: usize = 8 * 1024 * 1024; // bytes, comfortably above a full block in JSON
+const MAX_INV_ITEMS: usize = 1000; // Items in a single inv or getdata message
+const MAX_HEADERS: i64 = 2000; // Headers sent in reply to a single getheaders message
+const MAX_LOCATOR_HASHES: usize = 64;
+const DEFAULT_LISTEN: &str = "127.0.0.1:9000";
+const DEFAULT_MAX_PEERS: usize = 16;
+const OUTBOX_SIZE: usize = 256; // Messages queued for a peer before new ones are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90); // Silence after which a peer is dropped, pings included
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(10); // Between two attempts to reach the seed peers
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30); // For the reply to a getheaders message
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_peers]
+}
+
+/// Network shared between the blockchain, the transaction handlers and the peer connections.
+pub type SharedNetwork = Arc<Network>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvKind {
+    Tx,
+    Block,
+}
+
+/// Something a node has and its peers may want: a transaction by txid or a block by hash.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InvItem {
+    pub kind: InvKind,
+    pub hash: String,
+}
+
+impl InvItem {
+    pub fn tx(txid: &str) -> Self {
+        InvItem { kind: InvKind::Tx, hash: txid.to_string() }
+    }
+
+    pub fn block(hash: &str) -> Self {
+        InvItem { kind: InvKind::Block, hash: hash.to_string() }
+    }
+}
+
+/// First message sent on every connection, in both directions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Version {
+    pub version: u32,
+    /// Random per node, detects connections to ourselves and duplicate connections
+    pub nonce: u64,
+    /// Hash of the first block, peers on another chain are refused
+    pub genesis: String,
+    pub height: i32,
+    pub best_hash: String,
+}
+
+/// Block header with its hash, as sent during header sync.
+#[derive(Clone, Debug, FromRow, Serialize, Deserialize)]
+pub struct HeaderEntry {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub header: BlockHeader,
+    pub hash: String,
+}
+
+/// Peer to peer messages. On the wire each one is the magic bytes, the big endian u32 length of
+/// the payload and the payload, the message as JSON.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "lowercase")]
+pub enum Message {
+    Version(Version),
+    /// Acknowledges the peer's version, the handshake is done once both sides got one
+    Verack,
+    Ping(u64),
+    Pong(u64),
+    /// Announces items, the receiver asks for the ones it does not have with `GetData`
+    Inv(Vec<InvItem>),
+    GetData(Vec<InvItem>),
+    Block(Block),
+    Tx(Transaction),
+    /// Block locator: hashes from the sender's head back to its genesis, densest near the head.
+    /// The receiver replies with the headers following the first hash it has.
+    GetHeaders(Vec<String>),
+    Headers(Vec<HeaderEntry>),
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &Message) -> io::Result<()> {
+    let payload = serde_json::to_vec(message)?;
+    if payload.len() > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message is too large"));
+    }
+
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend_from_slice(&MAGIC);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame).await
+}
+
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Message> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header).await?;
+    if header[..4] != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "bad message magic"));
+    }
+    let len = u32::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes is too large", len)));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await?;
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+/// State the peer connections read and update.
+#[derive(Clone)]
+pub struct Node {
+    pub store: SharedStore,
+    pub consensus: SharedConsensus,
+    pub mempool: SharedMempool,
+    pub blockchain: SharedBlockchain,
+}
+
+#[derive(Clone, Serialize)]
+pub struct PeerInfo {
+    pub id: u64,
+    pub addr: String,
+    pub inbound: bool,
+    pub version: u32,
+    /// Height and best block hash the peer reported in its handshake
+    pub height: i32,
+    pub best_hash: String,
+}
+
+struct Peer {
+    info: PeerInfo,
+    nonce: u64,
+    outbox: mpsc::Sender<Message>,
+    // Notified to drop the connection
+    kick: Arc<Notify>,
+}
+
+/// TCP peer to peer layer: accepts connections on `P2P_LISTEN`, keeps connections to the
+/// `P2P_SEEDS` peers and gossips accepted transactions and blocks to every connected peer.
+pub struct Network {
+    listen: Option<String>,
+    seeds: Vec<String>,
+    max_peers: usize,
+    nonce: u64,
+    next_peer_id: AtomicU64,
+    peers: Mutex<HashMap<u64, Peer>>,
+    // Nonce of the node behind each seed address, once reached
+    seed_nonces: Mutex<HashMap<String, u64>>,
+    local_addr: Mutex<Option<SocketAddr>>,
+    // Pending getheaders request of each peer
+    header_requests: Mutex<HashMap<u64, oneshot::Sender<Vec<HeaderEntry>>>>,
+}
+
+impl Network {
+    pub fn new(listen: Option<String>, seeds: Vec<String>, max_peers: usize) -> Self {
+        Network {
+            listen,
+            seeds,
+            max_peers: max_peers.max(1),
+            nonce: rand::random(),
+            next_peer_id: AtomicU64::new(1),
+            peers: Mutex::new(HashMap::new()),
+            seed_nonces: Mutex::new(HashMap::new()),
+            local_addr: Mutex::new(None),
+            header_requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Network that neither listens nor connects, announcements go nowhere.
+    pub fn disabled() -> Self {
+        Network::new(None, Vec::new(), DEFAULT_MAX_PEERS)
+    }
+
+    /// Listen address from `P2P_LISTEN` (empty to not accept connections), seed peers from the
+    /// comma separated `P2P_SEEDS` and connection limit from `P2P_MAX_PEERS`.
+    pub fn from_env() -> Self {
+        let listen = std::env::var("P2P_LISTEN").unwrap_or_else(|_| DEFAULT_LISTEN.to_string());
+        let listen = Some(listen.trim().to_string()).filter(|listen| !listen.is_empty());
+        let seeds = std::env::var("P2P_SEEDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|seed| seed.trim().to_string())
+            .filter(|seed| !seed.is_empty())
+            .collect();
+        let max_peers = std::env::var("P2P_MAX_PEERS")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_MAX_PEERS);
+        Network::new(listen, seeds, max_peers)
+    }
+
+    /// Address connections are accepted on, once `start` bound it.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *self.local_addr.lock().expect("network lock")
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        let mut peers: Vec<PeerInfo> = self.lock().values().map(|peer| peer.info.clone()).collect();
+        peers.sort_by_key(|peer| peer.id);
+        peers
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Peer>> {
+        self.peers.lock().expect("network lock")
+    }
+
+    pub fn has_seeds(&self) -> bool {
+        !self.seeds.is_empty()
+    }
+
+    /// Announce `item` to every connected peer.
+    pub fn announce(&self, item: InvItem) {
+        let message = Message::Inv(vec![item]);
+        for peer in self.lock().values() {
+            if peer.outbox.try_send(message.clone()).is_err() {
+                warn!("peer {} is not keeping up, dropping an announcement", peer.info.addr);
+            }
+        }
+    }
+
+    /// Send `message` to the peer `id`.
+    pub async fn send_to(&self, id: u64, message: Message) -> io::Result<()> {
+        let outbox = self.lock().get(&id).map(|peer| peer.outbox.clone());
+        match outbox {
+            Some(outbox) => outbox.send(message).await.map_err(|_| disconnected()),
+            None => Err(disconnected()),
+        }
+    }
+
+    /// Ask the peer `id` for the headers following the first `locator` hash it has.
+    pub async fn request_headers(&self, id: u64, locator: Vec<String>) -> io::Result<Vec<HeaderEntry>> {
+        let (sender, receiver) = oneshot::channel();
+        self.header_requests.lock().expect("network lock").insert(id, sender);
+        self.send_to(id, Message::GetHeaders(locator)).await?;
+        time::timeout(REQUEST_TIMEOUT, receiver)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no reply to getheaders"))?
+            .map_err(|_| disconnected())
+    }
+
+    /// Record the height a peer is known to have reached, past its handshake.
+    pub fn set_peer_height(&self, id: u64, height: i32) {
+        if let Some(peer) = self.lock().get_mut(&id) {
+            peer.info.height = height;
+        }
+    }
+
+    /// Drop the connection to the peer `id`.
+    pub fn disconnect(&self, id: u64) {
+        if let Some(peer) = self.lock().get(&id) {
+            peer.kick.notify_one();
+        }
+    }
+
+    /// Bind the listen address and keep connections to the seed peers in the background.
+    pub async fn start(self: &Arc<Self>, node: Node) -> io::Result<()> {
+        if let Some(listen) = &self.listen {
+            let listener = TcpListener::bind(listen).await?;
+            let local_addr = listener.local_addr()?;
+            *self.local_addr.lock().expect("network lock") = Some(local_addr);
+            info!("accepting peer connections on {}", local_addr);
+            tokio::spawn(Arc::clone(self).accept_peers(listener, node.clone()));
+        }
+        if !self.seeds.is_empty() {
+            tokio::spawn(Arc::clone(self).connect_seeds(node));
+        }
+        Ok(())
+    }
+
+    async fn accept_peers(self: Arc<Self>, listener: TcpListener, node: Node) {
+        loop {
+            let (stream, addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("failed to accept peer connection: {}", e);
+                    time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            if self.lock().len() >= self.max_peers {
+                debug!("refusing peer {}: {} peers connected already", addr, self.max_peers);
+                continue;
+            }
+
+            let network = Arc::clone(&self);
+            let node = node.clone();
+            tokio::spawn(async move {
+                if let Err(e) = network.connect_stream(node, stream, addr, true).await {
+                    info!("peer {} rejected: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    async fn connect_seeds(self: Arc<Self>, node: Node) {
+        let mut interval = time::interval(RECONNECT_INTERVAL);
+        loop {
+            interval.tick().await;
+            for seed in &self.seeds {
+                let connected = self.seed_nonces.lock().expect("network lock").get(seed).is_some_and(|nonce| {
+                    self.lock().values().any(|peer| peer.nonce == *nonce)
+                });
+                if connected || self.lock().len() >= self.max_peers {
+                    continue;
+                }
+
+                if let Err(e) = self.connect(node.clone(), seed).await {
+                    info!("failed to connect to seed peer {}: {}", seed, e);
+                }
+            }
+        }
+    }
+
+    /// Connect to the peer at `addr` and exchange versions, serving the connection in the background.
+    pub async fn connect(self: &Arc<Self>, node: Node, addr: &str) -> io::Result<()> {
+        let stream = time::timeout(HANDSHAKE_TIMEOUT, TcpStream::connect(addr))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connection timed out"))??;
+        let peer_addr = stream.peer_addr()?;
+        let nonce = Arc::clone(self).connect_stream(node, stream, peer_addr, false).await?;
+        self.seed_nonces.lock().expect("network lock").insert(addr.to_string(), nonce);
+        Ok(())
+    }
+
+    /// Handshake on `stream`, then register the peer and serve it until it disconnects.
+    /// Returns the peer's nonce once it is registered.
+    async fn connect_stream(self: Arc<Self>, node: Node, stream: TcpStream, addr: SocketAddr, inbound: bool) -> io::Result<u64> {
+        let (mut reader, mut writer) = stream.into_split();
+        let version = time::timeout(HANDSHAKE_TIMEOUT, self.handshake(&node, &mut reader, &mut writer))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "handshake timed out"))??;
+
+        let (outbox, inbox) = mpsc::channel(OUTBOX_SIZE);
+        let id = self.next_peer_id.fetch_add(1, Ordering::Relaxed);
+        let kick = {
+            let mut peers = self.lock();
+            if peers.values().any(|peer| peer.nonce == version.nonce) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already connected to this node"));
+            }
+            let kick = Arc::new(Notify::new());
+            let info = PeerInfo {
+                id,
+                addr: addr.to_string(),
+                inbound,
+                version: version.version.min(PROTOCOL_VERSION),
+                height: version.height,
+                best_hash: version.best_hash,
+            };
+            peers.insert(id, Peer { info, nonce: version.nonce, outbox: outbox.clone(), kick: Arc::clone(&kick) });
+            kick
+        };
+        info!("connected to peer {} ({}) at height {}", addr, if inbound { "inbound" } else { "outbound" }, version.height);
+
+        let writer = tokio::spawn(write_messages(writer, inbox));
+        let pinger = tokio::spawn(ping(outbox.clone()));
+        tokio::spawn(async move {
+            let served = tokio::select! {
+                served = self.serve(&node, id, &mut reader, &outbox) => served,
+                _ = kick.notified() => Err(io::Error::new(io::ErrorKind::ConnectionAborted, "dropped by this node")),
+            };
+            if let Err(e) = served {
+                info!("disconnected from peer {}: {}", addr, e);
+            }
+            self.lock().remove(&id);
+            self.header_requests.lock().expect("network lock").remove(&id);
+            pinger.abort();
+            writer.abort();
+        });
+        Ok(version.nonce)
+    }
+
+    async fn handshake<R, W>(&self, node: &Node, reader: &mut R, writer: &mut W) -> io::Result<Version>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let head = node.store.head().await.map_err(io::Error::other)?;
+        let genesis = block_by_idx(node.store.as_ref(), 1).await.map_err(|e| io::Error::other(e.message()))?;
+        let ours = Version {
+            version: PROTOCOL_VERSION,
+            nonce: self.nonce,
+            genesis: genesis.hash.clone(),
+            height: head.as_ref().map_or(0, |block| block.header.idx),
+            best_hash: head.map(|block| block.hash).unwrap_or_default(),
+        };
+        write_message(writer, &Message::Version(ours)).await?;
+
+        let mut version = None;
+        let mut acknowledged = false;
+        while version.is_none() || !acknowledged {
+            match read_message(reader).await? {
+                Message::Version(theirs) if version.is_none() => {
+                    if theirs.nonce == self.nonce {
+                        return Err(io::Error::new(io::ErrorKind::AddrInUse, "connected to ourselves"));
+                    }
+                    if theirs.version < MIN_PROTOCOL_VERSION {
+                        return Err(io::Error::new(
+                            io::ErrorKind::Unsupported,
+                            format!("protocol version {} is older than {}", theirs.version, MIN_PROTOCOL_VERSION),
+                        ));
+                    }
+                    if theirs.genesis != genesis.hash {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("peer is on another chain, genesis {}", theirs.genesis),
+                        ));
+                    }
+                    write_message(writer, &Message::Verack).await?;
+                    version = Some(theirs);
+                }
+                Message::Verack => acknowledged = true,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected message during handshake")),
+            }
+        }
+        Ok(version.unwrap())
+    }
+
+    /// Handle the peer's messages until it disconnects, misbehaves or stays silent for too long.
+    async fn serve<R: AsyncRead + Unpin>(&self, node: &Node, id: u64, reader: &mut R, outbox: &mpsc::Sender<Message>) -> io::Result<()> {
+        loop {
+            let message = time::timeout(IDLE_TIMEOUT, read_message(reader))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "peer went silent"))??;
+
+            let reply = |message: Message| async move { outbox.send(message).await.map_err(|_| disconnected()) };
+            match message {
+                Message::Version(_) | Message::Verack => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "handshake message after the handshake"));
+                }
+                Message::Ping(nonce) => reply(Message::Pong(nonce)).await?,
+                Message::Pong(_) => {}
+                Message::Inv(items) | Message::GetData(items) if items.len() > MAX_INV_ITEMS => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} items in one message", items.len())));
+                }
+                Message::Inv(items) => {
+                    let mut wanted = Vec::new();
+                    for item in items {
+                        if !self.has(node, &item).await.map_err(io::Error::other)? {
+                            wanted.push(item);
+                        }
+                    }
+                    if !wanted.is_empty() {
+                        reply(Message::GetData(wanted)).await?;
+                    }
+                }
+                Message::GetData(items) => {
+                    for item in items {
+                        if let Some(message) = self.find(node, &item).await.map_err(io::Error::other)? {
+                            reply(message).await?;
+                        }
+                    }
+                }
+                Message::Tx(transaction) => {
+                    let txid = transaction.txid();
+                    match transactions::submit_transaction(node.store.as_ref(), &node.mempool, self, transaction).await {
+                        Ok(_) => debug!("accepted transaction {} from a peer", txid),
+                        Err(ApiError::Internal(e)) => warn!("failed to accept transaction {} from a peer: {}", txid, e),
+                        Err(e) => debug!("rejected transaction {} from a peer: {}", txid, e.message()),
+                    }
+                }
+                Message::GetHeaders(locator) if locator.len() > MAX_LOCATOR_HASHES => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} hashes in a block locator", locator.len())));
+                }
+                Message::GetHeaders(locator) => {
+                    let headers = headers_after(node.store.as_ref(), &locator).await.map_err(|e| io::Error::other(e.message()))?;
+                    reply(Message::Headers(headers)).await?;
+                }
+                Message::Headers(headers) => {
+                    let request = self.header_requests.lock().expect("network lock").remove(&id);
+                    match request {
+                        Some(request) => {
+                            let _ = request.send(headers);
+                        }
+                        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "headers that were not asked for")),
+                    }
+                }
+                Message::Block(block) => {
+                    let hash = block.hash.clone();
+                    let idx = block.header.idx;
+                    let blockchain = Arc::clone(&node.blockchain);
+                    let imported = task::spawn_blocking(move || blockchain::import_block(&blockchain, block))
+                        .await
+                        .map_err(io::Error::other)?;
+                    match imported {
+                        Ok(_) => {
+                            info!("imported block {} at idx {} from a peer", hash, idx);
+                            // Only a valid block tells how far the peer's chain goes
+                            if self.lock().get(&id).is_some_and(|peer| peer.info.height < idx) {
+                                self.set_peer_height(id, idx);
+                            }
+                        }
+                        Err(ApiError::Internal(e)) => warn!("failed to import block {} from a peer: {}", hash, e),
+                        Err(e) => info!("rejected block {} from a peer: {}", hash, e.message()),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the item is already known: pending, queued or stored, on a side branch included.
+    async fn has(&self, node: &Node, item: &InvItem) -> Result<bool, ApiError> {
+        match item.kind {
+            InvKind::Tx => {
+                if node.mempool.contains(&item.hash) || node.mempool.queued(&item.hash).is_some() {
+                    return Ok(true);
+                }
+                Ok(node.store.transaction(&item.hash).await?.is_some())
+            }
+            InvKind::Block => Ok(blockchain::find_block(node.store.as_ref(), &item.hash).await?.is_some()),
+        }
+    }
+
+    /// The message carrying the item, `None` if it is not pending nor stored.
+    async fn find(&self, node: &Node, item: &InvItem) -> Result<Option<Message>, ApiError> {
+        match item.kind {
+            InvKind::Tx => Ok(node.mempool.get(&item.hash).map(|entry| Message::Tx(entry.transaction))),
+            InvKind::Block => Ok(blockchain::find_block(node.store.as_ref(), &item.hash).await?.map(Message::Block)),
+        }
+    }
+}
+
+/// Headers of the main chain blocks following the first `locator` hash on it, none if the
+/// locator shares no block with it.
+async fn headers_after(store: &dyn ChainStore, locator: &[String]) -> Result<Vec<HeaderEntry>, ApiError> {
+    for hash in locator {
+        let Some(block) = store.block_by_hash(hash).await? else {
+            continue;
+        };
+        return store.headers_after(block.header.idx, MAX_HEADERS).await;
+    }
+    Ok(Vec::new())
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")
+}
+
+async fn write_messages(mut writer: impl AsyncWrite + Unpin, mut inbox: mpsc::Receiver<Message>) {
+    while let Some(message) = inbox.recv().await {
+        if write_message(&mut writer, &message).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Ping the peer until the connection closes, its pongs keep the idle timeout from firing.
+async fn ping(outbox: mpsc::Sender<Message>) {
+    let mut interval = time::interval(PING_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if outbox.send(Message::Ping(rand::random())).await.is_err() {
+            break;
+        }
+    }
+}
+
+
+#[get("/peers")]
+async fn get_peers(network: &State<SharedNetwork>) -> ApiResult<Vec<PeerInfo>> {
+    Ok(Json(network.peers()))
+}
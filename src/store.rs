@@ -0,0 +1,138 @@
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use sqlx::FromRow;
+use std::sync::Arc;
+use crate::blockchain::{Block, BlockHeader};
+use crate::p2p::HeaderEntry;
+use crate::transactions::{Transaction, Wallet};
+use crate::utils::ApiError;
+
+pub mod memory;
+pub mod postgres;
+mod sql;
+pub mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+
+const DEFAULT_MAX_CONNECTIONS: u32 = 5;
+
+/// Chain store shared between the blockchain, the mempool, the peer connections and the request handlers.
+pub type SharedStore = Arc<dyn ChainStore>;
+
+/// A main chain block header with the totals the streaming verification checks the coinbase against.
+#[derive(FromRow)]
+pub struct BlockSummary {
+    #[sqlx(flatten)]
+    pub header: BlockHeader,
+    pub hash: String,
+    pub coinbase_count: i64,
+    pub coinbase_amount: i64,
+    /// Fees paid by the block's other transactions
+    pub fees: i64,
+}
+
+/// Persistent state of the node: the main chain, the side branches, the transactions (pending or
+/// confirmed) and the wallets.
+///
+/// Every method leaves the store consistent on its own. The ones changing several records at
+/// once, accepting a transaction or connecting a block, apply all of their changes or none.
+/// Pending transactions are identified by a rowid increasing in the order they were accepted.
+#[async_trait]
+pub trait ChainStore: Send + Sync {
+    /// Number of blocks of the main chain.
+    async fn height(&self) -> Result<i32, ApiError>;
+
+    /// Block with the highest index, `None` if the chain is empty.
+    async fn head(&self) -> Result<Option<Block>, ApiError>;
+
+    async fn block_by_idx(&self, idx: i32) -> Result<Option<Block>, ApiError>;
+
+    /// Block of the main chain with `hash`.
+    async fn block_by_hash(&self, hash: &str) -> Result<Option<Block>, ApiError>;
+
+    /// Main chain blocks after `idx`, in order, at most `limit` of them.
+    async fn blocks_after(&self, idx: i32, limit: i64) -> Result<Vec<Block>, ApiError>;
+
+    /// Headers of the main chain blocks after `idx`, in order, at most `limit` of them.
+    async fn headers_after(&self, idx: i32, limit: i64) -> Result<Vec<HeaderEntry>, ApiError>;
+
+    /// Headers of the main chain with their coinbase totals, in order, read as they are consumed.
+    fn block_summaries(&self) -> BoxStream<'_, Result<BlockSummary, ApiError>>;
+
+    /// Block of a side branch with `hash`.
+    async fn side_block(&self, hash: &str) -> Result<Option<Block>, ApiError>;
+
+    async fn put_side_block(&self, block: &Block) -> Result<(), ApiError>;
+
+    async fn delete_side_block(&self, hash: &str) -> Result<(), ApiError>;
+
+    /// Append `block` to the main chain, taking it off the side branches, and return it as
+    /// stored. Its coinbase is recorded and credited, creating the recipient's wallet with
+    /// `coinbase_pub_key` if needed, and its other transactions are linked to it. `Conflict`,
+    /// changing nothing, if one of them is no longer pending or the coinbase overflows the balance.
+    async fn connect_block(&self, block: &Block, coinbase_pub_key: &str) -> Result<Block, ApiError>;
+
+    /// Move the head `block` of the main chain to the side branches, deleting its transactions
+    /// and reverting them latest first: the recipients are debited, the senders credited back
+    /// and given their nonce again. No transaction may be pending.
+    async fn disconnect_block(&self, block: &Block) -> Result<(), ApiError>;
+
+    /// Transaction with `txid`, pending or confirmed.
+    async fn transaction(&self, txid: &str) -> Result<Option<Transaction>, ApiError>;
+
+    /// Transactions of the block at `idx`, in the order of `transactions_in_blocks`.
+    async fn block_transactions(&self, idx: i32) -> Result<Vec<Transaction>, ApiError>;
+
+    /// Transactions of the blocks `first` to `last`, by block, the coinbase first and then the
+    /// others in the order they were accepted: the order the Merkle tree was built in.
+    async fn transactions_in_blocks(&self, first: i32, last: i32) -> Result<Vec<Transaction>, ApiError>;
+
+    /// Transactions without a block and their rowids, in the order they were accepted.
+    async fn pending_transactions(&self) -> Result<Vec<(i64, Transaction)>, ApiError>;
+
+    async fn pending_count(&self) -> Result<i64, ApiError>;
+
+    /// Debit the sender by the amount plus the fee and use its nonce, credit the recipient and
+    /// store the transaction as pending. Returns its rowid and the transaction as stored.
+    /// `Conflict` if the nonce is not the sender's next one, the balance is too low or the
+    /// transaction exists already or the credit overflows the recipient's balance, `NotFound` if
+    /// the recipient has no wallet.
+    async fn accept_transaction(&self, transaction: &Transaction) -> Result<(i64, Transaction), ApiError>;
+
+    /// Delete the pending transaction at `rowid` and undo its debit, credit and nonce. Returns
+    /// false, changing nothing, if it was included in a block, its recipient spent the funds or
+    /// its sender used a later nonce.
+    async fn revert_pending(&self, rowid: i64, transaction: &Transaction) -> Result<bool, ApiError>;
+
+    async fn wallet(&self, address: &str) -> Result<Option<Wallet>, ApiError>;
+
+    async fn wallets(&self) -> Result<Vec<Wallet>, ApiError>;
+
+    /// Store `wallet`, replacing the wallet with the same address if there is one.
+    async fn put_wallet(&self, wallet: &Wallet) -> Result<(), ApiError>;
+
+    /// Check the storage itself for corruption, below the chain rules.
+    async fn check_integrity(&self) -> Result<(), String>;
+}
+
+/// Store for `DATABASE_URL`, with its migrations applied: a `PostgresStore` for `postgres://` and
+/// `postgresql://` URLs, a `SqliteStore` otherwise. `DATABASE_MAX_CONNECTIONS` bounds the
+/// connections of the whole node: the store is created once at launch and shared by every
+/// handler and the miner.
+pub async fn connect() -> Result<SharedStore, String> {
+    let database_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://database.sqlite".to_string());
+    let max_connections = std::env::var("DATABASE_MAX_CONNECTIONS")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        Ok(Arc::new(PostgresStore::connect(&database_url, max_connections).await?))
+    } else {
+        Ok(Arc::new(SqliteStore::connect(&database_url, max_connections).await?))
+    }
+}
@@ -0,0 +1,396 @@
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, MutexGuard};
+use crate::blockchain::{now_millis, Block};
+use crate::p2p::HeaderEntry;
+use crate::store::{BlockSummary, ChainStore};
+use crate::transactions::{Transaction, Wallet};
+use crate::utils::ApiError;
+
+
+/// Store keeping everything in memory, lost when it is dropped. Lets the chain logic be tested
+/// without a database file.
+#[derive(Default)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    // Main chain, the block at idx i is at i - 1
+    blocks: Vec<Block>,
+    side_blocks: HashMap<String, Block>,
+    transactions: BTreeMap<i64, Transaction>,
+    // Rowid of every transaction by txid
+    txids: HashMap<String, i64>,
+    wallets: BTreeMap<String, Wallet>,
+    next_rowid: i64,
+}
+
+/// Copies of the wallets an operation changes, written back only once all of its changes were applied.
+struct StagedWallets<'a> {
+    wallets: &'a BTreeMap<String, Wallet>,
+    changed: HashMap<String, Wallet>,
+}
+
+impl<'a> StagedWallets<'a> {
+    fn new(wallets: &'a BTreeMap<String, Wallet>) -> Self {
+        StagedWallets { wallets, changed: HashMap::new() }
+    }
+
+    fn get_mut(&mut self, address: &str) -> Option<&mut Wallet> {
+        if !self.changed.contains_key(address) {
+            let wallet = self.wallets.get(address)?.clone();
+            self.changed.insert(address.to_string(), wallet);
+        }
+        self.changed.get_mut(address)
+    }
+
+    fn insert(&mut self, wallet: Wallet) {
+        self.changed.insert(wallet.address.clone(), wallet);
+    }
+
+    fn into_changes(self) -> HashMap<String, Wallet> {
+        self.changed
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryState> {
+        self.state.lock().expect("memory store lock")
+    }
+}
+
+impl MemoryState {
+    fn insert_transaction(&mut self, transaction: Transaction) -> i64 {
+        self.next_rowid += 1;
+        self.txids.insert(transaction.txid(), self.next_rowid);
+        self.transactions.insert(self.next_rowid, transaction);
+        self.next_rowid
+    }
+
+    fn delete_transaction(&mut self, rowid: i64) {
+        if let Some(transaction) = self.transactions.remove(&rowid) {
+            self.txids.remove(&transaction.txid());
+        }
+    }
+}
+
+/// `balance` credited back with the amount and the fee `transaction` cost its sender, `None` if
+/// that overflows.
+fn refunded(balance: i32, transaction: &Transaction) -> Option<i32> {
+    balance.checked_add(transaction.amount)?.checked_add(transaction.fee)
+}
+
+#[async_trait]
+impl ChainStore for MemoryStore {
+    async fn height(&self) -> Result<i32, ApiError> {
+        Ok(self.lock().blocks.len() as i32)
+    }
+
+    async fn head(&self) -> Result<Option<Block>, ApiError> {
+        Ok(self.lock().blocks.last().cloned())
+    }
+
+    async fn block_by_idx(&self, idx: i32) -> Result<Option<Block>, ApiError> {
+        let state = self.lock();
+        Ok(usize::try_from(idx - 1).ok().and_then(|i| state.blocks.get(i)).cloned())
+    }
+
+    async fn block_by_hash(&self, hash: &str) -> Result<Option<Block>, ApiError> {
+        Ok(self.lock().blocks.iter().find(|block| block.hash == hash).cloned())
+    }
+
+    async fn blocks_after(&self, idx: i32, limit: i64) -> Result<Vec<Block>, ApiError> {
+        let state = self.lock();
+        Ok(state.blocks.iter().skip(idx.max(0) as usize).take(limit.max(0) as usize).cloned().collect())
+    }
+
+    async fn headers_after(&self, idx: i32, limit: i64) -> Result<Vec<HeaderEntry>, ApiError> {
+        let blocks = self.blocks_after(idx, limit).await?;
+        Ok(blocks.into_iter().map(|block| HeaderEntry { header: block.header, hash: block.hash }).collect())
+    }
+
+    fn block_summaries(&self) -> BoxStream<'_, Result<BlockSummary, ApiError>> {
+        let state = self.lock();
+        let summaries: Vec<Result<BlockSummary, ApiError>> = state
+            .blocks
+            .iter()
+            .map(|block| {
+                let linked = state.transactions.values().filter(|tx| tx.block_id == Some(block.header.idx));
+                let (mut coinbase_count, mut coinbase_amount, mut fees) = (0, 0, 0);
+                for tx in linked {
+                    if tx.is_coinbase() {
+                        coinbase_count += 1;
+                        coinbase_amount += tx.amount as i64;
+                    } else {
+                        fees += tx.fee as i64;
+                    }
+                }
+                Ok(BlockSummary { header: block.header.clone(), hash: block.hash.clone(), coinbase_count, coinbase_amount, fees })
+            })
+            .collect();
+        stream::iter(summaries).boxed()
+    }
+
+    async fn side_block(&self, hash: &str) -> Result<Option<Block>, ApiError> {
+        Ok(self.lock().side_blocks.get(hash).cloned())
+    }
+
+    async fn put_side_block(&self, block: &Block) -> Result<(), ApiError> {
+        let mut state = self.lock();
+        if state.side_blocks.contains_key(&block.hash) {
+            return Err(ApiError::Internal(format!("side block {} is already stored", block.hash)));
+        }
+        state.side_blocks.insert(block.hash.clone(), block.clone());
+        Ok(())
+    }
+
+    async fn delete_side_block(&self, hash: &str) -> Result<(), ApiError> {
+        self.lock().side_blocks.remove(hash);
+        Ok(())
+    }
+
+    async fn connect_block(&self, block: &Block, coinbase_pub_key: &str) -> Result<Block, ApiError> {
+        let mut state = self.lock();
+        if block.header.idx as usize != state.blocks.len() + 1 {
+            return Err(ApiError::Internal(format!("block at idx {} does not follow the head", block.header.idx)));
+        }
+
+        // Everything that can fail is checked before the state changes
+        let mut linked = Vec::new();
+        for txid in block.body.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.txid()) {
+            match state.txids.get(&txid) {
+                Some(rowid) if state.transactions[rowid].block_id.is_none() => linked.push(*rowid),
+                _ => return Err(ApiError::Conflict(format!("transaction {} is no longer pending", txid))),
+            }
+        }
+        let coinbases: Vec<&Transaction> = block.body.iter().filter(|tx| tx.is_coinbase()).collect();
+        if coinbases.iter().any(|coinbase| state.txids.contains_key(&coinbase.txid())) {
+            return Err(ApiError::Internal(format!("coinbase of block {} is already stored", block.hash)));
+        }
+
+        let mut wallets = StagedWallets::new(&state.wallets);
+        for coinbase in &coinbases {
+            match wallets.get_mut(&coinbase.to_address) {
+                Some(wallet) => {
+                    wallet.balance = wallet.balance.checked_add(coinbase.amount).ok_or_else(|| {
+                        ApiError::Conflict(format!(
+                            "coinbase of {} overflows the balance of {}",
+                            coinbase.amount, coinbase.to_address
+                        ))
+                    })?
+                }
+                None => wallets.insert(Wallet {
+                    address: coinbase.to_address.clone(),
+                    balance: coinbase.amount,
+                    pub_key: coinbase_pub_key.to_string(),
+                    initial_balance: 0,
+                    nonce: 0,
+                }),
+            }
+        }
+        let changes = wallets.into_changes();
+        state.wallets.extend(changes);
+
+        for coinbase in coinbases {
+            let mut coinbase = coinbase.clone();
+            coinbase.added_to_block = Some(true);
+            coinbase.block_id = Some(block.header.idx);
+            state.insert_transaction(coinbase);
+        }
+        for rowid in linked {
+            if let Some(transaction) = state.transactions.get_mut(&rowid) {
+                transaction.block_id = Some(block.header.idx);
+            }
+        }
+        state.side_blocks.remove(&block.hash);
+        state.blocks.push(block.clone());
+        Ok(block.clone())
+    }
+
+    async fn disconnect_block(&self, block: &Block) -> Result<(), ApiError> {
+        let mut state = self.lock();
+        if state.blocks.last().is_none_or(|head| head.hash != block.hash) {
+            return Err(ApiError::Internal(format!("block {} is not the head", block.hash)));
+        }
+
+        let linked: Vec<(i64, Transaction)> = state
+            .transactions
+            .iter()
+            .rev()
+            .filter(|(_, tx)| tx.block_id == Some(block.header.idx))
+            .map(|(rowid, tx)| (*rowid, tx.clone()))
+            .collect();
+
+        let mut wallets = StagedWallets::new(&state.wallets);
+        for (_, transaction) in &linked {
+            match wallets.get_mut(&transaction.to_address) {
+                Some(to) if to.balance >= transaction.amount => to.balance -= transaction.amount,
+                _ => {
+                    return Err(ApiError::Internal(format!(
+                        "{} cannot pay back the {} of transaction {}, it cannot be reverted",
+                        transaction.to_address, transaction.amount, transaction.txid()
+                    )));
+                }
+            }
+            if transaction.is_coinbase() {
+                continue;
+            }
+            match wallets.get_mut(&transaction.from_address) {
+                Some(from) if from.nonce == transaction.nonce + 1 => {
+                    from.balance = refunded(from.balance, transaction).ok_or_else(|| {
+                        ApiError::Internal(format!(
+                            "refunding transaction {} overflows the balance of {}",
+                            transaction.txid(), transaction.from_address
+                        ))
+                    })?;
+                    from.nonce -= 1;
+                }
+                _ => {
+                    return Err(ApiError::Internal(format!(
+                        "transaction {} is not the latest of {}, it cannot be reverted",
+                        transaction.txid(), transaction.from_address
+                    )));
+                }
+            }
+        }
+        let changes = wallets.into_changes();
+        state.wallets.extend(changes);
+
+        for (rowid, _) in linked {
+            state.delete_transaction(rowid);
+        }
+        state.blocks.pop();
+        state.side_blocks.insert(block.hash.clone(), block.clone());
+        Ok(())
+    }
+
+    async fn transaction(&self, txid: &str) -> Result<Option<Transaction>, ApiError> {
+        let state = self.lock();
+        Ok(state.txids.get(txid).map(|rowid| state.transactions[rowid].clone()))
+    }
+
+    async fn block_transactions(&self, idx: i32) -> Result<Vec<Transaction>, ApiError> {
+        let mut transactions: Vec<Transaction> = self.lock().transactions.values().filter(|tx| tx.block_id == Some(idx)).cloned().collect();
+        transactions.sort_by_key(|tx| !tx.is_coinbase());
+        Ok(transactions)
+    }
+
+    async fn transactions_in_blocks(&self, first: i32, last: i32) -> Result<Vec<Transaction>, ApiError> {
+        let mut transactions: Vec<Transaction> = self
+            .lock()
+            .transactions
+            .values()
+            .filter(|tx| tx.block_id.is_some_and(|idx| (first..=last).contains(&idx)))
+            .cloned()
+            .collect();
+        // Stable, so each block's transactions stay in rowid order
+        transactions.sort_by_key(|tx| (tx.block_id, !tx.is_coinbase()));
+        Ok(transactions)
+    }
+
+    async fn pending_transactions(&self) -> Result<Vec<(i64, Transaction)>, ApiError> {
+        let state = self.lock();
+        Ok(state.transactions.iter().filter(|(_, tx)| tx.block_id.is_none()).map(|(rowid, tx)| (*rowid, tx.clone())).collect())
+    }
+
+    async fn pending_count(&self) -> Result<i64, ApiError> {
+        Ok(self.lock().transactions.values().filter(|tx| tx.block_id.is_none()).count() as i64)
+    }
+
+    async fn accept_transaction(&self, transaction: &Transaction) -> Result<(i64, Transaction), ApiError> {
+        let mut state = self.lock();
+        let spent = transaction.amount as i64 + transaction.fee as i64;
+
+        let mut wallets = StagedWallets::new(&state.wallets);
+        match wallets.get_mut(&transaction.from_address) {
+            Some(from) if from.nonce == transaction.nonce && from.balance as i64 >= spent => {
+                from.balance -= spent as i32;
+                from.nonce += 1;
+            }
+            Some(from) if from.nonce == transaction.nonce => {
+                return Err(ApiError::Conflict("insufficient balance in from wallet".to_string()));
+            }
+            from => {
+                return Err(ApiError::Conflict(format!(
+                    "nonce {} is not the next nonce of {}, expected {}",
+                    transaction.nonce, transaction.from_address, from.map_or(0, |from| from.nonce)
+                )));
+            }
+        }
+        match wallets.get_mut(&transaction.to_address) {
+            Some(to) => {
+                to.balance = to.balance.checked_add(transaction.amount).ok_or_else(|| {
+                    ApiError::Conflict(format!("crediting {} overflows the balance of the to wallet", transaction.amount))
+                })?
+            }
+            None => return Err(ApiError::NotFound("to wallet not found".to_string())),
+        }
+        let txid = transaction.txid();
+        if state.txids.contains_key(&txid) {
+            return Err(ApiError::Conflict(format!("transaction {} already exists", txid)));
+        }
+        let changes = wallets.into_changes();
+        state.wallets.extend(changes);
+
+        let stored = Transaction {
+            added_to_block: Some(false),
+            created_at: Some(now_millis() as f64 / 1000.0),
+            block_id: None,
+            ..transaction.clone()
+        };
+        let rowid = state.insert_transaction(stored.clone());
+        Ok((rowid, stored))
+    }
+
+    async fn revert_pending(&self, rowid: i64, transaction: &Transaction) -> Result<bool, ApiError> {
+        let mut state = self.lock();
+        if state.transactions.get(&rowid).is_none_or(|tx| tx.block_id.is_some()) {
+            return Ok(false);
+        }
+
+        let mut wallets = StagedWallets::new(&state.wallets);
+        match wallets.get_mut(&transaction.to_address) {
+            Some(to) if to.balance >= transaction.amount => to.balance -= transaction.amount,
+            _ => return Ok(false),
+        }
+        match wallets.get_mut(&transaction.from_address) {
+            Some(from) if from.nonce == transaction.nonce + 1 => match refunded(from.balance, transaction) {
+                Some(balance) => {
+                    from.balance = balance;
+                    from.nonce -= 1;
+                }
+                None => return Ok(false),
+            },
+            _ => return Ok(false),
+        }
+        let changes = wallets.into_changes();
+        state.wallets.extend(changes);
+
+        state.delete_transaction(rowid);
+        Ok(true)
+    }
+
+    async fn wallet(&self, address: &str) -> Result<Option<Wallet>, ApiError> {
+        Ok(self.lock().wallets.get(address).cloned())
+    }
+
+    async fn wallets(&self) -> Result<Vec<Wallet>, ApiError> {
+        Ok(self.lock().wallets.values().cloned().collect())
+    }
+
+    async fn put_wallet(&self, wallet: &Wallet) -> Result<(), ApiError> {
+        self.lock().wallets.insert(wallet.address.clone(), wallet.clone());
+        Ok(())
+    }
+
+    async fn check_integrity(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,592 @@
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{FromRow, PgConnection, PgPool, Postgres, Row};
+use crate::blockchain::Block;
+use crate::p2p::HeaderEntry;
+use crate::store::sql::{self, SqlBackend};
+use crate::store::{BlockSummary, ChainStore};
+use crate::transactions::{Transaction, Wallet};
+use crate::utils::ApiError;
+
+
+/// Store in a Postgres database, migrated with the files in `migrations_postgres/`, the
+/// counterparts of the SQLite ones. Transactions carry an explicit `rowid` column numbering them
+/// in insertion order like the implicit one of SQLite, and `u32` header fields are stored as BIGINT.
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+#[derive(FromRow)]
+struct PendingRow {
+    rowid: i64,
+    #[sqlx(flatten)]
+    transaction: Transaction,
+}
+
+impl PostgresStore {
+    /// Connect to the database at `database_url` and bring it up to date: apply the migrations
+    /// and store the txid of the rows inserted before transactions had one.
+    pub async fn connect(database_url: &str, max_connections: u32) -> Result<Self, String> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(database_url)
+            .await
+            .map_err(|e| format!("failed to connect to Postgres at {}: {}", database_url, e))?;
+        sqlx::migrate!("./migrations_postgres").run(&pool).await.map_err(|e| format!("migrations failed: {}", e))?;
+
+        let store = PostgresStore { pool };
+        sql::backfill_txids(&store).await.map_err(|e| format!("failed to backfill transaction ids: {}", e))?;
+        Ok(store)
+    }
+
+    /// The underlying pool, for tools and tests that reach below the store.
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl ChainStore for PostgresStore {
+    async fn height(&self) -> Result<i32, ApiError> {
+        let count = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*) FROM blocks;
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get chain size", e))?;
+        Ok(count as i32)
+    }
+
+    async fn head(&self) -> Result<Option<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM blocks
+            ORDER BY idx DESC
+            LIMIT 1;
+            "#
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block", e))
+    }
+
+    async fn block_by_idx(&self, idx: i32) -> Result<Option<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM blocks
+            WHERE idx = $1;
+            "#
+        )
+        .bind(idx)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block", e))
+    }
+
+    async fn block_by_hash(&self, hash: &str) -> Result<Option<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM blocks
+            WHERE hash = $1;
+            "#
+        )
+        .bind(hash)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block", e))
+    }
+
+    async fn blocks_after(&self, idx: i32, limit: i64) -> Result<Vec<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM blocks
+            WHERE idx > $1
+            ORDER BY idx ASC
+            LIMIT $2;
+            "#,
+        )
+        .bind(idx)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get blocks", e))
+    }
+
+    async fn headers_after(&self, idx: i32, limit: i64) -> Result<Vec<HeaderEntry>, ApiError> {
+        sqlx::query_as::<_, HeaderEntry>(
+            r#"
+            SELECT version, idx, previous_hash, merkle_root, timestamp, difficulty, nonce, signature, hash
+            FROM blocks
+            WHERE idx > $1
+            ORDER BY idx ASC
+            LIMIT $2;
+            "#,
+        )
+        .bind(idx)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to read block headers", e))
+    }
+
+    fn block_summaries(&self) -> BoxStream<'_, Result<BlockSummary, ApiError>> {
+        // Rows are read one at a time, the chain is never loaded in memory at once. The query
+        // macros are checked against SQLite, so this one is built at runtime.
+        sqlx::query_as::<_, BlockSummary>(
+            r#"
+            SELECT
+                version, idx, timestamp, hash, previous_hash, merkle_root, difficulty, nonce, signature,
+                (SELECT COUNT(*) FROM transactions t
+                    WHERE t.block_id = blocks.idx AND t.from_address = '') AS coinbase_count,
+                (SELECT COALESCE(SUM(t.amount), 0) FROM transactions t
+                    WHERE t.block_id = blocks.idx AND t.from_address = '') AS coinbase_amount,
+                (SELECT COALESCE(SUM(t.fee), 0) FROM transactions t
+                    WHERE t.block_id = blocks.idx AND t.from_address != '') AS fees
+            FROM blocks
+            ORDER BY idx ASC
+            "#
+        )
+        .fetch(&self.pool)
+        .map(|row| row.map_err(|e| ApiError::internal("failed to read blocks", e)))
+        .boxed()
+    }
+
+    async fn side_block(&self, hash: &str) -> Result<Option<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM side_blocks
+            WHERE hash = $1;
+            "#
+        )
+        .bind(hash)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get side block", e))
+    }
+
+    async fn put_side_block(&self, block: &Block) -> Result<(), ApiError> {
+        let mut conn = self.pool.acquire().await.map_err(|e| ApiError::internal("failed to get connection", e))?;
+        Self::insert_side_block(&mut conn, block).await.map_err(|e| ApiError::internal("failed to insert side block", e))
+    }
+
+    async fn delete_side_block(&self, hash: &str) -> Result<(), ApiError> {
+        let mut conn = self.pool.acquire().await.map_err(|e| ApiError::internal("failed to get connection", e))?;
+        <Self as SqlBackend>::delete_side_block(&mut conn, hash)
+            .await
+            .map_err(|e| ApiError::internal("failed to delete side block", e))?;
+        Ok(())
+    }
+
+    async fn connect_block(&self, block: &Block, coinbase_pub_key: &str) -> Result<Block, ApiError> {
+        sql::connect_block(self, block, coinbase_pub_key).await
+    }
+
+    async fn disconnect_block(&self, block: &Block) -> Result<(), ApiError> {
+        sql::disconnect_block(self, block).await
+    }
+
+    async fn transaction(&self, txid: &str) -> Result<Option<Transaction>, ApiError> {
+        sqlx::query_as::<_, Transaction>(
+            r#"
+            SELECT *
+            FROM transactions
+            WHERE txid = $1;
+            "#,
+        )
+        .bind(txid)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get transaction", e))
+    }
+
+    async fn block_transactions(&self, idx: i32) -> Result<Vec<Transaction>, ApiError> {
+        sqlx::query_as::<_, Transaction>(
+            r#"
+            SELECT *
+            FROM transactions
+            WHERE block_id = $1
+            ORDER BY (from_address = '') DESC, rowid ASC;
+            "#,
+        )
+        .bind(idx)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block transactions", e))
+    }
+
+    async fn transactions_in_blocks(&self, first: i32, last: i32) -> Result<Vec<Transaction>, ApiError> {
+        sqlx::query_as::<_, Transaction>(
+            r#"
+            SELECT * FROM transactions
+            WHERE block_id BETWEEN $1 AND $2
+            ORDER BY block_id ASC, (from_address = '') DESC, rowid ASC;
+            "#,
+        )
+        .bind(first)
+        .bind(last)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block transactions", e))
+    }
+
+    async fn pending_transactions(&self) -> Result<Vec<(i64, Transaction)>, ApiError> {
+        let rows = sqlx::query_as::<_, PendingRow>(
+            r#"
+            SELECT *
+            FROM transactions
+            WHERE block_id IS NULL
+            ORDER BY rowid ASC;
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get pending transactions", e))?;
+        Ok(rows.into_iter().map(|row| (row.rowid, row.transaction)).collect())
+    }
+
+    async fn pending_count(&self) -> Result<i64, ApiError> {
+        sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*)
+            FROM transactions
+            WHERE block_id IS NULL;
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to count pending transactions", e))
+    }
+
+    async fn accept_transaction(&self, transaction: &Transaction) -> Result<(i64, Transaction), ApiError> {
+        sql::accept_transaction(self, transaction).await
+    }
+
+    async fn revert_pending(&self, rowid: i64, transaction: &Transaction) -> Result<bool, ApiError> {
+        sql::revert_pending(self, rowid, transaction).await
+    }
+
+    async fn wallet(&self, address: &str) -> Result<Option<Wallet>, ApiError> {
+        sqlx::query_as::<_, Wallet>(
+            r#"
+            SELECT *
+            FROM wallets
+            WHERE address = $1;
+            "#,
+        )
+        .bind(address)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get wallet", e))
+    }
+
+    async fn wallets(&self) -> Result<Vec<Wallet>, ApiError> {
+        sqlx::query_as::<_, Wallet>("SELECT * FROM wallets;")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| ApiError::internal("failed to get wallets", e))
+    }
+
+    async fn put_wallet(&self, wallet: &Wallet) -> Result<(), ApiError> {
+        sql::put_wallet(self, wallet).await
+    }
+
+    async fn check_integrity(&self) -> Result<(), String> {
+        // Postgres has no integrity_check counterpart (pages are checked by the server itself when
+        // data checksums are on), so only make sure the schema can be read
+        sqlx::query("SELECT 1 FROM blocks LIMIT 1;")
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| format!("postgres check query failed: {}", e))?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SqlBackend for PostgresStore {
+    type Database = Postgres;
+
+    fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    async fn insert_block(conn: &mut PgConnection, block: &Block) -> sqlx::Result<Block> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            INSERT INTO blocks (idx, version, timestamp, merkle_root, body, previous_hash, hash, nonce, difficulty, signature)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING *;
+            "#,
+        )
+        .bind(block.header.idx)
+        .bind(block.header.version as i64)
+        .bind(block.header.timestamp)
+        .bind(&block.header.merkle_root)
+        .bind(sqlx::types::Json(&block.body))
+        .bind(&block.header.previous_hash)
+        .bind(&block.hash)
+        .bind(block.header.nonce as i64)
+        .bind(block.header.difficulty)
+        .bind(&block.header.signature)
+        .fetch_one(conn)
+        .await
+    }
+
+    async fn delete_block(conn: &mut PgConnection, idx: i32) -> sqlx::Result<u64> {
+        let result = sqlx::query("DELETE FROM blocks WHERE idx = $1;").bind(idx).execute(conn).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_side_block(conn: &mut PgConnection, block: &Block) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO side_blocks (idx, version, timestamp, merkle_root, body, previous_hash, hash, nonce, difficulty, signature)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);
+            "#,
+        )
+        .bind(block.header.idx)
+        .bind(block.header.version as i64)
+        .bind(block.header.timestamp)
+        .bind(&block.header.merkle_root)
+        .bind(sqlx::types::Json(&block.body))
+        .bind(&block.header.previous_hash)
+        .bind(&block.hash)
+        .bind(block.header.nonce as i64)
+        .bind(block.header.difficulty)
+        .bind(&block.header.signature)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_side_block(conn: &mut PgConnection, hash: &str) -> sqlx::Result<u64> {
+        let result = sqlx::query("DELETE FROM side_blocks WHERE hash = $1;").bind(hash).execute(conn).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn linked_transactions(conn: &mut PgConnection, idx: i32) -> sqlx::Result<Vec<Transaction>> {
+        sqlx::query_as::<_, Transaction>(
+            r#"
+            SELECT *
+            FROM transactions
+            WHERE block_id = $1
+            ORDER BY rowid DESC;
+            "#,
+        )
+        .bind(idx)
+        .fetch_all(conn)
+        .await
+    }
+
+    async fn link_transaction(conn: &mut PgConnection, txid: &str, idx: i32) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE transactions
+            SET block_id = $1
+            WHERE txid = $2 AND block_id IS NULL;
+            "#,
+        )
+        .bind(idx)
+        .bind(txid)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_block_transactions(conn: &mut PgConnection, idx: i32) -> sqlx::Result<u64> {
+        let result = sqlx::query("DELETE FROM transactions WHERE block_id = $1;").bind(idx).execute(conn).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_coinbase(conn: &mut PgConnection, coinbase: &Transaction, idx: i32) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO transactions (from_address, to_address, amount, sig, added_to_block, created_at, block_id, fee, nonce, txid)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);
+            "#,
+        )
+        .bind(&coinbase.from_address)
+        .bind(&coinbase.to_address)
+        .bind(coinbase.amount)
+        .bind(coinbase.sig.clone().unwrap_or_default())
+        .bind(true)
+        .bind(coinbase.created_at)
+        .bind(idx)
+        .bind(coinbase.fee)
+        .bind(coinbase.nonce)
+        .bind(coinbase.txid())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_pending(conn: &mut PgConnection, transaction: &Transaction, txid: &str, created_at: f64) -> sqlx::Result<(i64, Transaction)> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO transactions (from_address, to_address, amount, sig, added_to_block, created_at, fee, nonce, txid)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING rowid, from_address, to_address, amount, sig, added_to_block, created_at, block_id, fee, nonce;
+            "#
+        )
+        .bind(&transaction.from_address)
+        .bind(&transaction.to_address)
+        .bind(transaction.amount)
+        .bind(&transaction.sig)
+        .bind(false)
+        .bind(created_at)
+        .bind(transaction.fee)
+        .bind(transaction.nonce)
+        .bind(txid)
+        .fetch_one(conn)
+        .await?;
+        Ok((row.try_get("rowid")?, Transaction::from_row(&row)?))
+    }
+
+    async fn transactions_without_txid(conn: &mut PgConnection) -> sqlx::Result<Vec<(i64, Transaction)>> {
+        let rows = sqlx::query_as::<_, PendingRow>(
+            r#"
+            SELECT *
+            FROM transactions
+            WHERE txid IS NULL
+            ORDER BY rowid ASC;
+            "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(rows.into_iter().map(|row| (row.rowid, row.transaction)).collect())
+    }
+
+    async fn set_txid(conn: &mut PgConnection, rowid: i64, txid: &str) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE transactions
+            SET txid = $1
+            WHERE rowid = $2;
+            "#,
+        )
+        .bind(txid)
+        .bind(rowid)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_pending(conn: &mut PgConnection, rowid: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM transactions
+            WHERE rowid = $1 AND block_id IS NULL;
+            "#,
+        )
+        .bind(rowid)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn wallet_nonce(conn: &mut PgConnection, address: &str) -> sqlx::Result<Option<i64>> {
+        sqlx::query_scalar::<_, i64>("SELECT nonce FROM wallets WHERE address = $1;")
+            .bind(address)
+            .fetch_optional(conn)
+            .await
+    }
+
+    async fn credit(conn: &mut PgConnection, address: &str, amount: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = balance + $1
+            WHERE address = $2 AND balance <= 2147483647 - $1;
+            "#,
+        )
+        .bind(amount)
+        .bind(address)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn debit(conn: &mut PgConnection, address: &str, amount: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = balance - $1
+            WHERE address = $2 AND balance >= $1;
+            "#,
+        )
+        .bind(amount)
+        .bind(address)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn debit_sender(conn: &mut PgConnection, address: &str, amount: i64, nonce: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = balance - $1, nonce = nonce + 1
+            WHERE address = $2 AND balance >= $1 AND nonce = $3;
+            "#,
+        )
+        .bind(amount)
+        .bind(address)
+        .bind(nonce)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn refund_sender(conn: &mut PgConnection, address: &str, amount: i64, next_nonce: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = balance + $1, nonce = nonce - 1
+            WHERE address = $2 AND nonce = $3 AND balance <= 2147483647 - $1;
+            "#,
+        )
+        .bind(amount)
+        .bind(address)
+        .bind(next_nonce)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_wallet(conn: &mut PgConnection, wallet: &Wallet) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO wallets (address, balance, pub_key, initial_balance, nonce)
+            VALUES ($1, $2, $3, $4, $5);
+            "#,
+        )
+        .bind(&wallet.address)
+        .bind(wallet.balance)
+        .bind(&wallet.pub_key)
+        .bind(wallet.initial_balance)
+        .bind(wallet.nonce)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_wallet(conn: &mut PgConnection, wallet: &Wallet) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = $1, pub_key = $2, initial_balance = $3, nonce = $4
+            WHERE address = $5;
+            "#,
+        )
+        .bind(wallet.balance)
+        .bind(&wallet.pub_key)
+        .bind(wallet.initial_balance)
+        .bind(wallet.nonce)
+        .bind(&wallet.address)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,290 @@
+use async_trait::async_trait;
+use rocket::warn;
+use sqlx::{Database, Pool};
+use crate::blockchain::{now_millis, Block};
+use crate::transactions::{Transaction, Wallet};
+use crate::utils::{is_unique_violation, ApiError};
+
+
+pub(super) type Connection<S> = <<S as SqlBackend>::Database as Database>::Connection;
+
+/// Statements of a SQL backend, each a single query run on a connection inside a database
+/// transaction. The store operations made of several of them are written once below, so every
+/// backend applies the wallet updates in the same order. Updates and deletes return the number
+/// of rows they changed.
+#[async_trait]
+pub(super) trait SqlBackend: Sync {
+    type Database: Database;
+
+    fn pool(&self) -> &Pool<Self::Database>;
+
+    async fn insert_block(conn: &mut Connection<Self>, block: &Block) -> sqlx::Result<Block>;
+
+    async fn delete_block(conn: &mut Connection<Self>, idx: i32) -> sqlx::Result<u64>;
+
+    async fn insert_side_block(conn: &mut Connection<Self>, block: &Block) -> sqlx::Result<()>;
+
+    async fn delete_side_block(conn: &mut Connection<Self>, hash: &str) -> sqlx::Result<u64>;
+
+    /// Transactions of the block at `idx`, the latest first.
+    async fn linked_transactions(conn: &mut Connection<Self>, idx: i32) -> sqlx::Result<Vec<Transaction>>;
+
+    /// Link the pending transaction `txid` to the block at `idx`.
+    async fn link_transaction(conn: &mut Connection<Self>, txid: &str, idx: i32) -> sqlx::Result<u64>;
+
+    async fn delete_block_transactions(conn: &mut Connection<Self>, idx: i32) -> sqlx::Result<u64>;
+
+    async fn insert_coinbase(conn: &mut Connection<Self>, coinbase: &Transaction, idx: i32) -> sqlx::Result<()>;
+
+    /// Store `transaction` as pending, created at `created_at` seconds since the unix epoch,
+    /// returning its rowid and the transaction as stored.
+    async fn insert_pending(conn: &mut Connection<Self>, transaction: &Transaction, txid: &str, created_at: f64) -> sqlx::Result<(i64, Transaction)>;
+
+    /// Rows stored before transactions had a txid, or whose txid a migration cleared, oldest first.
+    async fn transactions_without_txid(conn: &mut Connection<Self>) -> sqlx::Result<Vec<(i64, Transaction)>>;
+
+    async fn set_txid(conn: &mut Connection<Self>, rowid: i64, txid: &str) -> sqlx::Result<u64>;
+
+    async fn delete_pending(conn: &mut Connection<Self>, rowid: i64) -> sqlx::Result<u64>;
+
+    async fn wallet_nonce(conn: &mut Connection<Self>, address: &str) -> sqlx::Result<Option<i64>>;
+
+    /// Add `amount` to the balance of `address` if the balance stays within an `i32`.
+    async fn credit(conn: &mut Connection<Self>, address: &str, amount: i64) -> sqlx::Result<u64>;
+
+    /// Take `amount` from the balance of `address` if it is that high.
+    async fn debit(conn: &mut Connection<Self>, address: &str, amount: i64) -> sqlx::Result<u64>;
+
+    /// Take `amount` from the balance of `address` and use its `nonce`, if that is its next one
+    /// and the balance is that high.
+    async fn debit_sender(conn: &mut Connection<Self>, address: &str, amount: i64, nonce: i64) -> sqlx::Result<u64>;
+
+    /// Give `amount` back to `address` along with its nonce, if `next_nonce` is its next one and
+    /// the balance stays within an `i32`.
+    async fn refund_sender(conn: &mut Connection<Self>, address: &str, amount: i64, next_nonce: i64) -> sqlx::Result<u64>;
+
+    async fn insert_wallet(conn: &mut Connection<Self>, wallet: &Wallet) -> sqlx::Result<()>;
+
+    async fn update_wallet(conn: &mut Connection<Self>, wallet: &Wallet) -> sqlx::Result<u64>;
+}
+
+pub(super) async fn connect_block<S: SqlBackend>(store: &S, block: &Block, coinbase_pub_key: &str) -> Result<Block, ApiError> {
+    // Returning early drops `db_tx`, which rolls back whatever was already applied
+    let mut db_tx = store.pool().begin().await.map_err(|e| ApiError::internal("failed to begin transaction", e))?;
+
+    let stored_block = S::insert_block(&mut db_tx, block).await.map_err(|e| ApiError::internal("failed to insert block", e))?;
+    S::delete_side_block(&mut db_tx, &block.hash)
+        .await
+        .map_err(|e| ApiError::internal("failed to delete side block", e))?;
+
+    for coinbase in block.body.iter().filter(|tx| tx.is_coinbase()) {
+        apply_coinbase::<S>(&mut db_tx, coinbase, stored_block.header.idx, coinbase_pub_key).await?;
+    }
+
+    for txid in block.body.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.txid()) {
+        let linked = S::link_transaction(&mut db_tx, &txid, stored_block.header.idx)
+            .await
+            .map_err(|e| ApiError::internal("failed to link transactions to block", e))?;
+        if linked != 1 {
+            return Err(ApiError::Conflict(format!("transaction {} is no longer pending", txid)));
+        }
+    }
+
+    db_tx.commit().await.map_err(|e| ApiError::internal("failed to commit block", e))?;
+    Ok(stored_block)
+}
+
+pub(super) async fn disconnect_block<S: SqlBackend>(store: &S, block: &Block) -> Result<(), ApiError> {
+    let mut db_tx = store.pool().begin().await.map_err(|e| ApiError::internal("failed to begin transaction", e))?;
+
+    let linked = S::linked_transactions(&mut db_tx, block.header.idx)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block transactions", e))?;
+    for transaction in &linked {
+        revert_transaction::<S>(&mut db_tx, transaction).await?;
+    }
+
+    S::delete_block_transactions(&mut db_tx, block.header.idx)
+        .await
+        .map_err(|e| ApiError::internal("failed to delete block transactions", e))?;
+    S::delete_block(&mut db_tx, block.header.idx).await.map_err(|e| ApiError::internal("failed to delete block", e))?;
+    S::insert_side_block(&mut db_tx, block).await.map_err(|e| ApiError::internal("failed to insert side block", e))?;
+
+    db_tx.commit().await.map_err(|e| ApiError::internal("failed to commit transaction", e))?;
+    Ok(())
+}
+
+pub(super) async fn accept_transaction<S: SqlBackend>(store: &S, transaction: &Transaction) -> Result<(i64, Transaction), ApiError> {
+    // Debit, credit and insert run in a single database transaction: returning early drops `db_tx`,
+    // which rolls back whatever was already applied.
+    let mut db_tx = store.pool().begin().await.map_err(|e| ApiError::internal("failed to begin transaction", e))?;
+
+    // Reduce from_wallet by the amount plus the fee (paid to the miner) and increase to_wallet by the amount.
+    // The balance and the nonce are checked in the UPDATE itself so that concurrent requests cannot
+    // double spend or reuse a nonce.
+    let spent = transaction.amount as i64 + transaction.fee as i64;
+    let debited = S::debit_sender(&mut db_tx, &transaction.from_address, spent, transaction.nonce)
+        .await
+        .map_err(|e| ApiError::internal("failed to update from wallet", e))?;
+    if debited == 0 {
+        let nonce = S::wallet_nonce(&mut db_tx, &transaction.from_address)
+            .await
+            .map_err(|e| ApiError::internal("failed to get wallet nonce", e))?
+            .unwrap_or(0);
+        if nonce != transaction.nonce {
+            return Err(ApiError::Conflict(format!(
+                "nonce {} is not the next nonce of {}, expected {}",
+                transaction.nonce, transaction.from_address, nonce
+            )));
+        }
+        return Err(ApiError::Conflict("insufficient balance in from wallet".to_string()));
+    }
+
+    let credited = S::credit(&mut db_tx, &transaction.to_address, transaction.amount as i64)
+        .await
+        .map_err(|e| ApiError::internal("failed to update to wallet", e))?;
+    if credited == 0 {
+        return match S::wallet_nonce(&mut db_tx, &transaction.to_address).await {
+            Ok(Some(_)) => Err(ApiError::Conflict(format!("crediting {} overflows the balance of the to wallet", transaction.amount))),
+            Ok(None) => Err(ApiError::NotFound("to wallet not found".to_string())),
+            Err(e) => Err(ApiError::internal("failed to get wallet nonce", e)),
+        };
+    }
+
+    let txid = transaction.txid();
+    let (rowid, stored) = S::insert_pending(&mut db_tx, transaction, &txid, now_millis() as f64 / 1000.0).await.map_err(|e| {
+        if is_unique_violation(&e) {
+            ApiError::Conflict(format!("transaction {} already exists", txid))
+        } else {
+            ApiError::internal("failed to insert transaction", e)
+        }
+    })?;
+
+    db_tx.commit().await.map_err(|e| ApiError::internal("failed to commit transaction", e))?;
+    Ok((rowid, stored))
+}
+
+pub(super) async fn revert_pending<S: SqlBackend>(store: &S, rowid: i64, transaction: &Transaction) -> Result<bool, ApiError> {
+    let tx = transaction;
+    let mut db_tx = store.pool().begin().await.map_err(|e| ApiError::internal("failed to begin transaction", e))?;
+
+    let deleted = S::delete_pending(&mut db_tx, rowid).await.map_err(|e| ApiError::internal("failed to delete transaction", e))?;
+    if deleted == 0 {
+        return Ok(false);
+    }
+
+    let debited = S::debit(&mut db_tx, &tx.to_address, tx.amount as i64)
+        .await
+        .map_err(|e| ApiError::internal("failed to update to wallet", e))?;
+    if debited == 0 {
+        return Ok(false);
+    }
+
+    let refunded = S::refund_sender(&mut db_tx, &tx.from_address, tx.amount as i64 + tx.fee as i64, tx.nonce + 1)
+        .await
+        .map_err(|e| ApiError::internal("failed to update from wallet", e))?;
+    if refunded == 0 {
+        return Ok(false);
+    }
+
+    db_tx.commit().await.map_err(|e| ApiError::internal("failed to commit transaction", e))?;
+    Ok(true)
+}
+
+pub(super) async fn put_wallet<S: SqlBackend>(store: &S, wallet: &Wallet) -> Result<(), ApiError> {
+    let mut db_tx = store.pool().begin().await.map_err(|e| ApiError::internal("failed to begin transaction", e))?;
+
+    // Addresses are not a key of the table, so no upsert
+    let updated = S::update_wallet(&mut db_tx, wallet).await.map_err(|e| ApiError::internal("failed to update wallet", e))?;
+    if updated == 0 {
+        S::insert_wallet(&mut db_tx, wallet).await.map_err(|e| ApiError::internal("failed to insert wallet", e))?;
+    }
+
+    db_tx.commit().await.map_err(|e| ApiError::internal("failed to commit wallet", e))
+}
+
+/// Store the txid of rows inserted before transactions had one. Rows that duplicate an earlier
+/// transaction (the same signed payload submitted again) are left without txid.
+pub(super) async fn backfill_txids<S: SqlBackend>(store: &S) -> Result<(), ApiError> {
+    // Not in a database transaction: a duplicate must not roll back the rows before it
+    let mut conn = store.pool().acquire().await.map_err(|e| ApiError::internal("failed to get connection", e))?;
+    let rows = S::transactions_without_txid(&mut conn)
+        .await
+        .map_err(|e| ApiError::internal("failed to get transactions without txid", e))?;
+
+    for (rowid, transaction) in rows {
+        let txid = transaction.txid();
+        match S::set_txid(&mut conn, rowid, &txid).await {
+            Ok(_) => {}
+            Err(e) if is_unique_violation(&e) => warn!("transaction {} duplicates txid {}", rowid, txid),
+            Err(e) => return Err(ApiError::internal("failed to backfill txid", e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Record `coinbase` in the block stored at `idx` and credit the reward to its recipient,
+/// creating the wallet with `pub_key` if it does not exist yet.
+async fn apply_coinbase<S: SqlBackend>(conn: &mut Connection<S>, coinbase: &Transaction, idx: i32, pub_key: &str) -> Result<(), ApiError> {
+    S::insert_coinbase(conn, coinbase, idx).await.map_err(|e| ApiError::internal("failed to insert coinbase", e))?;
+
+    let credited = S::credit(conn, &coinbase.to_address, coinbase.amount as i64)
+        .await
+        .map_err(|e| ApiError::internal("failed to credit miner wallet", e))?;
+    if credited == 0 {
+        let exists = S::wallet_nonce(conn, &coinbase.to_address)
+            .await
+            .map_err(|e| ApiError::internal("failed to get wallet nonce", e))?
+            .is_some();
+        if exists {
+            return Err(ApiError::Conflict(format!(
+                "coinbase of {} overflows the balance of {}",
+                coinbase.amount, coinbase.to_address
+            )));
+        }
+        let wallet = Wallet {
+            address: coinbase.to_address.clone(),
+            balance: coinbase.amount,
+            pub_key: pub_key.to_string(),
+            initial_balance: 0,
+            nonce: 0,
+        };
+        S::insert_wallet(conn, &wallet).await.map_err(|e| ApiError::internal("failed to create miner wallet", e))?;
+    }
+
+    Ok(())
+}
+
+/// Undo a confirmed transaction: debit the recipient and, unless it is a coinbase, credit the
+/// sender back and give it its nonce again. Every later transaction of both must be undone already.
+async fn revert_transaction<S: SqlBackend>(conn: &mut Connection<S>, transaction: &Transaction) -> Result<(), ApiError> {
+    let debited = S::debit(conn, &transaction.to_address, transaction.amount as i64)
+        .await
+        .map_err(|e| ApiError::internal("failed to update to wallet", e))?;
+    if debited == 0 {
+        return Err(ApiError::Internal(format!(
+            "{} cannot pay back the {} of transaction {}, it cannot be reverted",
+            transaction.to_address, transaction.amount, transaction.txid()
+        )));
+    }
+    if transaction.is_coinbase() {
+        return Ok(());
+    }
+
+    let refunded = S::refund_sender(
+        conn,
+        &transaction.from_address,
+        transaction.amount as i64 + transaction.fee as i64,
+        transaction.nonce + 1,
+    )
+    .await
+    .map_err(|e| ApiError::internal("failed to update from wallet", e))?;
+    if refunded == 0 {
+        return Err(ApiError::Internal(format!(
+            "transaction {} is not the latest of {}, it cannot be reverted",
+            transaction.txid(), transaction.from_address
+        )));
+    }
+    Ok(())
+}
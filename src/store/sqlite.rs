@@ -0,0 +1,619 @@
+use async_trait::async_trait;
+use futures::stream::{BoxStream, StreamExt};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{FromRow, Row, Sqlite, SqliteConnection, SqlitePool};
+use crate::blockchain::{Block, BlockHeader};
+use crate::p2p::HeaderEntry;
+use crate::store::sql::{self, SqlBackend};
+use crate::store::{BlockSummary, ChainStore};
+use crate::transactions::{Transaction, Wallet};
+use crate::utils::ApiError;
+
+
+/// Store in a SQLite database, migrated with the files in `migrations/`.
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+#[derive(FromRow)]
+struct PendingRow {
+    rowid: i64,
+    #[sqlx(flatten)]
+    transaction: Transaction,
+}
+
+impl SqliteStore {
+    /// Open the database at `database_url` and bring it up to date: apply the migrations and
+    /// store the txid of the rows inserted before transactions had one.
+    pub async fn connect(database_url: &str, max_connections: u32) -> Result<Self, String> {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .connect(database_url)
+            .await
+            .map_err(|e| format!("failed to connect to SQLite at {}: {}", database_url, e))?;
+        sqlx::migrate!("./migrations").run(&pool).await.map_err(|e| format!("migrations failed: {}", e))?;
+
+        let store = SqliteStore { pool };
+        sql::backfill_txids(&store).await.map_err(|e| format!("failed to backfill transaction ids: {}", e))?;
+        Ok(store)
+    }
+
+    /// The underlying pool, for tools and tests that reach below the store.
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl ChainStore for SqliteStore {
+    async fn height(&self) -> Result<i32, ApiError> {
+        sqlx::query_scalar::<_, i32>(
+        r#"
+            SELECT COUNT(*) FROM blocks;
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get chain size", e))
+    }
+
+    async fn head(&self) -> Result<Option<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM blocks
+            ORDER BY idx DESC
+            LIMIT 1;
+            "#
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block", e))
+    }
+
+    async fn block_by_idx(&self, idx: i32) -> Result<Option<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM blocks
+            WHERE idx = ?;
+            "#
+        )
+        .bind(idx)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block", e))
+    }
+
+    async fn block_by_hash(&self, hash: &str) -> Result<Option<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM blocks
+            WHERE hash = ?;
+            "#
+        )
+        .bind(hash)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block", e))
+    }
+
+    async fn blocks_after(&self, idx: i32, limit: i64) -> Result<Vec<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM blocks
+            WHERE idx > ?
+            ORDER BY idx ASC
+            LIMIT ?;
+            "#,
+        )
+        .bind(idx)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get blocks", e))
+    }
+
+    async fn headers_after(&self, idx: i32, limit: i64) -> Result<Vec<HeaderEntry>, ApiError> {
+        sqlx::query_as::<_, HeaderEntry>(
+            r#"
+            SELECT version, idx, previous_hash, merkle_root, timestamp, difficulty, nonce, signature, hash
+            FROM blocks
+            WHERE idx > ?
+            ORDER BY idx ASC
+            LIMIT ?;
+            "#,
+        )
+        .bind(idx)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to read block headers", e))
+    }
+
+    fn block_summaries(&self) -> BoxStream<'_, Result<BlockSummary, ApiError>> {
+        // Rows are read one at a time, the chain is never loaded in memory at once
+        sqlx::query!(
+            r#"
+            SELECT
+                version AS "version!: u32", idx AS "idx!: i32", timestamp AS "timestamp!", hash AS "hash!",
+                previous_hash AS "previous_hash!", merkle_root AS "merkle_root!", difficulty AS "difficulty!: i32",
+                nonce AS "nonce!: u32", signature,
+                (SELECT COUNT(*) FROM transactions t
+                    WHERE t.block_id = blocks.idx AND t.from_address = '') AS "coinbase_count!: i64",
+                (SELECT COALESCE(SUM(t.amount), 0) FROM transactions t
+                    WHERE t.block_id = blocks.idx AND t.from_address = '') AS "coinbase_amount!: i64",
+                (SELECT COALESCE(SUM(t.fee), 0) FROM transactions t
+                    WHERE t.block_id = blocks.idx AND t.from_address != '') AS "fees!: i64"
+            FROM blocks
+            ORDER BY idx ASC
+            "#
+        )
+        .fetch(&self.pool)
+        .map(|row| {
+            let row = row.map_err(|e| ApiError::internal("failed to read blocks", e))?;
+            Ok(BlockSummary {
+                header: BlockHeader {
+                    version: row.version,
+                    idx: row.idx,
+                    previous_hash: row.previous_hash,
+                    merkle_root: row.merkle_root,
+                    timestamp: row.timestamp,
+                    difficulty: row.difficulty,
+                    nonce: row.nonce,
+                    signature: row.signature,
+                },
+                hash: row.hash,
+                coinbase_count: row.coinbase_count,
+                coinbase_amount: row.coinbase_amount,
+                fees: row.fees,
+            })
+        })
+        .boxed()
+    }
+
+    async fn side_block(&self, hash: &str) -> Result<Option<Block>, ApiError> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            SELECT * FROM side_blocks
+            WHERE hash = ?;
+            "#
+        )
+        .bind(hash)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get side block", e))
+    }
+
+    async fn put_side_block(&self, block: &Block) -> Result<(), ApiError> {
+        let mut conn = self.pool.acquire().await.map_err(|e| ApiError::internal("failed to get connection", e))?;
+        Self::insert_side_block(&mut conn, block).await.map_err(|e| ApiError::internal("failed to insert side block", e))
+    }
+
+    async fn delete_side_block(&self, hash: &str) -> Result<(), ApiError> {
+        let mut conn = self.pool.acquire().await.map_err(|e| ApiError::internal("failed to get connection", e))?;
+        <Self as SqlBackend>::delete_side_block(&mut conn, hash)
+            .await
+            .map_err(|e| ApiError::internal("failed to delete side block", e))?;
+        Ok(())
+    }
+
+    async fn connect_block(&self, block: &Block, coinbase_pub_key: &str) -> Result<Block, ApiError> {
+        sql::connect_block(self, block, coinbase_pub_key).await
+    }
+
+    async fn disconnect_block(&self, block: &Block) -> Result<(), ApiError> {
+        sql::disconnect_block(self, block).await
+    }
+
+    async fn transaction(&self, txid: &str) -> Result<Option<Transaction>, ApiError> {
+        sqlx::query_as::<_, Transaction>(
+            r#"
+            SELECT *
+            FROM transactions
+            WHERE txid = ?;
+            "#,
+        )
+        .bind(txid)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get transaction", e))
+    }
+
+    async fn block_transactions(&self, idx: i32) -> Result<Vec<Transaction>, ApiError> {
+        sqlx::query_as::<_, Transaction>(
+            r#"
+            SELECT *
+            FROM transactions
+            WHERE block_id = ?
+            ORDER BY (from_address = '') DESC, rowid ASC;
+            "#,
+        )
+        .bind(idx)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block transactions", e))
+    }
+
+    async fn transactions_in_blocks(&self, first: i32, last: i32) -> Result<Vec<Transaction>, ApiError> {
+        sqlx::query_as::<_, Transaction>(
+            r#"
+            SELECT * FROM transactions
+            WHERE block_id BETWEEN ? AND ?
+            ORDER BY block_id ASC, (from_address = '') DESC, rowid ASC;
+            "#,
+        )
+        .bind(first)
+        .bind(last)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get block transactions", e))
+    }
+
+    async fn pending_transactions(&self) -> Result<Vec<(i64, Transaction)>, ApiError> {
+        let rows = sqlx::query_as::<_, PendingRow>(
+            r#"
+            SELECT rowid, *
+            FROM transactions
+            WHERE block_id IS NULL OR block_id = ''
+            ORDER BY rowid ASC;
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get pending transactions", e))?;
+        Ok(rows.into_iter().map(|row| (row.rowid, row.transaction)).collect())
+    }
+
+    async fn pending_count(&self) -> Result<i64, ApiError> {
+        sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*)
+            FROM transactions
+            WHERE block_id IS NULL OR block_id = '';
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to count pending transactions", e))
+    }
+
+    async fn accept_transaction(&self, transaction: &Transaction) -> Result<(i64, Transaction), ApiError> {
+        sql::accept_transaction(self, transaction).await
+    }
+
+    async fn revert_pending(&self, rowid: i64, transaction: &Transaction) -> Result<bool, ApiError> {
+        sql::revert_pending(self, rowid, transaction).await
+    }
+
+    async fn wallet(&self, address: &str) -> Result<Option<Wallet>, ApiError> {
+        sqlx::query_as::<_, Wallet>(
+            r#"
+            SELECT *
+            FROM wallets
+            WHERE address = ?;
+            "#,
+        )
+        .bind(address)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| ApiError::internal("failed to get wallet", e))
+    }
+
+    async fn wallets(&self) -> Result<Vec<Wallet>, ApiError> {
+        sqlx::query_as::<_, Wallet>("SELECT * FROM wallets;")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| ApiError::internal("failed to get wallets", e))
+    }
+
+    async fn put_wallet(&self, wallet: &Wallet) -> Result<(), ApiError> {
+        sql::put_wallet(self, wallet).await
+    }
+
+    async fn check_integrity(&self) -> Result<(), String> {
+        // Note: this may be slow on very large databases
+        let integrity_row = sqlx::query("PRAGMA integrity_check;")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(|e| format!("PRAGMA integrity_check query failed: {}", e))?;
+
+        let integrity_result: String = integrity_row
+            .try_get(0)
+            .map_err(|e| format!("failed to read integrity_check result: {}", e))?;
+
+        if integrity_result.to_lowercase() != "ok" {
+            return Err(format!("sqlite integrity_check failed: {}", integrity_result));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SqlBackend for SqliteStore {
+    type Database = Sqlite;
+
+    fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    async fn insert_block(conn: &mut SqliteConnection, block: &Block) -> sqlx::Result<Block> {
+        sqlx::query_as::<_, Block>(
+            r#"
+            INSERT INTO blocks (idx, version, timestamp, merkle_root, body, previous_hash, hash, nonce, difficulty, signature)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING *;
+            "#,
+        )
+        .bind(block.header.idx)
+        .bind(block.header.version)
+        .bind(block.header.timestamp)
+        .bind(&block.header.merkle_root)
+        .bind(sqlx::types::Json(&block.body))
+        .bind(&block.header.previous_hash)
+        .bind(&block.hash)
+        .bind(block.header.nonce)
+        .bind(block.header.difficulty)
+        .bind(&block.header.signature)
+        .fetch_one(conn)
+        .await
+    }
+
+    async fn delete_block(conn: &mut SqliteConnection, idx: i32) -> sqlx::Result<u64> {
+        let result = sqlx::query("DELETE FROM blocks WHERE idx = ?;").bind(idx).execute(conn).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_side_block(conn: &mut SqliteConnection, block: &Block) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO side_blocks (idx, version, timestamp, merkle_root, body, previous_hash, hash, nonce, difficulty, signature)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
+            "#,
+        )
+        .bind(block.header.idx)
+        .bind(block.header.version)
+        .bind(block.header.timestamp)
+        .bind(&block.header.merkle_root)
+        .bind(sqlx::types::Json(&block.body))
+        .bind(&block.header.previous_hash)
+        .bind(&block.hash)
+        .bind(block.header.nonce)
+        .bind(block.header.difficulty)
+        .bind(&block.header.signature)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn delete_side_block(conn: &mut SqliteConnection, hash: &str) -> sqlx::Result<u64> {
+        let result = sqlx::query("DELETE FROM side_blocks WHERE hash = ?;").bind(hash).execute(conn).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn linked_transactions(conn: &mut SqliteConnection, idx: i32) -> sqlx::Result<Vec<Transaction>> {
+        sqlx::query_as::<_, Transaction>(
+            r#"
+            SELECT *
+            FROM transactions
+            WHERE block_id = ?
+            ORDER BY rowid DESC;
+            "#,
+        )
+        .bind(idx)
+        .fetch_all(conn)
+        .await
+    }
+
+    async fn link_transaction(conn: &mut SqliteConnection, txid: &str, idx: i32) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE transactions
+            SET block_id = ?
+            WHERE txid = ? AND block_id IS NULL;
+            "#,
+        )
+        .bind(idx)
+        .bind(txid)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_block_transactions(conn: &mut SqliteConnection, idx: i32) -> sqlx::Result<u64> {
+        let result = sqlx::query("DELETE FROM transactions WHERE block_id = ?;").bind(idx).execute(conn).await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_coinbase(conn: &mut SqliteConnection, coinbase: &Transaction, idx: i32) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO transactions (from_address, to_address, amount, sig, added_to_block, created_at, block_id, fee, nonce, txid)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?);
+            "#,
+        )
+        .bind(&coinbase.from_address)
+        .bind(&coinbase.to_address)
+        .bind(coinbase.amount)
+        .bind(coinbase.sig.clone().unwrap_or_default())
+        .bind(true)
+        .bind(coinbase.created_at)
+        .bind(idx)
+        .bind(coinbase.fee)
+        .bind(coinbase.nonce)
+        .bind(coinbase.txid())
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn insert_pending(conn: &mut SqliteConnection, transaction: &Transaction, txid: &str, created_at: f64) -> sqlx::Result<(i64, Transaction)> {
+        let row = sqlx::query(
+            r#"
+            INSERT INTO transactions (from_address, to_address, amount, sig, added_to_block, created_at, fee, nonce, txid)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING rowid, from_address, to_address, amount, sig, added_to_block, created_at, block_id, fee, nonce;
+            "#
+        )
+        .bind(&transaction.from_address)
+        .bind(&transaction.to_address)
+        .bind(transaction.amount)
+        .bind(&transaction.sig)
+        .bind(false)
+        .bind(created_at)
+        .bind(transaction.fee)
+        .bind(transaction.nonce)
+        .bind(txid)
+        .fetch_one(conn)
+        .await?;
+        Ok((row.try_get("rowid")?, Transaction::from_row(&row)?))
+    }
+
+    async fn transactions_without_txid(conn: &mut SqliteConnection) -> sqlx::Result<Vec<(i64, Transaction)>> {
+        let rows = sqlx::query_as::<_, PendingRow>(
+            r#"
+            SELECT rowid, *
+            FROM transactions
+            WHERE txid IS NULL
+            ORDER BY rowid ASC;
+            "#,
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(rows.into_iter().map(|row| (row.rowid, row.transaction)).collect())
+    }
+
+    async fn set_txid(conn: &mut SqliteConnection, rowid: i64, txid: &str) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE transactions
+            SET txid = ?
+            WHERE rowid = ?;
+            "#,
+        )
+        .bind(txid)
+        .bind(rowid)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn delete_pending(conn: &mut SqliteConnection, rowid: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM transactions
+            WHERE rowid = ? AND block_id IS NULL;
+            "#,
+        )
+        .bind(rowid)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn wallet_nonce(conn: &mut SqliteConnection, address: &str) -> sqlx::Result<Option<i64>> {
+        sqlx::query_scalar::<_, i64>("SELECT nonce FROM wallets WHERE address = ?;")
+            .bind(address)
+            .fetch_optional(conn)
+            .await
+    }
+
+    async fn credit(conn: &mut SqliteConnection, address: &str, amount: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = balance + ?
+            WHERE address = ? AND balance <= 2147483647 - ?;
+            "#,
+        )
+        .bind(amount)
+        .bind(address)
+        .bind(amount)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn debit(conn: &mut SqliteConnection, address: &str, amount: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = balance - ?
+            WHERE address = ? AND balance >= ?;
+            "#,
+        )
+        .bind(amount)
+        .bind(address)
+        .bind(amount)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn debit_sender(conn: &mut SqliteConnection, address: &str, amount: i64, nonce: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = balance - ?, nonce = nonce + 1
+            WHERE address = ? AND balance >= ? AND nonce = ?;
+            "#,
+        )
+        .bind(amount)
+        .bind(address)
+        .bind(amount)
+        .bind(nonce)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn refund_sender(conn: &mut SqliteConnection, address: &str, amount: i64, next_nonce: i64) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = balance + ?, nonce = nonce - 1
+            WHERE address = ? AND nonce = ? AND balance <= 2147483647 - ?;
+            "#,
+        )
+        .bind(amount)
+        .bind(address)
+        .bind(next_nonce)
+        .bind(amount)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_wallet(conn: &mut SqliteConnection, wallet: &Wallet) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO wallets (address, balance, pub_key, initial_balance, nonce)
+            VALUES (?, ?, ?, ?, ?);
+            "#,
+        )
+        .bind(&wallet.address)
+        .bind(wallet.balance)
+        .bind(&wallet.pub_key)
+        .bind(wallet.initial_balance)
+        .bind(wallet.nonce)
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn update_wallet(conn: &mut SqliteConnection, wallet: &Wallet) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE wallets
+            SET balance = ?, pub_key = ?, initial_balance = ?, nonce = ?
+            WHERE address = ?;
+            "#,
+        )
+        .bind(wallet.balance)
+        .bind(&wallet.pub_key)
+        .bind(wallet.initial_balance)
+        .bind(wallet.nonce)
+        .bind(&wallet.address)
+        .execute(conn)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,265 @@
+use rocket::tokio::sync::watch;
+use rocket::tokio::time;
+use rocket::{get, info, routes, serde::json::Json, warn, State};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::blockchain::{block_by_idx, find_block, Block};
+use crate::consensus::ConsensusEngine;
+use crate::p2p::{HeaderEntry, InvItem, Message, Network, Node, SharedNetwork};
+use crate::store::ChainStore;
+use crate::utils::{ApiError, ApiResult};
+
+
+const SYNC_BATCH_SIZE: usize = 64; // Blocks requested from the peer at once
+const BATCH_TIMEOUT: Duration = Duration::from_secs(60); // For a whole batch to be imported
+const STORED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const PEER_WAIT: Duration = Duration::from_secs(30); // For a first seed peer before mining on our own chain
+const RESYNC_INTERVAL: Duration = Duration::from_secs(10); // Between two checks for peers ahead of us, once synced
+
+pub fn routes() -> Vec<rocket::Route> {
+    routes![get_sync]
+}
+
+/// Chain sync shared between the sync task, the cpu worker and the request handlers.
+pub type SharedSync = Arc<ChainSync>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncState {
+    /// Waiting for a first peer to compare chains with
+    Connecting,
+    /// Fetching headers and blocks from a peer ahead of us
+    Downloading,
+    /// No known peer is ahead of us
+    Synced,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SyncProgress {
+    pub state: SyncState,
+    pub height: i32,
+    /// Height of the peer being downloaded from, our height once synced
+    pub target_height: i32,
+    /// Address of the peer being downloaded from
+    pub peer: Option<String>,
+}
+
+/// Initial block download: brings the local chain up to the best connected peer before the node
+/// mines, then keeps following peers found ahead of it.
+pub struct ChainSync {
+    progress: Mutex<SyncProgress>,
+    synced: watch::Sender<bool>,
+}
+
+impl ChainSync {
+    pub fn new() -> Self {
+        ChainSync {
+            progress: Mutex::new(SyncProgress { state: SyncState::Connecting, height: 0, target_height: 0, peer: None }),
+            synced: watch::Sender::new(false),
+        }
+    }
+
+    pub fn progress(&self) -> SyncProgress {
+        self.progress.lock().expect("sync lock").clone()
+    }
+
+    /// Whether the initial block download is over. Later downloads from peers found ahead of us
+    /// leave it set.
+    pub fn is_synced(&self) -> bool {
+        *self.synced.borrow()
+    }
+
+    /// Wait for the initial block download to be over.
+    pub async fn wait_synced(&self) {
+        let mut synced = self.synced.subscribe();
+        // The sender lives as long as self, so this never fails
+        let _ = synced.wait_for(|synced| *synced).await;
+    }
+
+    fn update(&self, state: SyncState, height: i32, target_height: i32, peer: Option<String>) {
+        *self.progress.lock().expect("sync lock") = SyncProgress { state, height, target_height, peer };
+    }
+
+    /// Sync from the connected peers until the node shuts down.
+    pub async fn run(self: Arc<Self>, node: Node, network: SharedNetwork) {
+        let height = local_height(node.store.as_ref()).await.unwrap_or_default();
+        self.update(SyncState::Connecting, height, height, None);
+
+        // Without seeds nobody may ever connect, our own chain is the best one known
+        if network.has_seeds() {
+            let deadline = time::Instant::now() + PEER_WAIT;
+            while network.peers().is_empty() && time::Instant::now() < deadline {
+                time::sleep(Duration::from_millis(100)).await;
+            }
+            if network.peers().is_empty() {
+                warn!("no peer reached within {}s, starting from the local chain", PEER_WAIT.as_secs());
+            }
+        }
+
+        loop {
+            match self.sync_once(&node, &network).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => warn!("chain sync failed: {}", e.message()),
+            }
+            let height = local_height(node.store.as_ref()).await.unwrap_or_default();
+            self.update(SyncState::Synced, height, height, None);
+            if !self.is_synced() {
+                info!("chain synced at height {}", height);
+                self.synced.send_replace(true);
+            }
+            time::sleep(RESYNC_INTERVAL).await;
+        }
+    }
+
+    /// Download from the highest peer if it is ahead of us, returns whether it was.
+    async fn sync_once(&self, node: &Node, network: &Network) -> Result<bool, ApiError> {
+        let height = local_height(node.store.as_ref()).await?;
+        let Some(peer) = network.peers().into_iter().max_by_key(|peer| peer.height) else {
+            return Ok(false);
+        };
+        if peer.height <= height {
+            return Ok(false);
+        }
+
+        info!("downloading blocks {} to {} from peer {}", height + 1, peer.height, peer.addr);
+        match self.download_from(node, network, peer.id, &peer.addr, peer.height).await {
+            Ok(()) => {
+                // Whatever the peer announced in its handshake, it has nothing more for us
+                network.set_peer_height(peer.id, local_height(node.store.as_ref()).await?);
+            }
+            Err(e) => {
+                warn!("dropping peer {}, block download failed: {}", peer.addr, e.message());
+                network.disconnect(peer.id);
+                time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Download the peer's headers after the last block we share, then their blocks in batches.
+    /// Blocks are imported by the peer connection as they arrive: extending the head or stored on
+    /// a side branch, which a reorg makes the main chain once it has more work.
+    async fn download_from(&self, node: &Node, network: &Network, id: u64, addr: &str, target: i32) -> Result<(), ApiError> {
+        let mut last_hash: Option<String> = None;
+        loop {
+            let height = local_height(node.store.as_ref()).await?;
+            self.update(SyncState::Downloading, height, target.max(height), Some(addr.to_string()));
+
+            // Continue after the headers already downloaded, they may be on a side branch
+            let mut locator = block_locator(node.store.as_ref(), height).await?;
+            locator.splice(0..0, last_hash.take());
+            let headers = network
+                .request_headers(id, locator)
+                .await
+                .map_err(|e| ApiError::Conflict(format!("getheaders failed: {}", e)))?;
+            let Some(first) = headers.first() else {
+                return Ok(());
+            };
+            let parent = find_block(node.store.as_ref(), &first.header.previous_hash)
+                .await?
+                .ok_or_else(|| ApiError::Conflict(format!("headers start after unknown block {}", first.header.previous_hash)))?;
+            check_headers(node.consensus.as_ref(), &parent, &headers)?;
+
+            for batch in headers.chunks(SYNC_BATCH_SIZE) {
+                let mut items = Vec::new();
+                for entry in batch {
+                    if find_block(node.store.as_ref(), &entry.hash).await?.is_none() {
+                        items.push(InvItem::block(&entry.hash));
+                    }
+                }
+                if !items.is_empty() {
+                    network
+                        .send_to(id, Message::GetData(items))
+                        .await
+                        .map_err(|e| ApiError::Conflict(format!("getdata failed: {}", e)))?;
+                }
+
+                let last = batch.last().expect("batches are not empty");
+                if !wait_stored(node.store.as_ref(), &last.hash).await? {
+                    return Err(ApiError::Conflict(format!("block {} at idx {} was not imported in time", last.hash, last.header.idx)));
+                }
+                let height = local_height(node.store.as_ref()).await?;
+                self.update(SyncState::Downloading, height, target.max(height), Some(addr.to_string()));
+            }
+            last_hash = headers.last().map(|entry| entry.hash.clone());
+        }
+    }
+}
+
+impl Default for ChainSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wait up to `BATCH_TIMEOUT` for the block with `hash` to be stored, returns whether it was.
+async fn wait_stored(store: &dyn ChainStore, hash: &str) -> Result<bool, ApiError> {
+    let deadline = time::Instant::now() + BATCH_TIMEOUT;
+    while find_block(store, hash).await?.is_none() {
+        if time::Instant::now() >= deadline {
+            return Ok(false);
+        }
+        time::sleep(STORED_POLL_INTERVAL).await;
+    }
+    Ok(true)
+}
+
+async fn local_height(store: &dyn ChainStore) -> Result<i32, ApiError> {
+    Ok(store.head().await?.map_or(0, |block| block.header.idx))
+}
+
+/// Hashes of the blocks at `height`, the 10 below it, then exponentially further apart down to
+/// the genesis block, so a peer on another branch finds a recent common block in few hashes.
+async fn block_locator(store: &dyn ChainStore, height: i32) -> Result<Vec<String>, ApiError> {
+    let mut idxs = Vec::new();
+    let mut idx = height;
+    let mut step = 1;
+    while idx > 1 {
+        idxs.push(idx);
+        if idxs.len() >= 10 {
+            step *= 2;
+        }
+        idx -= step;
+    }
+    idxs.push(1);
+
+    let mut locator = Vec::with_capacity(idxs.len());
+    for idx in idxs {
+        locator.push(block_by_idx(store, idx).await?.hash);
+    }
+    Ok(locator)
+}
+
+/// Headers must follow `parent` one after the other and carry a valid seal. Difficulty retargets
+/// and block contents are checked when each block is imported.
+fn check_headers(consensus: &dyn ConsensusEngine, parent: &Block, headers: &[HeaderEntry]) -> Result<(), ApiError> {
+    let mut previous_hash = &parent.hash;
+    let mut previous = &parent.header;
+    let mut idx = parent.header.idx;
+    for entry in headers {
+        if entry.header.previous_hash != *previous_hash {
+            return Err(ApiError::InvalidInput(format!("header {} does not link to the previous one", entry.hash)));
+        }
+        if entry.header.idx != idx + 1 {
+            return Err(ApiError::InvalidInput(format!("header {} has idx {}, expected {}", entry.hash, entry.header.idx, idx + 1)));
+        }
+        if entry.header.hash() != entry.hash {
+            return Err(ApiError::InvalidInput(format!("header {} does not match its hash", entry.hash)));
+        }
+        if let Err(e) = consensus.verify_seal(&entry.header, &entry.hash, previous) {
+            return Err(ApiError::InvalidInput(format!("header {} has an invalid seal: {}", entry.hash, e)));
+        }
+        previous_hash = &entry.hash;
+        previous = &entry.header;
+        idx = entry.header.idx;
+    }
+    Ok(())
+}
+
+
+#[get("/sync")]
+async fn get_sync(sync: &State<SharedSync>) -> ApiResult<SyncProgress> {
+    Ok(Json(sync.progress()))
+}
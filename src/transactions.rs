@@ -1,14 +1,18 @@
 use sqlx::FromRow;
 use crate::utils::*;
-use crate::error_response;
-use rocket::{error, get, post, serde::json::Json, routes};
+use rocket::{get, post, serde::json::Json, routes, warn, State};
 use serde::{Deserialize, Serialize};
+use crate::blockchain::{block_by_idx, BlockHeader};
+use crate::crypto;
+use crate::mempool::{mempool_expiry, mempool_max_txs, SharedMempool};
+use crate::p2p::{InvItem, Network, SharedNetwork};
+use crate::merkle::{self, MerkleStep};
+use crate::store::{ChainStore, SharedStore};
 use sha2::{Digest, Sha256};
-use rocket::http::Status;
 
 
 pub fn routes() -> Vec<rocket::Route> {
-    routes![create_transaction, get_wallet_details]
+    routes![create_transaction, get_transaction, get_transaction_proof, get_wallet_details]
 }
 
 
@@ -19,79 +23,205 @@ pub struct Transaction {
     pub amount: i32,
     pub sig: Option<String>,
     pub added_to_block: Option<bool>,
+    // Seconds since the unix epoch at which the node stored it, the block time for a coinbase
     pub created_at: Option<f64>,
-    pub block_id: Option<i32>
+    pub block_id: Option<i32>,
+    #[serde(default)]
+    pub fee: i32,
+    // Position of the transaction among the ones sent by `from_address`, starting at 0
+    #[serde(default)]
+    pub nonce: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionStatus {
+    /// Waiting for the transactions with a lower nonce from the same sender
+    Queued,
+    /// Waiting in the mempool
+    Pending,
+    /// Included in a block of the chain
+    Confirmed,
+}
+
+/// A transaction looked up by txid, with where it stands in the chain.
+#[derive(Serialize)]
+pub struct TransactionInfo {
+    pub txid: String,
+    pub transaction: Transaction,
+    pub status: TransactionStatus,
+    /// Index of the block containing the transaction
+    pub block_idx: Option<i32>,
+    /// Number of blocks on top of and including the containing block, 0 while pending
+    pub confirmations: i32,
+}
+
+/// Proof that a confirmed transaction is committed to by a block header: folding `branch` into
+/// `leaf` gives `header.merkle_root` (see `merkle::verify_merkle_proof`).
+#[derive(Serialize, Deserialize)]
+pub struct TransactionProof {
+    pub txid: String,
+    /// Merkle leaf of the transaction
+    pub leaf: String,
+    /// Position of the transaction in the block body
+    pub index: usize,
+    /// Sibling hashes from the leaf up to the root
+    pub branch: Vec<MerkleStep>,
+    pub block_hash: String,
+    pub header: BlockHeader,
 }
 
 #[derive(Clone, FromRow, Serialize, Deserialize)]
 pub struct Wallet {
     pub address: String,
     pub balance: i32,
-    pub pub_key: String
+    pub pub_key: String,
+    // Funds the wallet was created with, outside of any transaction
+    #[serde(default)]
+    pub initial_balance: i32,
+    // Nonce the next transaction sent from the wallet must carry
+    #[serde(default)]
+    pub nonce: i64,
 }
 
 impl Transaction {
-    // pub fn new() -> Self {
-    //     todo!()
-    // }
-
-    pub async fn is_valid(&self) -> Result<bool, (Status, Json<ErrorBody>)> {
-        let pool = db_pool().await;
-
-        let from_wallet = sqlx::query_as::<_, Wallet>(
-        r#"
-                SELECT *
-                FROM wallets
-                WHERE address = ?;
-                "#,
-            )
-            .bind(&self.from_address)
-            .fetch_optional(&pool)
-            .await
-            .unwrap_or_else(|e| {
-                error!("failed to get block: {}", e);
-                panic!("failed to get block");
-            });
-        let from_wallet = match from_wallet {
+    /// Unsigned transaction moving `amount` (plus `fee` for the miner) between two addresses,
+    /// the sender's `nonce`-th.
+    pub fn new(from_address: String, to_address: String, amount: i32, fee: i32, nonce: i64) -> Self {
+        Transaction {
+            from_address,
+            to_address,
+            amount,
+            sig: None,
+            added_to_block: None,
+            created_at: None,
+            block_id: None,
+            fee,
+            nonce,
+        }
+    }
+
+    /// Sign the transaction with the sender's hex encoded secret key.
+    pub fn sign(&mut self, secret_key: &str) -> Result<(), String> {
+        self.sig = Some(crypto::sign(secret_key, &self.signing_payload())?);
+        Ok(())
+    }
+
+    /// Transaction paying the block reward to the miner. It has no sender, so nothing is debited.
+    pub fn coinbase(to_address: String, amount: i32, height: i32, timestamp: f64) -> Self {
+        Transaction {
+            from_address: String::new(),
+            to_address,
+            amount,
+            sig: Some(format!("coinbase:{}", height)),
+            added_to_block: Some(true),
+            created_at: Some(timestamp),
+            block_id: None,
+            fee: 0,
+            nonce: 0,
+        }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.from_address.is_empty()
+    }
+
+    pub async fn is_valid(&self, store: &dyn ChainStore) -> Result<bool, ApiError> {
+        // Stores debit the amount and the fee together, queued transactions included
+        if self.is_coinbase() || self.amount < 0 || self.fee < 0 || self.amount.checked_add(self.fee).is_none() {
+            return Ok(false);
+        }
+
+        // Reject typos in addresses before touching the database
+        for address in [&self.from_address, &self.to_address] {
+            crypto::validate_address(address)
+                .map_err(ApiError::InvalidInput)?;
+        }
+
+        let from_wallet = match store.wallet(&self.from_address).await? {
             Some(wallet) => wallet,
             None => return Ok(false),
         };
-        if self.amount > from_wallet.balance {
-            return Ok(false);
+
+        // The sender must control the key the from address was derived from
+        if crypto::address_from_public_key(&from_wallet.pub_key).ok().as_ref() != Some(&from_wallet.address) {
+            return Err(ApiError::InvalidInput(
+                "from address is not derived from the wallet's public key".to_string(),
+            ));
         }
 
-        let to_exists: i64 = sqlx::query_scalar(
-            r#"
-            SELECT EXISTS(
-                SELECT 1
-                FROM wallets
-                WHERE address = ?
-            );
-            "#,
-        )
-        .bind(&self.to_address)
-        .fetch_one(&pool)
-        .await
-        .map_err(|e| {
-            error!("failed to check to wallet: {}", e);
-            error_response!(Status::InternalServerError, "failed to check to wallet")
-        })?;
-        if to_exists == 0 {
+        let sig = match self.sig.as_deref() {
+            Some(sig) if !sig.is_empty() => sig,
+            _ => return Err(ApiError::InvalidInput("transaction is not signed".to_string())),
+        };
+        crypto::verify(&from_wallet.pub_key, &self.signing_payload(), sig)
+            .map_err(|e| ApiError::InvalidInput(format!("invalid transaction signature: {}", e)))?;
+
+        // A signed transaction can only be accepted once, at the sender's next nonce. Later
+        // nonces wait in the mempool's queue and their balance is checked when their turn comes.
+        if self.nonce < from_wallet.nonce {
+            return Err(ApiError::Conflict(format!(
+                "nonce {} was already used, the next nonce of {} is {}",
+                self.nonce, self.from_address, from_wallet.nonce
+            )));
+        }
+        if self.nonce == from_wallet.nonce && self.amount + self.fee > from_wallet.balance {
             return Ok(false);
         }
 
-        if self.amount < 0 {
+        if store.wallet(&self.to_address).await?.is_none() {
             return Ok(false);
         }
 
         Ok(true)
     }
 
+    /// Canonical bytes covered by the sender's signature. Addresses are length prefixed and
+    /// numbers big endian so that two different transactions never share a payload.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for field in [self.from_address.as_bytes(), self.to_address.as_bytes()] {
+            payload.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            payload.extend_from_slice(field);
+        }
+        payload.extend_from_slice(&self.amount.to_be_bytes());
+        payload.extend_from_slice(&self.fee.to_be_bytes());
+        payload.extend_from_slice(&self.nonce.to_be_bytes());
+        payload
+    }
+
+    /// Canonical bytes of the signed transaction: the signing payload followed by the length
+    /// prefixed signature.
+    pub fn signed_payload(&self) -> Vec<u8> {
+        let mut payload = self.signing_payload();
+        let sig = self.sig.as_deref().unwrap_or_default().as_bytes();
+        payload.extend_from_slice(&(sig.len() as u32).to_be_bytes());
+        payload.extend_from_slice(sig);
+        payload
+    }
+
+    /// Identifier of the transaction, the hex encoded SHA-256 of its signed payload.
+    pub fn txid(&self) -> String {
+        hex::encode(Sha256::digest(self.signed_payload()))
+    }
+
+    /// Size in bytes counted against the block size limit.
+    pub fn size(&self) -> usize {
+        self.signed_payload().len()
+    }
+
     pub fn to_json(&self) -> String {
         serde_json::to_string(self).unwrap()
     }
 }
 
+impl Wallet {
+    /// Nonce the next transaction sent from `address` must carry, 0 for an unknown address.
+    pub async fn next_nonce(store: &dyn ChainStore, address: &str) -> Result<i64, ApiError> {
+        Ok(store.wallet(address).await?.map_or(0, |wallet| wallet.nonce))
+    }
+}
+
 // impl Wallet {
 //     pub fn new() -> Self {
 //         todo!()
@@ -100,104 +230,176 @@ impl Transaction {
 
 
 #[post("/tx", data="<transaction>")]
-async fn create_transaction(transaction: Json<Transaction>) ->ApiResult<Transaction> {
-    let pool = db_pool().await;
-    let is_valid_tx = transaction.is_valid().await?;
-    if !is_valid_tx {
-        return Err(error_response!(Status::NotFound, "transaction not valid"))
-    }
-
-    // Calculate transaction hash (will bre raplaced with signateure calculated with private key)
-    let mut hasher = Sha256::new();
-    hasher.update(&transaction.from_address);
-    hasher.update(&transaction.to_address);
-    hasher.update(transaction.amount.to_string());
-    let signature = format!("{:x}", hasher.finalize());
-
-    // Reduce from_wallet and increase to_wallet by the same amount
-    let from_result = sqlx::query(
-        r#"
-        UPDATE wallets
-        SET balance = balance - ?
-        WHERE address = ?
-        RETURNING *;
-        "#,
-    )
-    .bind(&transaction.amount)
-    .bind(&transaction.from_address)
-    .execute(&pool)
-    .await
-    .map_err(|e| {
-        error!("failed to update from wallet: {}", e);
-        error_response!(Status::InternalServerError, "failed to update from wallet")
-    })?;
-    if from_result.rows_affected() == 0 {
-        return Err(error_response!(Status::NotFound, "from wallet not found"));
-    }
-
-    let to_result = sqlx::query(
-        r#"
-        UPDATE wallets
-        SET balance = balance + ?
-        WHERE address = ?
-        RETURNING *;
-        "#,
-    )
-    .bind(&transaction.amount)
-    .bind(&transaction.to_address)
-    .execute(&pool)
-    .await
-    .map_err(|e| {
-        error!("failed to update to wallet: {}", e);
-        error_response!(Status::InternalServerError, "failed to update to wallet")
-    })?;
-    if to_result.rows_affected() == 0 {
-        return Err(error_response!(Status::NotFound, "to wallet not found"));
-    }
-
-    
-    // Create transaction
-    let transaction: Transaction = sqlx::query_as::<_, Transaction>(
-        r#"
-        INSERT INTO transactions (from_address, to_address, amount, sig, added_to_block)
-        VALUES (?, ?, ?, ?, ?)
-        RETURNING from_address, to_address, amount, sig, added_to_block, created_at, block_id;
-        "#
-    )
-    .bind(&transaction.from_address)
-    .bind(&transaction.to_address)
-    .bind(transaction.amount)
-    .bind(signature)
-    .bind(false)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or_else(|e| {
-        error!("failed to get transaction: {}", e);
-        panic!("failed to get transaction");
-    });
+async fn create_transaction(
+    store: &State<SharedStore>,
+    mempool: &State<SharedMempool>,
+    network: &State<SharedNetwork>,
+    transaction: Json<Transaction>,
+) -> ApiResult<Transaction> {
+    let transaction = submit_transaction(store.as_ref(), mempool, network, transaction.into_inner()).await?;
 
     Ok(Json(transaction))
 }
 
+/// Validate a transaction submitted to this node and accept it, or queue it if its nonce is
+/// ahead of its sender's. Every transaction accepted as a result is announced to the peers.
+pub async fn submit_transaction(
+    store: &dyn ChainStore,
+    mempool: &SharedMempool,
+    network: &Network,
+    transaction: Transaction,
+) -> Result<Transaction, ApiError> {
+    if !transaction.is_valid(store).await? {
+        return Err(ApiError::InvalidInput("transaction not valid".to_string()));
+    }
+
+    if transaction.nonce > Wallet::next_nonce(store, &transaction.from_address).await? {
+        mempool.queue(transaction.clone())?;
+        return Ok(transaction);
+    }
+
+    let accepted = accept_transaction(store, mempool, transaction).await?;
+    network.announce(InvItem::tx(&accepted.txid()));
+    for promoted in promote_queued(store, mempool, &accepted.from_address).await? {
+        network.announce(InvItem::tx(&promoted.txid()));
+    }
+
+    Ok(accepted)
+}
+
+/// Apply a validated transaction at its sender's next nonce and add it to the mempool.
+pub async fn accept_transaction(
+    store: &dyn ChainStore,
+    mempool: &SharedMempool,
+    transaction: Transaction,
+) -> Result<Transaction, ApiError> {
+    let txid = transaction.txid();
+    let (rowid, transaction) = store.accept_transaction(&transaction).await?;
+
+    // A full mempool evicts its lowest fee rates, possibly the transaction just accepted
+    mempool.insert(rowid, transaction.clone());
+    mempool.evict(store, mempool_max_txs(), mempool_expiry()).await?;
+    if !mempool.contains(&txid) {
+        return Err(ApiError::Conflict("mempool is full and the transaction fee rate is too low".to_string()));
+    }
+
+    Ok(transaction)
+}
+
+/// Accept the queued transactions of `address` whose nonce came up, returning them. A queued
+/// transaction that cannot be accepted yet, for lack of funds, stays queued.
+pub async fn promote_queued(
+    store: &dyn ChainStore,
+    mempool: &SharedMempool,
+    address: &str,
+) -> Result<Vec<Transaction>, ApiError> {
+    let mut promoted = Vec::new();
+    loop {
+        let nonce = Wallet::next_nonce(store, address).await?;
+        let transaction = match mempool.take_queued(address, nonce) {
+            Some(transaction) => transaction,
+            None => return Ok(promoted),
+        };
+
+        match accept_transaction(store, mempool, transaction.clone()).await {
+            Ok(accepted) => promoted.push(accepted),
+            Err(e) => {
+                warn!("queued transaction {} not accepted: {}", transaction.txid(), e);
+                if matches!(e, ApiError::Conflict(_)) {
+                    mempool.queue(transaction)?;
+                }
+                return Ok(promoted);
+            }
+        }
+    }
+}
+
+
+#[get("/tx/<txid>")]
+async fn get_transaction(
+    store: &State<SharedStore>,
+    mempool: &State<SharedMempool>,
+    txid: String,
+) -> ApiResult<TransactionInfo> {
+    validate_txid(&txid)?;
+
+    if let Some(transaction) = mempool.queued(&txid) {
+        return Ok(Json(TransactionInfo {
+            txid,
+            transaction,
+            status: TransactionStatus::Queued,
+            block_idx: None,
+            confirmations: 0,
+        }));
+    }
+
+    let transaction = store
+        .transaction(&txid)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("transaction {} not found", txid)))?;
+
+    let (status, confirmations) = match transaction.block_id {
+        Some(block_idx) => (TransactionStatus::Confirmed, store.height().await? - block_idx + 1),
+        None => (TransactionStatus::Pending, 0),
+    };
+
+    Ok(Json(TransactionInfo {
+        txid,
+        block_idx: transaction.block_id,
+        transaction,
+        status,
+        confirmations,
+    }))
+}
+
+#[get("/tx/<txid>/proof")]
+async fn get_transaction_proof(store: &State<SharedStore>, txid: String) -> ApiResult<TransactionProof> {
+    validate_txid(&txid)?;
+
+    let block_id = store
+        .transaction(&txid)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("transaction {} not found", txid)))?
+        .block_id
+        .ok_or_else(|| ApiError::Conflict(format!("transaction {} is not in a block yet", txid)))?;
+
+    // The leaves are built from the body, the transactions exactly as the block committed to them
+    let block = block_by_idx(store.as_ref(), block_id).await?;
+    let index = block
+        .body
+        .iter()
+        .position(|transaction| transaction.txid() == txid)
+        .ok_or_else(|| ApiError::Internal(format!("transaction {} missing from the body of block {}", txid, block_id)))?;
+    let leaves: Vec<String> = block.body.iter().map(merkle::leaf_hash).collect();
+    let branch = merkle::merkle_branch(&leaves, index)
+        .ok_or_else(|| ApiError::Internal(format!("no merkle branch for transaction {}", txid)))?;
+
+    Ok(Json(TransactionProof {
+        txid,
+        leaf: leaves[index].clone(),
+        index,
+        branch,
+        block_hash: block.hash,
+        header: block.header,
+    }))
+}
+
+fn validate_txid(txid: &str) -> Result<(), ApiError> {
+    if txid.len() != 64 || !txid.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return Err(ApiError::InvalidInput("txid must be 64 lowercase hex characters".to_string()));
+    }
+    Ok(())
+}
+
 
 #[get("/wallet/<address>")]
-async fn get_wallet_details(address: String) -> ApiResult<Wallet> {
-    let pool = db_pool().await;
-
-    let wallet = sqlx::query_as::<_, Wallet>(
-        r#"
-        SELECT *
-        FROM wallets
-        WHERE address = ?;
-        "#,
-    )
-    .bind(address)
-    .fetch_one(&pool)
-    .await
-    .unwrap_or_else(|e| {
-        error!("failed to get block: {}", e);
-        panic!("failed to get block");
-    });
+async fn get_wallet_details(store: &State<SharedStore>, address: String) -> ApiResult<Wallet> {
+    crypto::validate_address(&address).map_err(ApiError::InvalidInput)?;
+
+    let wallet = store
+        .wallet(&address)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("wallet {} not found", address)))?;
 
     Ok(Json(wallet))
 }
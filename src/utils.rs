@@ -1,11 +1,17 @@
-use rocket::error;
-use sqlx::sqlite::SqlitePoolOptions;
-use sqlx::SqlitePool;
+use rocket::{catch, catchers, error, Request};
 use rocket::http::Status;
 use rocket::serde::json::Json;
 use serde::Serialize;
 use futures::TryStreamExt;
-use sqlx::Row;
+use std::collections::{HashMap, VecDeque};
+use crate::consensus::ConsensusEngine;
+use crate::crypto;
+use crate::store::ChainStore;
+use crate::transactions::Transaction;
+use crate::blockchain::{
+    Block, BlockHeader, check_coinbase, check_timestamp, check_version, is_retarget_height, meets_difficulty, now_millis, retarget,
+    retarget_interval, retarget_window_start, target_block_time, BLOCK_VERSION, HEADERLESS_BLOCK_VERSION,
+};
 
 
 #[derive(Debug, Serialize)]
@@ -32,88 +38,407 @@ macro_rules! error_response {
 
 pub type ApiResult<T> = Result<Json<T>, (Status, Json<ErrorBody>)>;
 
-pub async fn db_pool() -> SqlitePool {
-    let database_url =
-        std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://database.sqlite".to_string());
-
-    SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await
-        .unwrap_or_else(|e| {
-            error!("failed to connect to SQLite at {}: {}", database_url, e);
-            panic!("failed to connect to SQLite");
-        })
+/// Errors returned by the library and the request handlers. Handlers turn them into an
+/// `ErrorBody` with the matching status code through `?`.
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(String),
+    InvalidInput(String),
+    Conflict(String),
+    Internal(String),
 }
 
+impl ApiError {
+    /// Log the underlying error and keep only `context` for the client.
+    pub fn internal(context: &str, e: impl std::fmt::Display) -> Self {
+        error!("{}: {}", context, e);
+        ApiError::Internal(context.to_string())
+    }
 
-/// Memory-efficient DB verification: PRAGMA integrity_check + streaming block linkage check.
-/// Does not load all blocks into memory.
-pub async fn verify_db_state_streaming() -> Result<(), String> {
-    let pool = db_pool().await;
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::NotFound(_) => Status::NotFound,
+            ApiError::InvalidInput(_) => Status::BadRequest,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::Internal(_) => Status::InternalServerError,
+        }
+    }
 
-    // 1) SQLite integrity check (note: this may still be slow on very large DBs)
-    let integrity_row = sqlx::query("PRAGMA integrity_check;")
-        .fetch_one(&pool)
-        .await
-        .map_err(|e| format!("PRAGMA integrity_check query failed: {}", e))?;
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(msg)
+            | ApiError::InvalidInput(msg)
+            | ApiError::Conflict(msg)
+            | ApiError::Internal(msg) => msg,
+        }
+    }
+}
 
-    let integrity_result: String = integrity_row
-        .try_get(0)
-        .map_err(|e| format!("failed to read integrity_check result: {}", e))?;
+/// Whether a query failed on a UNIQUE constraint.
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error().is_some_and(|e| e.is_unique_violation())
+}
 
-    if integrity_result.to_lowercase() != "ok" {
-        return Err(format!("sqlite integrity_check failed: {}", integrity_result));
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message())
     }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<ApiError> for (Status, Json<ErrorBody>) {
+    fn from(e: ApiError) -> Self {
+        error_response!(e.status(), e.message())
+    }
+}
+
+pub fn catchers() -> Vec<rocket::Catcher> {
+    catchers![bad_request, not_found, unprocessable_entity, internal_error]
+}
+
+#[catch(400)]
+fn bad_request() -> Json<ErrorBody> {
+    Json(ErrorBody { message: "bad request".to_string() })
+}
+
+#[catch(404)]
+fn not_found(req: &Request) -> Json<ErrorBody> {
+    Json(ErrorBody { message: format!("no route for {}", req.uri()) })
+}
+
+#[catch(422)]
+fn unprocessable_entity(req: &Request) -> Json<ErrorBody> {
+    Json(ErrorBody { message: format!("could not parse the parameters or body of {}", req.uri()) })
+}
+
+#[catch(500)]
+fn internal_error() -> Json<ErrorBody> {
+    Json(ErrorBody { message: "internal server error".to_string() })
+}
+
+/// Memory-efficient DB verification: storage integrity check + streaming block linkage check.
+/// Does not load all blocks into memory.
+pub async fn verify_db_state_streaming(store: &dyn ChainStore, consensus: &dyn ConsensusEngine) -> Result<(), String> {
+    // 1) Storage integrity check (note: this may still be slow on very large DBs)
+    store.check_integrity().await?;
 
     // 2) Streaming application-level chain linkage check
-    // Iterate rows ordered by idx, one row at a time (no fetch_all).
-    let mut stream = sqlx::query!(
-        r#"
-        SELECT idx, hash, previous_hash
-        FROM blocks
-        ORDER BY idx ASC
-        "#
-    )
-    .fetch(&pool);
-
-    let mut expected_idx: i64 = 1;
-    let mut last_hash = String::new();
-
-    while let Some(row) = stream
+    // Iterate blocks ordered by idx, one at a time (no fetch_all).
+    let mut stream = store.block_summaries();
+
+    let mut checker = ChainChecker::new(consensus);
+
+    while let Some(summary) = stream
         .try_next()
         .await
         .map_err(|e| format!("failed reading blocks during verification: {}", e))?
     {
-        let idx = row.idx;
-        let hash = row.hash;
-        let previous_hash = row.previous_hash;
+        checker.check_header(&summary.header, &summary.hash)?;
+        check_coinbase(summary.header.idx as i64, summary.coinbase_count, summary.coinbase_amount, summary.fees)?;
+    }
+
+    Ok(())
+}
+
+/// Consensus rules that only need block headers, shared by the streaming and the deep
+/// verification. Keeps just enough state to check the next block.
+struct ChainChecker<'a> {
+    consensus: &'a dyn ConsensusEngine,
+    expected_idx: i64,
+    last_hash: String,
+    last_header: Option<BlockHeader>,
+    // (idx, timestamp) of the blocks that can still open a retarget window
+    window: VecDeque<(i64, i64)>,
+}
 
-        if idx != expected_idx {
+impl<'a> ChainChecker<'a> {
+    fn new(consensus: &'a dyn ConsensusEngine) -> Self {
+        ChainChecker { consensus, expected_idx: 1, last_hash: String::new(), last_header: None, window: VecDeque::new() }
+    }
+
+    fn check_header(&mut self, header: &BlockHeader, hash: &str) -> Result<(), String> {
+        let (idx, timestamp, previous_hash, difficulty) =
+            (header.idx as i64, header.timestamp, header.previous_hash.as_str(), header.difficulty as i64);
+        if idx != self.expected_idx {
             return Err(format!(
                 "block index mismatch at row with idx {}: expected {}",
-                idx, expected_idx
+                idx, self.expected_idx
             ));
         }
 
-        if expected_idx == 1 {
-            // genesis block expected previous_hash == "0"
-            if previous_hash != "" {
+        if self.expected_idx == 1 {
+            // genesis block expected previous_hash == ""
+            if !previous_hash.is_empty() {
                 return Err(format!(
-                    "genesis block previous_hash invalid: got '{}', expected '0'",
+                    "genesis block previous_hash invalid: got '{}', expected ''",
                     previous_hash
                 ));
             }
-        } else if previous_hash != last_hash {
+        } else if previous_hash != self.last_hash {
             return Err(format!(
                 "previous_hash mismatch at idx {}: expected '{}', got '{}'",
-                idx, last_hash, previous_hash
+                idx, self.last_hash, previous_hash
+            ));
+        }
+
+        check_version(idx, header.version, self.last_header.as_ref().map(|parent| parent.version))?;
+        // The header migration truncated version 0 timestamps and gave the blocks mined before
+        // retargeting a default difficulty: only the proof of work of their stored hash is left
+        let headerless = header.version == HEADERLESS_BLOCK_VERSION;
+
+        let last_difficulty = self.last_header.as_ref().map_or(0, |last| last.difficulty as i64);
+        let expected_difficulty = if self.expected_idx == 1 || headerless {
+            difficulty
+        } else if self.consensus.retargets() && is_retarget_height(idx) {
+            let start = retarget_window_start(idx);
+            let (parent_idx, parent_timestamp) = *self.window.back().unwrap();
+            let start_timestamp = self
+                .window
+                .iter()
+                .find(|(i, _)| *i == start)
+                .map(|(_, ts)| *ts)
+                .ok_or_else(|| format!("retarget window start {} missing at idx {}", start, idx))?;
+            retarget(
+                last_difficulty as i32,
+                (parent_timestamp - start_timestamp) as f64 / 1000.0,
+                (parent_idx - start) as f64 * target_block_time(),
+            ) as i64
+        } else {
+            last_difficulty
+        };
+
+        if difficulty != expected_difficulty {
+            return Err(format!(
+                "difficulty mismatch at idx {}: expected {}, got {}",
+                idx, expected_difficulty, difficulty
+            ));
+        }
+
+        // The genesis block and legacy blocks keep the proof of work they were created with, whatever the engine
+        match self.last_header.as_ref().filter(|_| header.version == BLOCK_VERSION) {
+            Some(parent) => self
+                .consensus
+                .verify_seal(header, hash, parent)
+                .map_err(|e| format!("invalid seal at idx {}: {}", idx, e))?,
+            None if !meets_difficulty(hash, difficulty as i32) => {
+                return Err(format!(
+                    "hash at idx {} does not meet difficulty {}: '{}'",
+                    idx, difficulty, hash
+                ));
+            }
+            None => {}
+        }
+        if !headerless {
+            check_timestamp(idx, timestamp, self.last_header.as_ref().map(|parent| parent.timestamp), now_millis())?;
+        }
+
+        self.window.push_back((idx, timestamp));
+        while self.window.len() as i64 > retarget_interval() + 1 {
+            self.window.pop_front();
+        }
+
+        self.last_hash = hash.to_string();
+        self.last_header = Some(header.clone());
+        self.expected_idx += 1;
+        Ok(())
+    }
+}
+
+const VERIFY_PAGE_SIZE: i64 = 500; // Blocks loaded at once by the deep verification
+const MAX_REPORTED_MISMATCHES: usize = 100;
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerificationReport {
+    pub blocks_verified: i64,
+    pub transactions_verified: i64,
+    /// First block that fails verification, later blocks are not checked
+    pub first_bad_block: Option<BadBlock>,
+    /// Wallets whose stored balance differs from the replayed one (at most 100 are listed)
+    pub balance_mismatches: Vec<BalanceMismatch>,
+    pub balance_mismatch_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BadBlock {
+    pub idx: i64,
+    pub hash: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BalanceMismatch {
+    pub address: String,
+    pub expected: i64,
+    pub actual: i64,
+}
+
+impl VerificationReport {
+    pub fn is_valid(&self) -> bool {
+        self.first_bad_block.is_none() && self.balance_mismatch_count == 0
+    }
+
+    fn record_mismatch(&mut self, address: String, expected: i64, actual: i64) {
+        self.balance_mismatch_count += 1;
+        if self.balance_mismatches.len() < MAX_REPORTED_MISMATCHES {
+            self.balance_mismatches.push(BalanceMismatch { address, expected, actual });
+        }
+    }
+}
+
+/// Deep verification: on top of the streaming checks, recompute every block hash and Merkle root
+/// from the stored rows and replay all transactions from genesis to check the wallet balances and
+/// that every sender used its nonces in sequence.
+/// Blocks are loaded a page at a time so memory stays bounded by the page size and the number of
+/// wallets. `Err` is only returned when the database cannot be read.
+pub async fn verify_chain_deep(store: &dyn ChainStore, consensus: &dyn ConsensusEngine) -> Result<VerificationReport, String> {
+    let mut report = VerificationReport::default();
+    let mut checker = ChainChecker::new(consensus);
+
+    // Balances before any transaction: the allocation each wallet was created with
+    let wallets = store
+        .wallets()
+        .await
+        .map_err(|e| format!("failed reading wallets during verification: {}", e))?;
+    let mut balances: HashMap<String, i64> =
+        wallets.iter().map(|wallet| (wallet.address.clone(), wallet.initial_balance as i64)).collect();
+
+    // Next nonce of every sender
+    let mut nonces: HashMap<String, i64> = HashMap::new();
+
+    let mut last_idx: i32 = 0;
+    loop {
+        let blocks = store
+            .blocks_after(last_idx, VERIFY_PAGE_SIZE)
+            .await
+            .map_err(|e| format!("failed reading blocks during verification: {}", e))?;
+
+        let (first, last) = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) => (first.header.idx, last.header.idx),
+            _ => break,
+        };
+
+        // Coinbase first, then in insertion order: the order the Merkle tree was built in
+        let transactions = store
+            .transactions_in_blocks(first, last)
+            .await
+            .map_err(|e| format!("failed reading transactions during verification: {}", e))?;
+
+        let mut transactions = transactions.into_iter().peekable();
+        for block in blocks {
+            let mut block_transactions = Vec::new();
+            while let Some(tx) = transactions.next_if(|tx| tx.block_id == Some(block.header.idx)) {
+                block_transactions.push(tx);
+            }
+
+            let tx_count = block_transactions.len() as i64;
+            if let Err(reason) = check_block_deep(&mut checker, &block, block_transactions, &mut balances, &mut nonces) {
+                report.first_bad_block = Some(BadBlock { idx: block.header.idx as i64, hash: block.hash, reason });
+                return Ok(report);
+            }
+            report.blocks_verified += 1;
+            report.transactions_verified += tx_count;
+        }
+
+        last_idx = last;
+    }
+
+    // Pending transactions already moved funds when they were accepted
+    let pending = store
+        .pending_transactions()
+        .await
+        .map_err(|e| format!("failed reading pending transactions during verification: {}", e))?;
+    for (_, tx) in &pending {
+        apply_transaction(&mut balances, tx);
+    }
+
+    let wallets = store
+        .wallets()
+        .await
+        .map_err(|e| format!("failed reading wallets during verification: {}", e))?;
+    for wallet in wallets {
+        let (address, actual) = (wallet.address, wallet.balance as i64);
+        let expected = balances.remove(&address).unwrap_or(0);
+        if expected != actual {
+            report.record_mismatch(address, expected, actual);
+        }
+    }
+    // Funds replayed to addresses that have no wallet row
+    for (address, expected) in balances {
+        if expected != 0 {
+            report.record_mismatch(address, expected, 0);
+        }
+    }
+
+    Ok(report)
+}
+
+fn check_block_deep(
+    checker: &mut ChainChecker,
+    block: &Block,
+    transactions: Vec<Transaction>,
+    balances: &mut HashMap<String, i64>,
+    nonces: &mut HashMap<String, i64>,
+) -> Result<(), String> {
+    let header = &block.header;
+    let idx = header.idx as i64;
+    checker.check_header(header, &block.hash)?;
+
+    // The fields a version 0 hash covered were rewritten by the header migration
+    let headerless = header.version == HEADERLESS_BLOCK_VERSION;
+    let recomputed = block.calculate_hash();
+    if !headerless && recomputed != block.hash {
+        return Err(format!("hash mismatch at idx {}: stored '{}', recomputed '{}'", idx, block.hash, recomputed));
+    }
+
+    let coinbases: Vec<&Transaction> = transactions.iter().filter(|tx| tx.is_coinbase()).collect();
+    let fees: i64 = transactions.iter().filter(|tx| !tx.is_coinbase()).map(|tx| tx.fee as i64).sum();
+    let coinbase_amount: i64 = coinbases.iter().map(|tx| tx.amount as i64).sum();
+    check_coinbase(idx, coinbases.len() as i64, coinbase_amount, fees)?;
+    // Coinbases of version 0 blocks may predate addresses derived from public keys
+    for coinbase in coinbases.iter().filter(|_| !headerless) {
+        crypto::validate_address(&coinbase.to_address).map_err(|e| format!("coinbase at idx {} pays an invalid address: {}", idx, e))?;
+    }
+
+    for tx in &transactions {
+        if !tx.is_coinbase() {
+            let next_nonce = nonces.entry(tx.from_address.clone()).or_insert(0);
+            if tx.nonce != *next_nonce {
+                return Err(format!(
+                    "nonce mismatch at idx {}: {} used nonce {}, expected {}",
+                    idx, tx.from_address, tx.nonce, next_nonce
+                ));
+            }
+            *next_nonce += 1;
+        }
+
+        apply_transaction(balances, tx);
+        if !tx.is_coinbase() && balances.get(&tx.from_address).copied().unwrap_or(0) < 0 {
+            return Err(format!(
+                "wallet {} balance goes negative at idx {} when replaying transactions",
+                tx.from_address, idx
             ));
         }
+    }
 
-        last_hash = hash;
-        expected_idx += 1;
+    // Older Merkle trees hashed the JSON of the transactions, which no longer round-trips
+    if header.version != BLOCK_VERSION {
+        return Ok(());
+    }
+    let merkle_root = Block::merkle_root(&transactions);
+    if merkle_root != header.merkle_root {
+        return Err(format!("merkle root mismatch at idx {}: recomputed '{}'", idx, merkle_root));
+    }
+    if Block::merkle_root(&block.body) != header.merkle_root {
+        return Err(format!("block body at idx {} does not match its merkle root", idx));
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn apply_transaction(balances: &mut HashMap<String, i64>, tx: &Transaction) {
+    if !tx.is_coinbase() {
+        *balances.entry(tx.from_address.clone()).or_insert(0) -= tx.amount as i64 + tx.fee as i64;
+    }
+    *balances.entry(tx.to_address.clone()).or_insert(0) += tx.amount as i64;
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::chain;
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::crypto::{address_from_public_key, generate_keypair};
+use rocket::http::Status;
+use std::sync::Arc;
+
+
+#[rocket::async_test]
+async fn missing_blocks_and_wallets_are_not_found() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let client = node.client().await;
+    let unknown = address_from_public_key(&generate_keypair().1).unwrap();
+
+    for (uri, message) in [
+        ("/chain/999999".to_string(), "block 999999 not found".to_string()),
+        ("/chain/999999/txs".to_string(), "block 999999 not found".to_string()),
+        (format!("/wallet/{}", unknown), format!("wallet {} not found", unknown)),
+    ] {
+        let response = client.get(&uri).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound, "{}", uri);
+        let body: serde_json::Value = response.into_json().await.unwrap();
+        assert_eq!(body["message"], message.as_str(), "{}", uri);
+    }
+
+    // The genesis block is there
+    assert_eq!(client.get("/chain/1").dispatch().await.status(), Status::Ok);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{chain, chain_on, tamper_block, tamperable_store};
+use my_rust_blockchain::blockchain::{block_by_idx, HEADERLESS_BLOCK_VERSION, JSON_MERKLE_BLOCK_VERSION};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::miner::Miner;
+use my_rust_blockchain::store::SqliteStore;
+use my_rust_blockchain::utils::{verify_chain_deep, ApiError};
+use std::sync::Arc;
+
+
+const CHAIN_LENGTH: i32 = 4;
+
+async fn mine_chain(store: &Arc<SqliteStore>) {
+    let chain = chain_on(Arc::clone(store) as _, Arc::new(ProofOfWork)).await;
+    while chain.store.height().await.unwrap() < CHAIN_LENGTH {
+        chain.mine().await.unwrap();
+    }
+}
+
+/// Relink the block at `idx` to its (possibly rewritten) parent and redo its proof of work.
+async fn remine(store: &SqliteStore, miner: &Miner, idx: i32) {
+    let mut block = block_by_idx(store, idx).await.unwrap();
+    let parent = block_by_idx(store, idx - 1).await.unwrap();
+    block.header.previous_hash = parent.hash;
+    assert!(block.seal(&ProofOfWork, &parent.header, miner, miner.tip()));
+
+    let assignments = format!(
+        "previous_hash = '{}', timestamp = {}, nonce = {}, hash = '{}'",
+        block.header.previous_hash, block.header.timestamp, block.header.nonce, block.hash
+    );
+    tamper_block(store, idx, &assignments).await;
+}
+
+async fn first_bad_block(store: &SqliteStore) -> Option<(i64, String)> {
+    let report = verify_chain_deep(store, &ProofOfWork).await.unwrap();
+    report.first_bad_block.map(|bad| (bad.idx, bad.reason))
+}
+
+#[rocket::async_test]
+async fn tampering_with_a_block_invalidates_every_later_block() {
+    let store = tamperable_store().await;
+    let miner = Miner::from_env();
+    mine_chain(&store).await;
+
+    // The stored index and parent are the ones the proof of work committed to
+    let mut previous_hash = String::new();
+    for idx in 1..=CHAIN_LENGTH {
+        let block = block_by_idx(store.as_ref(), idx).await.unwrap();
+        assert_eq!(block.header.idx, idx);
+        assert_eq!(block.header.previous_hash, previous_hash);
+        assert_eq!(block.calculate_hash(), block.hash);
+        previous_hash = block.hash;
+    }
+    assert_eq!(first_bad_block(&store).await, None);
+
+    tamper_block(&store, 2, "timestamp = timestamp + 1").await;
+    let (idx, reason) = first_bad_block(&store).await.expect("tampered block accepted");
+    assert_eq!(idx, 2);
+    assert!(reason.starts_with("hash mismatch"), "{}", reason);
+
+    // Redoing the proof of work of the tampered block only moves the failure to its child,
+    // every later block has to be mined again before the chain verifies
+    for idx in 2..CHAIN_LENGTH {
+        remine(&store, &miner, idx).await;
+        let (bad_idx, reason) = first_bad_block(&store).await.expect("broken link accepted");
+        assert_eq!(bad_idx, idx as i64 + 1);
+        assert!(reason.starts_with("previous_hash mismatch"), "{}", reason);
+    }
+
+    remine(&store, &miner, CHAIN_LENGTH).await;
+    assert_eq!(first_bad_block(&store).await, None);
+}
+
+#[rocket::async_test]
+async fn legacy_blocks_are_verified_with_the_rules_of_their_version() {
+    let store = tamperable_store().await;
+    let miner = Miner::from_env();
+    mine_chain(&store).await;
+
+    // A chain upgraded from version 0, then 1: neither stored Merkle root matches today's tree
+    // and the version 0 hash covered fields the header migration rewrote
+    let legacy_root = "ab".repeat(32);
+    tamper_block(&store, 1, &format!("version = {}, merkle_root = '{}'", HEADERLESS_BLOCK_VERSION, legacy_root)).await;
+    tamper_block(&store, 2, &format!("version = {}, merkle_root = '{}'", JSON_MERKLE_BLOCK_VERSION, legacy_root)).await;
+    for idx in 2..=CHAIN_LENGTH {
+        remine(&store, &miner, idx).await;
+    }
+    assert_eq!(first_bad_block(&store).await, None);
+
+    // Unknown versions, and legacy versions after a current block, are not
+    tamper_block(&store, CHAIN_LENGTH, "version = 3").await;
+    remine(&store, &miner, CHAIN_LENGTH).await;
+    let (idx, reason) = first_bad_block(&store).await.expect("unknown version accepted");
+    assert_eq!(idx, CHAIN_LENGTH as i64);
+    assert!(reason.starts_with("unknown block version 3"), "{}", reason);
+
+    tamper_block(&store, CHAIN_LENGTH, &format!("version = {}", JSON_MERKLE_BLOCK_VERSION)).await;
+    remine(&store, &miner, CHAIN_LENGTH).await;
+    let (idx, reason) = first_bad_block(&store).await.expect("legacy version after a current one accepted");
+    assert_eq!(idx, CHAIN_LENGTH as i64);
+    assert!(reason.starts_with(&format!("block version at idx {} goes back", CHAIN_LENGTH)), "{}", reason);
+}
+
+#[rocket::async_test]
+async fn peers_only_send_blocks_of_the_current_version() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let (mut block, head) = node.template().await;
+    block.header.version = JSON_MERKLE_BLOCK_VERSION;
+    let miner = Miner::new(1);
+    assert!(block.seal(&ProofOfWork, &head.header, &miner, miner.tip()));
+    assert!(matches!(node.import(block).await, Err(ApiError::InvalidInput(_))));
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::{chain, wallet, TestChain};
+use my_rust_blockchain::blockchain::{block_subsidy, Block};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::crypto::{address_from_public_key, generate_keypair};
+use my_rust_blockchain::miner::Miner;
+use my_rust_blockchain::transactions::Transaction;
+use my_rust_blockchain::utils::ApiError;
+use std::sync::Arc;
+
+
+/// Block on top of the head whose coinbase pays `amount` to a new address, sealed as a peer would.
+async fn block_paying(chain: &TestChain, to: &str, amount: i32) -> Block {
+    let (mut block, head) = chain.template().await;
+    let created_at = block.header.timestamp as f64 / 1000.0;
+    block.body = vec![Transaction::coinbase(to.to_string(), amount, block.header.idx - 1, created_at)];
+    block.header.merkle_root = Block::merkle_root(&block.body);
+    let miner = Miner::new(1);
+    assert!(block.seal(&ProofOfWork, &head.header, &miner, miner.tip()));
+    block
+}
+
+#[rocket::async_test]
+async fn peer_blocks_cannot_mint_or_burn_coins() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let to = address_from_public_key(&generate_keypair().1).unwrap();
+    let subsidy = block_subsidy(1);
+
+    // Neither more than the subsidy (there are no fees to collect) nor a debit of the recipient
+    for amount in [subsidy + 1, -10] {
+        let block = block_paying(&node, &to, amount).await;
+        assert!(matches!(node.import(block).await, Err(ApiError::InvalidInput(_))), "coinbase of {} accepted", amount);
+        assert_eq!(node.store.height().await.unwrap(), 1);
+        assert!(node.store.wallet(&to).await.unwrap().is_none());
+    }
+
+    // Nor to a recipient that is not an address
+    for to in ["miner", &to[1..]] {
+        let block = block_paying(&node, to, subsidy).await;
+        assert!(matches!(node.import(block).await, Err(ApiError::InvalidInput(_))), "coinbase to {} accepted", to);
+        assert_eq!(node.store.height().await.unwrap(), 1);
+        assert!(node.store.wallet(to).await.unwrap().is_none());
+    }
+
+    let block = block_paying(&node, &to, subsidy).await;
+    node.import(block).await.unwrap();
+    assert_eq!(node.store.wallet(&to).await.unwrap().unwrap().balance, subsidy);
+}
+
+#[rocket::async_test]
+async fn block_transactions_list_the_coinbase_first() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let alice = wallet(node.store.as_ref(), 100).await;
+    let bob = wallet(node.store.as_ref(), 0).await.0;
+    node.submit(&alice, &bob, 10, 0).await.unwrap();
+
+    // The coinbase is stored after the transactions it collects the fees of
+    let (mut block, head) = node.template().await;
+    let to = address_from_public_key(&generate_keypair().1).unwrap();
+    let coinbase = Transaction::coinbase(to, block_subsidy(1) + 1, block.header.idx - 1, block.header.timestamp as f64 / 1000.0);
+    block.body.insert(0, coinbase);
+    block.header.merkle_root = Block::merkle_root(&block.body);
+    let miner = Miner::new(1);
+    assert!(block.seal(&ProofOfWork, &head.header, &miner, miner.tip()));
+    node.import(block.clone()).await.unwrap();
+
+    let committed: Vec<String> = block.body.iter().map(|tx| tx.txid()).collect();
+    let client = node.client().await;
+    let listed: Vec<Transaction> = client.get("/chain/2/txs").dispatch().await.into_json().await.unwrap();
+    assert_eq!(listed.iter().map(|tx| tx.txid()).collect::<Vec<_>>(), committed);
+}
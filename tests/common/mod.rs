@@ -0,0 +1,129 @@
+// Every test file compiles its own copy, using only some of the fixtures
+#![allow(dead_code)]
+
+use my_rust_blockchain::blockchain::{self, import_block, mine_block, Block, Blockchain, SharedBlockchain};
+use my_rust_blockchain::consensus::SharedConsensus;
+use my_rust_blockchain::crypto::{address_from_public_key, generate_keypair};
+use my_rust_blockchain::mempool::{self, Mempool, SharedMempool};
+use my_rust_blockchain::miner::{self, Miner};
+use my_rust_blockchain::p2p::Network;
+use my_rust_blockchain::store::{ChainStore, MemoryStore, SharedStore, SqliteStore};
+use my_rust_blockchain::transactions::{self, submit_transaction, Transaction, Wallet};
+use my_rust_blockchain::utils::{self, ApiError};
+use rocket::local::asynchronous::Client;
+use rocket::tokio::{runtime::Handle, task};
+use std::sync::{Arc, Mutex};
+
+
+/// Wallet funded with `balance`, returning its address and secret key.
+pub async fn wallet(store: &dyn ChainStore, balance: i32) -> (String, String) {
+    wallet_in(&[store], balance).await
+}
+
+/// The same funded wallet in every one of `stores`, as if they had synced it.
+pub async fn wallet_in(stores: &[&dyn ChainStore], balance: i32) -> (String, String) {
+    let (secret_key, pub_key) = generate_keypair();
+    let address = address_from_public_key(&pub_key).unwrap();
+    let wallet = Wallet { address: address.clone(), balance, pub_key, initial_balance: balance, nonce: 0 };
+    for store in stores {
+        store.put_wallet(&wallet).await.unwrap();
+    }
+    (address, secret_key)
+}
+
+/// Store for the tests that tamper with the chain. The chain code only ever stores valid blocks,
+/// so tampering goes through the rows themselves, below the store, with `tamper_block`.
+pub async fn tamperable_store() -> Arc<SqliteStore> {
+    Arc::new(SqliteStore::connect("sqlite::memory:", 1).await.unwrap())
+}
+
+/// Overwrite the row of the main chain block at `idx` with `assignments`, an SQL `SET` clause.
+pub async fn tamper_block(store: &SqliteStore, idx: i32, assignments: &str) {
+    let updated = sqlx::query(&format!("UPDATE blocks SET {} WHERE idx = ?;", assignments))
+        .bind(idx)
+        .execute(store.pool())
+        .await
+        .unwrap();
+    assert_eq!(updated.rows_affected(), 1, "no block at idx {}", idx);
+}
+
+/// Node without peers: its store, mempool and blockchain.
+pub struct TestChain {
+    pub store: SharedStore,
+    pub mempool: SharedMempool,
+    pub blockchain: SharedBlockchain,
+}
+
+/// Chain over a new memory store.
+pub async fn chain(consensus: SharedConsensus) -> TestChain {
+    chain_on(Arc::new(MemoryStore::new()), consensus).await
+}
+
+/// Chain over `store`, starting from the blocks and transactions it holds.
+pub async fn chain_on(store: SharedStore, consensus: SharedConsensus) -> TestChain {
+    let mempool = Arc::new(Mempool::load(store.as_ref()).await.unwrap());
+    let blockchain = Blockchain::new(Arc::clone(&store), consensus, Arc::new(Miner::new(1)), Arc::clone(&mempool), Arc::new(Network::disabled()))
+        .await
+        .unwrap();
+    TestChain { store, mempool, blockchain: Arc::new(Mutex::new(blockchain)) }
+}
+
+impl TestChain {
+    /// Submit a payment of `amount` plus a fee of 1 from the wallet `from`, returning its txid.
+    pub async fn submit(&self, from: &(String, String), to: &str, amount: i32, nonce: i64) -> Result<String, ApiError> {
+        let mut tx = Transaction::new(from.0.clone(), to.to_string(), amount, 1, nonce);
+        tx.sign(&from.1).unwrap();
+        Ok(submit_transaction(self.store.as_ref(), &self.mempool, &Network::disabled(), tx).await?.txid())
+    }
+
+    pub async fn mine(&self) -> Result<Block, ApiError> {
+        let blockchain = Arc::clone(&self.blockchain);
+        task::spawn_blocking(move || mine_block(&blockchain)).await.unwrap().map(|mined| mined.block)
+    }
+
+    pub async fn import(&self, block: Block) -> Result<Block, ApiError> {
+        let blockchain = Arc::clone(&self.blockchain);
+        task::spawn_blocking(move || import_block(&blockchain, block)).await.unwrap()
+    }
+
+    /// Unsealed block on top of the head, and the head it is to be sealed against.
+    pub async fn template(&self) -> (Block, Block) {
+        let blockchain = Arc::clone(&self.blockchain);
+        task::spawn_blocking(move || {
+            let blockchain = blockchain.lock().unwrap();
+            let block = Handle::current().block_on(blockchain.block_template()).unwrap();
+            (block, blockchain.blockchain_head.clone())
+        })
+        .await
+        .unwrap()
+    }
+
+    pub async fn balance(&self, address: &str) -> i32 {
+        self.store.wallet(address).await.unwrap().unwrap().balance
+    }
+
+    pub fn head(&self) -> Block {
+        self.blockchain.lock().unwrap().blockchain_head.clone()
+    }
+
+    /// Client of the node's routes over this chain, the way `main` mounts them.
+    pub async fn client(&self) -> Client {
+        let (consensus, miner, network) = {
+            let blockchain = self.blockchain.lock().unwrap();
+            (Arc::clone(&blockchain.consensus), Arc::clone(&blockchain.miner), Arc::clone(&blockchain.network))
+        };
+        let rocket = rocket::build()
+            .manage(Arc::clone(&self.store))
+            .manage(Arc::clone(&self.blockchain))
+            .manage(consensus)
+            .manage(miner)
+            .manage(Arc::clone(&self.mempool))
+            .manage(network)
+            .register("/", utils::catchers())
+            .mount("/", blockchain::routes())
+            .mount("/", transactions::routes())
+            .mount("/", miner::routes())
+            .mount("/", mempool::routes());
+        Client::tracked(rocket).await.unwrap()
+    }
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::{chain_on, wallet, TestChain};
+use my_rust_blockchain::blockchain::Block;
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::miner::Miner;
+use my_rust_blockchain::store::{SharedStore, SqliteStore};
+use my_rust_blockchain::transactions::Transaction;
+use my_rust_blockchain::utils::verify_chain_deep;
+use rocket::http::Status;
+use rocket::tokio::runtime::Handle;
+use rocket::tokio::task;
+use std::sync::Arc;
+
+
+/// Txids linked to the block at `idx`, in the order the block committed to them.
+async fn linked_txids(store: &SharedStore, idx: i32) -> Vec<String> {
+    let linked = store.transactions_in_blocks(idx, idx).await.unwrap();
+    linked.iter().map(|tx| tx.txid()).collect()
+}
+
+/// Store a sealed block the way `mine_block` does once mining is over.
+async fn add_block(node: &TestChain, block: Block) -> Block {
+    let blockchain = Arc::clone(&node.blockchain);
+    task::spawn_blocking(move || {
+        let mut blockchain = blockchain.lock().unwrap();
+        Handle::current().block_on(blockchain.add_block(block)).unwrap()
+    })
+    .await
+    .unwrap()
+}
+
+#[rocket::async_test]
+async fn blocks_link_only_the_transactions_they_committed_to() {
+    // The SQLite store, so that blocks and submitted transactions go through real database transactions
+    let store: SharedStore = Arc::new(SqliteStore::connect("sqlite::memory:", 1).await.unwrap());
+    let node = chain_on(store, Arc::new(ProofOfWork)).await;
+    let (alice, alice_secret) = wallet(node.store.as_ref(), 1_000).await;
+    let (bob, _) = wallet(node.store.as_ref(), 0).await;
+    let client = node.client().await;
+    let submit = async |nonce: i64| {
+        let mut tx = Transaction::new(alice.clone(), bob.clone(), 10, (nonce % 3) as i32, nonce);
+        tx.sign(&alice_secret).unwrap();
+        let response = client.post("/tx").json(&tx).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        tx.txid()
+    };
+
+    let mut before = Vec::new();
+    for nonce in 0..3 {
+        before.push(submit(nonce).await);
+    }
+    let (mut block, head) = node.template().await;
+
+    // Submitted after the template was built, while the block is being mined
+    let mut during = Vec::new();
+    for nonce in 3..6 {
+        during.push(submit(nonce).await);
+    }
+    let miner = Miner::new(1);
+    assert!(block.seal(&ProofOfWork, &head.header, &miner, miner.tip()));
+    let stored = add_block(&node, block.clone()).await;
+
+    let committed: Vec<String> = block.body.iter().map(|tx| tx.txid()).collect();
+    assert_eq!(committed, before);
+    assert_eq!(linked_txids(&node.store, stored.header.idx).await, committed);
+    assert_eq!(node.mempool.len(), during.len());
+    assert!(during.iter().all(|txid| node.mempool.contains(txid)));
+    assert_eq!(node.store.pending_count().await.unwrap() as usize, during.len());
+
+    // The next block takes the rest
+    let next = node.mine().await.unwrap();
+    assert_eq!(linked_txids(&node.store, next.header.idx).await, during);
+    assert!(node.mempool.is_empty());
+    assert_eq!(node.store.pending_count().await.unwrap(), 0);
+
+    let report = verify_chain_deep(node.store.as_ref(), &ProofOfWork).await.unwrap();
+    assert!(report.is_valid(), "{}", serde_json::to_string(&report).unwrap());
+}
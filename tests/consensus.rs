@@ -0,0 +1,55 @@
+mod common;
+
+use common::chain;
+use my_rust_blockchain::blockchain::GENESIS_HASH;
+use my_rust_blockchain::consensus::{ProofOfAuthority, SharedConsensus};
+use my_rust_blockchain::crypto::{generate_keypair, sign};
+use my_rust_blockchain::utils::{verify_chain_deep, ApiError};
+use std::sync::Arc;
+
+
+const PERIOD: f64 = 0.1; // seconds, short slots keep the test fast
+
+fn authority(signers: &[String], secret_key: Option<&String>) -> SharedConsensus {
+    Arc::new(ProofOfAuthority::new(signers.to_vec(), secret_key.cloned(), PERIOD).unwrap())
+}
+
+#[rocket::async_test]
+async fn signers_take_turns_and_other_seals_are_rejected() {
+    let keys: Vec<(String, String)> = (0..2).map(|_| generate_keypair()).collect();
+    let signers: Vec<String> = keys.iter().map(|(_, public_key)| public_key.clone()).collect();
+    let a = chain(authority(&signers, Some(&keys[0].0))).await;
+    let b = chain(authority(&signers, Some(&keys[1].0))).await;
+    let verifier = authority(&signers, None);
+
+    // Each signer seals in its own slots, the difficulty of the genesis block is kept
+    let a2 = a.mine().await.unwrap();
+    b.import(a2.clone()).await.unwrap();
+    let b3 = b.mine().await.unwrap();
+    assert!(b3.header.signature.is_some());
+    assert!(verifier.verify_seal(&b3.header, &b3.hash, &a2.header).is_ok());
+    assert_eq!(b3.header.difficulty, a2.header.difficulty);
+
+    // b's slot signed by a, or not signed at all, is refused
+    let mut forged = b3.clone();
+    forged.header.signature = Some(sign(&keys[0].0, &hex::decode(&b3.hash).unwrap()).unwrap());
+    assert!(matches!(a.import(forged).await, Err(ApiError::InvalidInput(_))));
+    let mut unsigned = b3.clone();
+    unsigned.header.signature = None;
+    assert!(matches!(a.import(unsigned).await, Err(ApiError::InvalidInput(_))));
+
+    a.import(b3.clone()).await.unwrap();
+    assert_eq!(a.blockchain.lock().unwrap().blockchain_head.hash, b3.hash);
+    // Every block weighs the same
+    assert_eq!(a.blockchain.lock().unwrap().head_work, 3);
+
+    let report = verify_chain_deep(a.store.as_ref(), verifier.as_ref()).await.unwrap();
+    assert!(report.is_valid(), "{}", serde_json::to_string(&report).unwrap());
+
+    // A node that is not a signer follows the chain but cannot seal
+    let follower = chain(Arc::clone(&verifier)).await;
+    assert_eq!(follower.blockchain.lock().unwrap().blockchain_head.hash, GENESIS_HASH);
+    assert!(matches!(follower.mine().await, Err(ApiError::Conflict(_))));
+    follower.import(a2).await.unwrap();
+    follower.import(b3).await.unwrap();
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::{chain_on, tamper_block, tamperable_store};
+use my_rust_blockchain::blockchain::{block_by_idx, is_retarget_height, now_millis, retarget, retarget_interval, retarget_window_start, target_block_time};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::miner::Miner;
+use my_rust_blockchain::utils::{verify_db_state_streaming, ApiError};
+use std::sync::Arc;
+
+
+#[test]
+fn difficulty_moves_one_bit_per_doubling_of_the_block_time() {
+    // 10 seconds for a 100 second window: three doublings too fast
+    assert_eq!(retarget(10, 10.0, 100.0), 13);
+    assert_eq!(retarget(10, 100.0, 100.0), 10);
+    assert_eq!(retarget(10, 400.0, 100.0), 8);
+
+    // At most 4 bits at once and never below 1
+    assert_eq!(retarget(10, 0.0, 100.0), 14);
+    assert_eq!(retarget(10, 1e9, 100.0), 6);
+    assert_eq!(retarget(2, 1e9, 100.0), 1);
+}
+
+#[test]
+fn difficulty_is_retargeted_from_the_previous_window() {
+    let interval = retarget_interval();
+
+    // The first window starts at the genesis block, later ones at the previous retarget
+    assert!(!is_retarget_height(1));
+    assert!(!is_retarget_height(interval));
+    assert!(is_retarget_height(interval + 1));
+    assert!(is_retarget_height(2 * interval + 1));
+    assert_eq!(retarget_window_start(interval + 1), 1);
+    assert_eq!(retarget_window_start(2 * interval + 1), interval);
+}
+
+#[rocket::async_test]
+async fn blocks_are_mined_and_verified_at_the_difficulty_of_their_timestamps() {
+    let store = tamperable_store().await;
+    let node = chain_on(Arc::clone(&store) as _, Arc::new(ProofOfWork)).await;
+    let interval = retarget_interval() as i32;
+    let initial = node.head().header.difficulty;
+
+    // The first window starts at the genesis block, created long before: as easy as one step allows
+    while node.store.height().await.unwrap() < interval + 1 {
+        node.mine().await.unwrap();
+    }
+    assert_eq!(node.head().header.difficulty, initial - 4);
+
+    // The next window is mined as fast as the miner goes, much faster than the target
+    while node.store.height().await.unwrap() < 2 * interval {
+        node.mine().await.unwrap();
+    }
+    let parent = node.head();
+    let window_start = retarget_window_start(parent.header.idx as i64 + 1);
+    let window_start_block = block_by_idx(store.as_ref(), window_start as i32).await.unwrap();
+    let expected = retarget(
+        parent.header.difficulty,
+        (parent.header.timestamp - window_start_block.header.timestamp) as f64 / 1000.0,
+        (parent.header.idx as i64 - window_start) as f64 * target_block_time(),
+    );
+    assert!(expected > parent.header.difficulty);
+
+    // A peer block keeping the old difficulty is refused
+    let (mut stale, head) = node.template().await;
+    assert_eq!(stale.header.difficulty, expected);
+    stale.header.difficulty = parent.header.difficulty;
+    let miner = Miner::new(1);
+    assert!(stale.seal(&ProofOfWork, &head.header, &miner, miner.tip()));
+    assert!(matches!(node.import(stale).await, Err(ApiError::InvalidInput(_))));
+
+    let retargeted = node.mine().await.unwrap();
+    assert_eq!(retargeted.header.difficulty, expected);
+    verify_db_state_streaming(store.as_ref(), &ProofOfWork).await.unwrap();
+
+    tamper_block(&store, retargeted.header.idx, &format!("difficulty = {}", parent.header.difficulty)).await;
+    let error = verify_db_state_streaming(store.as_ref(), &ProofOfWork).await.unwrap_err();
+    assert!(error.starts_with(&format!("difficulty mismatch at idx {}", retargeted.header.idx)), "{}", error);
+}
+
+#[rocket::async_test]
+async fn block_timestamps_follow_their_parent_and_stay_close_to_the_clock() {
+    let store = tamperable_store().await;
+    let node = chain_on(Arc::clone(&store) as _, Arc::new(ProofOfWork)).await;
+    node.mine().await.unwrap();
+    let miner = Miner::new(1);
+
+    // Back-dated to the parent's timestamp, then post-dated by an hour
+    let (template, head) = node.template().await;
+    assert!(template.header.timestamp > head.header.timestamp);
+    for timestamp in [head.header.timestamp, now_millis() + 60 * 60 * 1000] {
+        let mut block = template.clone();
+        block.header.timestamp = timestamp;
+        assert!(block.seal(&ProofOfWork, &head.header, &miner, miner.tip()));
+        assert!(matches!(node.import(block).await, Err(ApiError::InvalidInput(_))));
+    }
+
+    // A stored block back-dated and mined again is caught by the verification
+    let mut block = node.mine().await.unwrap();
+    block.header.timestamp = head.header.timestamp;
+    assert!(block.seal(&ProofOfWork, &head.header, &miner, miner.tip()));
+    let assignments = format!("timestamp = {}, nonce = {}, hash = '{}'", block.header.timestamp, block.header.nonce, block.hash);
+    tamper_block(&store, block.header.idx, &assignments).await;
+    let error = verify_db_state_streaming(store.as_ref(), &ProofOfWork).await.unwrap_err();
+    assert!(error.starts_with(&format!("block at idx {} has timestamp", block.header.idx)), "{}", error);
+}
@@ -0,0 +1,41 @@
+use my_rust_blockchain::crypto::{address_from_public_key, generate_keypair};
+use my_rust_blockchain::keystore::{decrypt_keystore, encrypt_keystore, read_keystore, write_keystore, Keystore};
+
+
+fn keystore(passphrase: &str) -> (String, Keystore) {
+    let (secret_key, pub_key) = generate_keypair();
+    let address = address_from_public_key(&pub_key).unwrap();
+    (secret_key.clone(), encrypt_keystore(&secret_key, &pub_key, &address, passphrase).unwrap())
+}
+
+#[test]
+fn the_passphrase_decrypts_the_secret_key() {
+    let (secret_key, keystore) = keystore("correct horse");
+    assert_ne!(keystore.ciphertext, secret_key);
+    assert_eq!(decrypt_keystore(&keystore, "correct horse").unwrap(), secret_key);
+
+    // Read back from its file, which is never overwritten
+    let path = std::env::temp_dir().join(format!("keystore-test-{}.json", std::process::id()));
+    write_keystore(&path, &keystore).unwrap();
+    assert!(write_keystore(&path, &keystore).is_err());
+    let read = read_keystore(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(decrypt_keystore(&read.unwrap(), "correct horse").unwrap(), secret_key);
+}
+
+#[test]
+fn a_wrong_passphrase_is_refused() {
+    let (_, keystore) = keystore("correct horse");
+    let error = decrypt_keystore(&keystore, "battery staple").unwrap_err();
+    assert_eq!(error, "wrong passphrase or corrupted keystore");
+}
+
+#[test]
+fn an_edited_public_key_is_refused() {
+    let (_, mut keystore) = keystore("correct horse");
+    let (_, other_pub_key) = generate_keypair();
+    keystore.pub_key = other_pub_key.clone();
+    keystore.address = address_from_public_key(&other_pub_key).unwrap();
+    let error = decrypt_keystore(&keystore, "correct horse").unwrap_err();
+    assert_eq!(error, "keystore public key does not match its secret key");
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{chain, wallet, TestChain};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::mempool::mempool_expiry;
+use my_rust_blockchain::p2p::Network;
+use my_rust_blockchain::transactions::{submit_transaction, Transaction};
+use rocket::http::Status;
+use std::sync::Arc;
+
+
+/// Submit a payment of `amount` paying `fee` from the wallet `from`, returning its txid.
+async fn pay(node: &TestChain, from: &(String, String), to: &str, amount: i32, fee: i32, nonce: i64) -> String {
+    let mut tx = Transaction::new(from.0.clone(), to.to_string(), amount, fee, nonce);
+    tx.sign(&from.1).unwrap();
+    submit_transaction(node.store.as_ref(), &node.mempool, &Network::disabled(), tx).await.unwrap().txid()
+}
+
+fn txids(node: &TestChain, max_size: usize, max_txs: usize) -> Vec<String> {
+    node.mempool.select(max_size, max_txs).into_iter().map(|entry| entry.txid).collect()
+}
+
+#[rocket::async_test]
+async fn blocks_take_the_best_fee_rates_within_the_limits() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let (alice, carol, dave) = (wallet(node.store.as_ref(), 100).await, wallet(node.store.as_ref(), 100).await, wallet(node.store.as_ref(), 100).await);
+    let bob = wallet(node.store.as_ref(), 0).await;
+    let low = pay(&node, &alice, &bob.0, 10, 1, 0).await;
+    let high = pay(&node, &carol, &bob.0, 10, 5, 0).await;
+    let middle = pay(&node, &dave, &bob.0, 10, 3, 0).await;
+
+    // Picked by fee rate, returned in the order they were accepted
+    assert_eq!(txids(&node, usize::MAX, 2), vec![high.clone(), middle.clone()]);
+    // Signatures vary in length, so do the sizes
+    let size = |txid: &String| node.mempool.get(txid).unwrap().size;
+    assert_eq!(txids(&node, size(&high) + 10, 10), vec![high.clone()]);
+    let smallest = [&low, &high, &middle].into_iter().map(size).min().unwrap();
+    assert!(txids(&node, smallest - 1, 10).is_empty());
+
+    // bob spends what the three paid him: his transaction only comes with all of them
+    let spend = pay(&node, &bob, &alice.0, 20, 10, 0).await;
+    assert_eq!(txids(&node, usize::MAX, 3), vec![low.clone(), high.clone(), middle.clone()]);
+    assert_eq!(txids(&node, usize::MAX, 4), vec![low.clone(), high.clone(), middle.clone(), spend.clone()]);
+
+    // The mempool lists the best fee rates first
+    let client = node.client().await;
+    let info: serde_json::Value = client.get("/mempool").dispatch().await.into_json().await.unwrap();
+    assert_eq!(info["count"], 4);
+    assert_eq!(info["fees"], 19);
+    let listed: Vec<&str> = info["transactions"].as_array().unwrap().iter().map(|entry| entry["txid"].as_str().unwrap()).collect();
+    assert_eq!(listed, vec![&spend, &high, &middle, &low]);
+
+    let response = client.get(format!("/mempool/{}", middle)).dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let entry: serde_json::Value = response.into_json().await.unwrap();
+    assert_eq!(entry["transaction"]["fee"], 3);
+    assert_eq!(client.get(format!("/mempool/{}", "ab".repeat(32))).dispatch().await.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn a_full_mempool_evicts_the_lowest_fee_rates() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let (alice, carol, dave) = (wallet(node.store.as_ref(), 100).await, wallet(node.store.as_ref(), 100).await, wallet(node.store.as_ref(), 100).await);
+    let (bob, erin) = (wallet(node.store.as_ref(), 0).await, wallet(node.store.as_ref(), 0).await);
+    let low = pay(&node, &alice, &bob.0, 10, 1, 0).await;
+    let high = pay(&node, &carol, &bob.0, 10, 5, 0).await;
+    // dave's payments are followed by a later one of his, the last by erin spending it
+    let middle = pay(&node, &dave, &bob.0, 10, 3, 0).await;
+    let funding = pay(&node, &dave, &erin.0, 20, 0, 1).await;
+    let spend = pay(&node, &erin, &bob.0, 5, 4, 0).await;
+
+    // Lowest fee rate first, skipping the transactions that others build on
+    let evicted = node.mempool.evict(node.store.as_ref(), 3, mempool_expiry()).await.unwrap();
+    assert_eq!(evicted, vec![low.clone(), spend.clone()]);
+    for txid in [&low, &spend] {
+        assert!(!node.mempool.contains(txid));
+        assert!(node.store.transaction(txid).await.unwrap().is_none());
+    }
+    for txid in [&high, &middle, &funding] {
+        assert!(node.mempool.contains(txid));
+    }
+
+    // The evicted transactions give back their funds and nonces
+    assert_eq!(node.balance(&alice.0).await, 100);
+    assert_eq!(node.store.wallet(&alice.0).await.unwrap().unwrap().nonce, 0);
+    assert_eq!(node.balance(&erin.0).await, 20);
+    assert_eq!(node.store.wallet(&erin.0).await.unwrap().unwrap().nonce, 0);
+    assert_eq!(node.balance(&bob.0).await, 20);
+
+    // Nothing more to evict below the limit
+    assert!(node.mempool.evict(node.store.as_ref(), 3, mempool_expiry()).await.unwrap().is_empty());
+}
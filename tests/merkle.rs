@@ -0,0 +1,70 @@
+use my_rust_blockchain::blockchain::Block;
+use my_rust_blockchain::merkle::{leaf_hash, merkle_branch, merkle_root, verify_merkle_proof, Position};
+use my_rust_blockchain::transactions::Transaction;
+
+
+/// Deterministic transactions: coinbases of consecutive heights paying the same address.
+fn transactions(count: i32) -> Vec<Transaction> {
+    (0..count).map(|height| Transaction::coinbase("miner".to_string(), 50, height, 0.0)).collect()
+}
+
+fn leaves(count: i32) -> Vec<String> {
+    transactions(count).iter().map(leaf_hash).collect()
+}
+
+#[test]
+fn merkle_roots_match_pinned_vectors() {
+    assert_eq!(leaf_hash(&transactions(1)[0]), "beaa57a46183b8393314650f41d8bfe05ba88ca399984e2b71fd7fecc94e3035");
+
+    let vectors = [
+        (0, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+        (1, "beaa57a46183b8393314650f41d8bfe05ba88ca399984e2b71fd7fecc94e3035"),
+        (2, "60f3acb8980e7856bd4131dcb7082733d84efab1ebb4708f8a07f1c2f7a2f47e"),
+        (3, "a145a7a5b4decc4b7f6a7da13eb154c756c9109aa96ee977553180c67705a177"),
+        (4, "bfa22a06a8d158251ce3449073ff7c816b572f01581917cb0c8c96e0ba6f866a"),
+        (5, "5c106946fa944256a26999e3fee7692bf5fed98f2de8eb3832309382b47d9613"),
+        (7, "bf38d667a7cceb85634354cece28f64659292fecabac595dd0538020deddf4a0"),
+    ];
+    for (count, root) in vectors {
+        assert_eq!(Block::merkle_root(&transactions(count)), root, "root of {} transactions", count);
+    }
+}
+
+#[test]
+fn distinct_transaction_lists_have_distinct_roots() {
+    // Duplicating the last transaction of an odd level used to leave the root unchanged
+    let mut duplicated = transactions(3);
+    duplicated.push(duplicated[2].clone());
+    assert_ne!(Block::merkle_root(&duplicated), Block::merkle_root(&transactions(3)));
+
+    // Leaves are prefixed hashes of the txid, a lone leaf is its own root
+    let transaction = &transactions(1)[0];
+    assert_ne!(leaf_hash(transaction), transaction.txid());
+    assert_eq!(merkle_root(&leaves(1)), leaf_hash(transaction));
+}
+
+#[test]
+fn every_leaf_has_a_verifying_branch() {
+    for count in 1..=9 {
+        let leaves = leaves(count);
+        let root = merkle_root(&leaves);
+        for (index, leaf) in leaves.iter().enumerate() {
+            let branch = merkle_branch(&leaves, index).unwrap();
+            assert!(verify_merkle_proof(leaf, &branch, &root), "leaf {} of {}", index, count);
+
+            // A branch only proves the leaf it was built for, at its position
+            if count > 1 {
+                let other = &leaves[(index + 1) % leaves.len()];
+                assert!(!verify_merkle_proof(other, &branch, &root), "leaf {} of {} with another leaf", index, count);
+
+                let mut flipped = branch.clone();
+                flipped[0].position = match flipped[0].position {
+                    Position::Left => Position::Right,
+                    Position::Right => Position::Left,
+                };
+                assert!(!verify_merkle_proof(leaf, &flipped, &root), "leaf {} of {} with a flipped step", index, count);
+            }
+        }
+        assert!(merkle_branch(&leaves, leaves.len()).is_none());
+    }
+}
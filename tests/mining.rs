@@ -0,0 +1,95 @@
+mod common;
+
+use common::{chain, wallet};
+use my_rust_blockchain::blockchain::{genesis_block, MinedBlock, MiningMode};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::miner::Miner;
+use my_rust_blockchain::transactions::Transaction;
+use rocket::http::Status;
+use std::sync::Arc;
+use std::time::Duration;
+
+
+#[test]
+fn only_continuous_mining_mines_without_pending_transactions() {
+    assert_eq!(MiningMode::from_name("on-demand"), MiningMode::OnDemand);
+    assert_eq!(MiningMode::from_name("WHEN_PENDING"), MiningMode::WhenPending);
+    assert_eq!(MiningMode::from_name(""), MiningMode::Continuous);
+
+    assert!(!MiningMode::Continuous.is_idle(0));
+    assert!(MiningMode::WhenPending.is_idle(0));
+    assert!(!MiningMode::WhenPending.is_idle(1));
+    // Blocks are only produced through POST /mine
+    assert!(MiningMode::OnDemand.is_idle(1));
+}
+
+#[rocket::async_test]
+async fn post_mine_appends_a_block_and_returns_it() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let alice = wallet(node.store.as_ref(), 100).await;
+    let (bob, _) = wallet(node.store.as_ref(), 0).await;
+    let txid = node.submit(&alice, &bob, 10, 0).await.unwrap();
+    let client = node.client().await;
+
+    let response = client.post("/mine").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let mined: MinedBlock = response.into_json().await.unwrap();
+    assert_eq!(mined.block.header.idx, 2);
+    assert_eq!(mined.block.hash, node.head().hash);
+    assert_eq!(node.store.height().await.unwrap(), 2);
+    assert!(mined.transactions.iter().any(|tx| tx.txid() == txid));
+    assert!(node.mempool.is_empty());
+    // In the order of the block body and its Merkle tree
+    let committed: Vec<String> = mined.block.body.iter().map(|tx| tx.txid()).collect();
+    assert_eq!(mined.transactions.iter().map(|tx| tx.txid()).collect::<Vec<_>>(), committed);
+    let listed: Vec<Transaction> = client.get("/chain/2/txs").dispatch().await.into_json().await.unwrap();
+    assert_eq!(listed.iter().map(|tx| tx.txid()).collect::<Vec<_>>(), committed);
+
+    // Without pending transactions the block is mined all the same
+    let response = client.post("/mine").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let mined: MinedBlock = response.into_json().await.unwrap();
+    assert_eq!(mined.block.header.idx, 3);
+    assert_eq!(mined.block.header.previous_hash, node.store.block_by_idx(2).await.unwrap().unwrap().hash);
+    let height: serde_json::Value = client.get("/chain/height").dispatch().await.into_json().await.unwrap();
+    assert_eq!(height["data"], 3);
+}
+
+#[test]
+fn a_tip_change_cancels_mining() {
+    let miner = Miner::new(2);
+    // Never solved within the test
+    let mut header = genesis_block().header;
+    header.difficulty = 64;
+
+    // The tip moved after the header was built but before mining started
+    let tip = miner.tip();
+    miner.tip_changed();
+    assert!(miner.mine(&header, tip).is_none());
+
+    // The tip moves while mining
+    let tip = miner.tip();
+    std::thread::scope(|scope| {
+        let mining = scope.spawn(|| miner.mine(&header, tip));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!mining.is_finished());
+        miner.tip_changed();
+        assert!(mining.join().unwrap().is_none());
+    });
+    assert!(miner.stats().hashes > 0);
+}
+
+#[rocket::async_test]
+async fn get_mining_reports_the_hashrate_of_the_last_block() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let client = node.client().await;
+    let stats: serde_json::Value = client.get("/mining").dispatch().await.into_json().await.unwrap();
+    assert_eq!(stats["hashrate"], 0);
+    assert_eq!(stats["hashes"], 0);
+
+    node.mine().await.unwrap();
+    let stats: serde_json::Value = client.get("/mining").dispatch().await.into_json().await.unwrap();
+    assert_eq!(stats["threads"], 1);
+    assert!(stats["hashrate"].as_u64().unwrap() > 0, "{}", stats);
+    assert!(stats["hashes"].as_u64().unwrap() > 0, "{}", stats);
+}
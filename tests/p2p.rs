@@ -0,0 +1,195 @@
+mod common;
+
+use common::wallet_in;
+use my_rust_blockchain::blockchain::{block_by_idx, mine_block, Blockchain, SharedBlockchain};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::mempool::{Mempool, SharedMempool};
+use my_rust_blockchain::miner::Miner;
+use my_rust_blockchain::p2p::{read_message, write_message, InvItem, Message, Network, Node, SharedNetwork};
+use my_rust_blockchain::store::{MemoryStore, SharedStore};
+use my_rust_blockchain::sync::{self, ChainSync, SharedSync, SyncState};
+use my_rust_blockchain::transactions::{submit_transaction, Transaction};
+use my_rust_blockchain::utils::verify_chain_deep;
+use rocket::local::asynchronous::Client;
+use rocket::tokio::{io, task, time};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+struct TestNode {
+    store: SharedStore,
+    mempool: SharedMempool,
+    blockchain: SharedBlockchain,
+    network: SharedNetwork,
+}
+
+/// Node listening on a free localhost port and connecting to `seeds`.
+async fn start_node(store: SharedStore, seeds: Vec<String>) -> TestNode {
+    let mempool = Arc::new(Mempool::load(store.as_ref()).await.unwrap());
+    let network = Arc::new(Network::new(Some("127.0.0.1:0".to_string()), seeds, 8));
+    let blockchain = Blockchain::new(Arc::clone(&store), Arc::new(ProofOfWork), Arc::new(Miner::new(1)), Arc::clone(&mempool), Arc::clone(&network))
+        .await
+        .unwrap();
+    let blockchain: SharedBlockchain = Arc::new(Mutex::new(blockchain));
+
+    let node = Node {
+        store: Arc::clone(&store),
+        consensus: Arc::new(ProofOfWork),
+        mempool: Arc::clone(&mempool),
+        blockchain: Arc::clone(&blockchain),
+    };
+    network.start(node).await.unwrap();
+    TestNode { store, mempool, blockchain, network }
+}
+
+async fn eventually(what: &str, mut condition: impl AsyncFnMut() -> bool) {
+    let deadline = time::Instant::now() + TIMEOUT;
+    while !condition().await {
+        assert!(time::Instant::now() < deadline, "timed out waiting for {}", what);
+        time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[rocket::async_test]
+async fn transactions_and_blocks_reach_every_node() {
+    let stores: [SharedStore; 3] = [Arc::new(MemoryStore::new()), Arc::new(MemoryStore::new()), Arc::new(MemoryStore::new())];
+    let (alice, alice_secret) = &wallet_in(&stores.each_ref().map(AsRef::as_ref), 1_000).await;
+    let (bob, _) = &wallet_in(&stores.each_ref().map(AsRef::as_ref), 0).await;
+
+    // a <- b <- c: transactions and blocks from a only reach c through b
+    let a = start_node(Arc::clone(&stores[0]), Vec::new()).await;
+    let b = start_node(Arc::clone(&stores[1]), vec![a.network.local_addr().unwrap().to_string()]).await;
+    let c = start_node(Arc::clone(&stores[2]), vec![b.network.local_addr().unwrap().to_string()]).await;
+    eventually("the nodes to connect", async || b.network.peers().len() == 2).await;
+    assert!(c.network.peers().iter().all(|peer| !peer.inbound));
+
+    let mut tx = Transaction::new(alice.clone(), bob.clone(), 10, 1, 0);
+    tx.sign(alice_secret).unwrap();
+    let txid = submit_transaction(a.store.as_ref(), &a.mempool, &a.network, tx).await.unwrap().txid();
+    eventually("the transaction to reach c", async || c.mempool.contains(&txid)).await;
+
+    let mined = task::spawn_blocking({
+        let blockchain = Arc::clone(&a.blockchain);
+        move || mine_block(&blockchain)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    eventually("the block to reach c", async || c.store.height().await.unwrap() == 2).await;
+
+    for node in [&b, &c] {
+        assert_eq!(block_by_idx(node.store.as_ref(), 2).await.unwrap().hash, mined.block.hash);
+        assert!(node.mempool.is_empty());
+        assert_eq!(node.blockchain.lock().unwrap().blockchain_head.hash, mined.block.hash);
+        let report = verify_chain_deep(node.store.as_ref(), &ProofOfWork).await.unwrap();
+        assert!(report.is_valid(), "{}", serde_json::to_string(&report).unwrap());
+    }
+}
+
+#[rocket::async_test]
+async fn a_new_node_downloads_the_chain_before_it_is_synced() {
+    let stores: [SharedStore; 2] = [Arc::new(MemoryStore::new()), Arc::new(MemoryStore::new())];
+    let (alice, alice_secret) = &wallet_in(&stores.each_ref().map(AsRef::as_ref), 1_000).await;
+    let (bob, _) = &wallet_in(&stores.each_ref().map(AsRef::as_ref), 0).await;
+
+    let a = start_node(Arc::clone(&stores[0]), Vec::new()).await;
+    let mut mined = Vec::new();
+    for nonce in 0..3 {
+        let mut tx = Transaction::new(alice.clone(), bob.clone(), 10, 1, nonce);
+        tx.sign(alice_secret).unwrap();
+        submit_transaction(a.store.as_ref(), &a.mempool, &a.network, tx).await.unwrap();
+        let blockchain = Arc::clone(&a.blockchain);
+        mined.push(task::spawn_blocking(move || mine_block(&blockchain)).await.unwrap().unwrap().block);
+    }
+
+    // b only learns about the blocks by asking a for the headers beyond its genesis
+    let b = start_node(Arc::clone(&stores[1]), vec![a.network.local_addr().unwrap().to_string()]).await;
+    let node = Node {
+        store: Arc::clone(&b.store),
+        consensus: Arc::new(ProofOfWork),
+        mempool: Arc::clone(&b.mempool),
+        blockchain: Arc::clone(&b.blockchain),
+    };
+    let sync = Arc::new(ChainSync::new());
+    rocket::tokio::spawn(Arc::clone(&sync).run(node, Arc::clone(&b.network)));
+    time::timeout(TIMEOUT, sync.wait_synced()).await.expect("timed out waiting for b to sync");
+
+    let progress = sync.progress();
+    assert_eq!(progress.state, SyncState::Synced);
+    assert_eq!(progress.height, 4);
+    for block in &mined {
+        assert_eq!(block_by_idx(b.store.as_ref(), block.header.idx).await.unwrap().hash, block.hash);
+    }
+    assert_eq!(b.blockchain.lock().unwrap().blockchain_head.hash, mined[2].hash);
+    let report = verify_chain_deep(b.store.as_ref(), &ProofOfWork).await.unwrap();
+    assert!(report.is_valid(), "{}", serde_json::to_string(&report).unwrap());
+}
+
+#[rocket::async_test]
+async fn get_sync_reports_the_download_progress() {
+    let stores: [SharedStore; 2] = [Arc::new(MemoryStore::new()), Arc::new(MemoryStore::new())];
+    let a = start_node(Arc::clone(&stores[0]), Vec::new()).await;
+    for _ in 0..3 {
+        let blockchain = Arc::clone(&a.blockchain);
+        task::spawn_blocking(move || mine_block(&blockchain)).await.unwrap().unwrap();
+    }
+
+    let seed = a.network.local_addr().unwrap().to_string();
+    let b = start_node(Arc::clone(&stores[1]), vec![seed.clone()]).await;
+    let node = Node {
+        store: Arc::clone(&b.store),
+        consensus: Arc::new(ProofOfWork),
+        mempool: Arc::clone(&b.mempool),
+        blockchain: Arc::clone(&b.blockchain),
+    };
+    let sync: SharedSync = Arc::new(ChainSync::new());
+    let client = Client::tracked(rocket::build().manage(Arc::clone(&sync)).mount("/", sync::routes())).await.unwrap();
+
+    // Holding b's chain stalls the import of the downloaded blocks, leaving the sync midway
+    let (locked, release) = (mpsc::channel(), mpsc::channel::<()>());
+    let holder = thread::spawn({
+        let blockchain = Arc::clone(&b.blockchain);
+        move || {
+            let _guard = blockchain.lock().unwrap();
+            locked.0.send(()).unwrap();
+            release.1.recv().unwrap();
+        }
+    });
+    locked.1.recv().unwrap();
+    rocket::tokio::spawn(Arc::clone(&sync).run(node, Arc::clone(&b.network)));
+
+    let get_sync = async || -> serde_json::Value { client.get("/sync").dispatch().await.into_json().await.unwrap() };
+    eventually("b to start downloading", async || get_sync().await["state"] == "downloading").await;
+    let progress = get_sync().await;
+    assert_eq!(progress["height"], 1);
+    assert_eq!(progress["target_height"], 4);
+    assert_eq!(progress["peer"], seed.as_str());
+
+    release.0.send(()).unwrap();
+    holder.join().unwrap();
+    time::timeout(TIMEOUT, sync.wait_synced()).await.expect("timed out waiting for b to sync");
+    let progress = get_sync().await;
+    assert_eq!(progress["state"], "synced");
+    assert_eq!(progress["height"], 4);
+    assert_eq!(progress["target_height"], 4);
+    assert!(progress["peer"].is_null());
+}
+
+#[rocket::async_test]
+async fn messages_round_trip_through_the_codec() {
+    let (mut client, mut server) = io::duplex(1024);
+    let message = Message::Inv(vec![InvItem::tx(&"ab".repeat(32))]);
+    write_message(&mut client, &message).await.unwrap();
+    match read_message(&mut server).await.unwrap() {
+        Message::Inv(items) => assert_eq!(items, vec![InvItem::tx(&"ab".repeat(32))]),
+        _ => panic!("decoded another message"),
+    }
+
+    // Anything not starting with the magic bytes is rejected
+    io::AsyncWriteExt::write_all(&mut client, b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    let error = read_message(&mut server).await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use common::{chain, wallet, wallet_in};
+use my_rust_blockchain::blockchain::{block_by_idx, block_work, genesis_block, Block};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::miner::Miner;
+use my_rust_blockchain::transactions::Wallet;
+use my_rust_blockchain::utils::{verify_chain_deep, ApiError};
+use std::sync::Arc;
+
+
+#[rocket::async_test]
+async fn the_branch_with_the_most_work_becomes_the_main_chain() {
+    let a = chain(Arc::new(ProofOfWork)).await;
+    let b = chain(Arc::new(ProofOfWork)).await;
+    let stores = [a.store.as_ref(), b.store.as_ref()];
+    let (alice, dave) = (&wallet_in(&stores, 1_000).await, &wallet_in(&stores, 1_000).await);
+    let (bob, carol) = (&wallet_in(&stores, 0).await.0, &wallet_in(&stores, 0).await.0);
+
+    // a confirms two payments to bob, b spends alice's nonce 0 on carol and builds one block further
+    let to_bob = a.submit(alice, bob, 10, 0).await.unwrap();
+    let from_dave = a.submit(dave, bob, 20, 0).await.unwrap();
+    let a1 = a.mine().await.unwrap();
+    b.submit(alice, carol, 30, 0).await.unwrap();
+    let b1 = b.mine().await.unwrap();
+    let b2 = b.mine().await.unwrap();
+
+    // Equal work: b1 is kept on a side branch and the chain seen first stays
+    a.import(b1.clone()).await.unwrap();
+    assert_eq!(a.head().hash, a1.hash);
+    assert!(a.store.side_block(&b1.hash).await.unwrap().is_some());
+    assert!(matches!(a.import(b1.clone()).await, Err(ApiError::Conflict(_))));
+
+    // More work: a disconnects a1 and connects b1 and b2
+    a.import(b2.clone()).await.unwrap();
+    assert_eq!(a.head().hash, b2.hash);
+    assert_eq!(block_by_idx(a.store.as_ref(), 2).await.unwrap().hash, b1.hash);
+    assert!(a.store.side_block(&a1.hash).await.unwrap().is_some());
+    assert!(a.store.side_block(&b1.hash).await.unwrap().is_none());
+    let genesis = block_by_idx(a.store.as_ref(), 1).await.unwrap();
+    let work: u128 = [&genesis, &b1, &b2].iter().map(|block| block_work(block.header.difficulty)).sum();
+    assert_eq!(a.blockchain.lock().unwrap().head_work, work);
+
+    // dave's payment is pending again, alice's conflicts with the new chain and is dropped
+    assert!(a.mempool.contains(&from_dave));
+    assert!(!a.mempool.contains(&to_bob));
+    assert_eq!(a.balance(&alice.0).await, 1_000 - 31);
+    assert_eq!(a.balance(carol).await, 30);
+    assert_eq!(a.balance(&dave.0).await, 1_000 - 21);
+    assert_eq!(a.balance(bob).await, 20);
+    assert_eq!(Wallet::next_nonce(a.store.as_ref(), &alice.0).await.unwrap(), 1);
+
+    let report = verify_chain_deep(a.store.as_ref(), &ProofOfWork).await.unwrap();
+    assert!(report.is_valid(), "{}", serde_json::to_string(&report).unwrap());
+}
+
+#[rocket::async_test]
+async fn a_peer_block_is_checked_against_the_confirmed_state() {
+    let a = chain(Arc::new(ProofOfWork)).await;
+    let b = chain(Arc::new(ProofOfWork)).await;
+    let stores = [a.store.as_ref(), b.store.as_ref()];
+    let (alice, dave) = (&wallet_in(&stores, 100).await, &wallet_in(&stores, 1_000).await);
+    let (bob, carol) = (&wallet_in(&stores, 0).await.0, &wallet_in(&stores, 0).await.0);
+
+    // a has alice's nonce 0 pending to bob, b confirms it spent on carol: both cannot be afforded
+    let to_bob = a.submit(alice, bob, 60, 0).await.unwrap();
+    let from_dave = a.submit(dave, bob, 20, 0).await.unwrap();
+    let to_carol = b.submit(alice, carol, 60, 0).await.unwrap();
+    let b1 = b.mine().await.unwrap();
+
+    a.import(b1.clone()).await.unwrap();
+    assert_eq!(a.head().hash, b1.hash);
+
+    // dave's payment is pending again on top of the block, alice's conflicts with it and is dropped
+    assert!(a.mempool.contains(&from_dave));
+    assert!(!a.mempool.contains(&to_bob));
+    assert!(!a.mempool.contains(&to_carol));
+    assert_eq!(a.balance(&alice.0).await, 100 - 61);
+    assert_eq!(a.balance(carol).await, 60);
+    assert_eq!(a.balance(&dave.0).await, 1_000 - 21);
+    assert_eq!(a.balance(bob).await, 20);
+    assert_eq!(Wallet::next_nonce(a.store.as_ref(), &alice.0).await.unwrap(), 1);
+
+    let report = verify_chain_deep(a.store.as_ref(), &ProofOfWork).await.unwrap();
+    assert!(report.is_valid(), "{}", serde_json::to_string(&report).unwrap());
+}
+
+#[rocket::async_test]
+async fn an_invalid_branch_restores_the_previous_chain() {
+    let a = chain(Arc::new(ProofOfWork)).await;
+    let b = chain(Arc::new(ProofOfWork)).await;
+    let stores = [a.store.as_ref(), b.store.as_ref()];
+    let (alice, dave) = (&wallet_in(&stores, 1_000).await, &wallet_in(&stores, 1_000).await);
+    let (bob, carol) = (&wallet_in(&stores, 0).await.0, &wallet_in(&stores, 0).await.0);
+    // Only b knows erin, a cannot connect a block spending from her
+    let erin = &wallet(b.store.as_ref(), 1_000).await;
+
+    let to_bob = a.submit(alice, bob, 10, 0).await.unwrap();
+    let a1 = a.mine().await.unwrap();
+    let from_dave = a.submit(dave, bob, 20, 0).await.unwrap();
+    b.submit(alice, carol, 30, 0).await.unwrap();
+    let b1 = b.mine().await.unwrap();
+    b.submit(erin, carol, 40, 0).await.unwrap();
+    let b2 = b.mine().await.unwrap();
+
+    // b1 connects, b2 does not: a goes back to a1 with dave's payment pending
+    a.import(b1.clone()).await.unwrap();
+    assert!(matches!(a.import(b2.clone()).await, Err(ApiError::InvalidInput(_))));
+    assert_eq!(a.head().hash, a1.hash);
+    assert_eq!(block_by_idx(a.store.as_ref(), 2).await.unwrap().hash, a1.hash);
+    assert!(a.store.side_block(&b1.hash).await.unwrap().is_some());
+    assert!(a.store.side_block(&b2.hash).await.unwrap().is_none());
+    let genesis = block_by_idx(a.store.as_ref(), 1).await.unwrap();
+    let work: u128 = [&genesis, &a1].iter().map(|block| block_work(block.header.difficulty)).sum();
+    assert_eq!(a.blockchain.lock().unwrap().head_work, work);
+
+    assert_eq!(a.store.transaction(&to_bob).await.unwrap().unwrap().block_id, Some(a1.header.idx));
+    assert!(a.mempool.contains(&from_dave));
+    assert_eq!(a.mempool.len(), 1);
+    assert_eq!(a.balance(&alice.0).await, 1_000 - 11);
+    assert_eq!(a.balance(&dave.0).await, 1_000 - 21);
+    assert_eq!(a.balance(bob).await, 30);
+    assert_eq!(a.balance(carol).await, 0);
+    assert_eq!(Wallet::next_nonce(a.store.as_ref(), &dave.0).await.unwrap(), 1);
+
+    let report = verify_chain_deep(a.store.as_ref(), &ProofOfWork).await.unwrap();
+    assert!(report.is_valid(), "{}", serde_json::to_string(&report).unwrap());
+}
+
+#[rocket::async_test]
+async fn side_blocks_are_mined_at_the_difficulty_of_their_branch() {
+    let a = chain(Arc::new(ProofOfWork)).await;
+    let a1 = a.mine().await.unwrap();
+
+    // As cheap as a retarget could make it, but block 2 is not a retarget height
+    let genesis = genesis_block();
+    let mut cheap = Block::new(2, genesis.hash.clone(), Vec::new());
+    cheap.header.difficulty = a1.header.difficulty - 4;
+    let miner = Miner::new(1);
+    assert!(cheap.seal(&ProofOfWork, &genesis.header, &miner, miner.tip()));
+    assert!(matches!(a.import(cheap.clone()).await, Err(ApiError::InvalidInput(_))));
+    assert!(a.store.side_block(&cheap.hash).await.unwrap().is_none());
+}
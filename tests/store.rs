@@ -0,0 +1,206 @@
+mod common;
+
+use common::wallet;
+use my_rust_blockchain::blockchain::{genesis_block, now_millis, Block};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::crypto::{address_from_public_key, generate_keypair};
+use my_rust_blockchain::store::{ChainStore, MemoryStore, PostgresStore, SqliteStore};
+use my_rust_blockchain::transactions::Transaction;
+use my_rust_blockchain::utils::{verify_chain_deep, verify_db_state_streaming, ApiError};
+use sqlx::postgres::PgPoolOptions;
+
+
+const DEFAULT_CREATED_AT: &str = "INSERT INTO transactions (from_address, to_address, amount, sig) VALUES ('', '', 0, '') RETURNING created_at;";
+
+async fn balance(store: &dyn ChainStore, address: &str) -> Option<i32> {
+    store.wallet(address).await.unwrap().map(|wallet| wallet.balance)
+}
+
+/// Asserts `created_at` is about now in seconds since the unix epoch, the unit of every backend.
+fn assert_created_now(created_at: Option<f64>) {
+    let now = now_millis() as f64 / 1000.0;
+    let created_at = created_at.expect("created_at is set");
+    assert!(now - 60.0 <= created_at && created_at <= now + 1.0, "created_at {} is not about {}", created_at, now);
+}
+
+/// Block following the genesis block paying `fees` plus the subsidy to `miner`. The store does
+/// not check the seal, it is left unsolved.
+fn block(miner: &str, transactions: Vec<Transaction>) -> Block {
+    let fees: i32 = transactions.iter().map(|tx| tx.fee).sum();
+    let mut block = Block::new(2, genesis_block().hash, Vec::new());
+    let mut body = vec![Transaction::coinbase(miner.to_string(), 50 + fees, 1, block.header.timestamp as f64 / 1000.0)];
+    body.extend(transactions);
+    block.header.merkle_root = Block::merkle_root(&body);
+    block.body = body;
+    block.hash = block.calculate_hash();
+    block
+}
+
+/// Every backend must apply or refuse the same changes, in full.
+async fn check_store(store: &dyn ChainStore) {
+    let (alice, alice_secret) = wallet(store, 100).await;
+    let (bob, _) = wallet(store, 0).await;
+    // Paid by the coinbase only, it has no wallet until the block is connected
+    let miner = address_from_public_key(&generate_keypair().1).unwrap();
+    let sign = |to: &str, amount: i32, nonce: i64| {
+        let mut tx = Transaction::new(alice.clone(), to.to_string(), amount, 1, nonce);
+        tx.sign(&alice_secret).unwrap();
+        tx
+    };
+
+    assert!(store.connect_block(&genesis_block(), "").await.is_ok());
+    assert_eq!(store.height().await.unwrap(), 1);
+
+    // Accepting moves the funds and uses the nonce, a refused transaction changes nothing
+    let (rowid, pending) = store.accept_transaction(&sign(&bob, 10, 0)).await.unwrap();
+    assert_eq!(pending.block_id, None);
+    assert_created_now(pending.created_at);
+    assert_eq!(store.transaction(&pending.txid()).await.unwrap().unwrap().created_at, pending.created_at);
+    assert_eq!(balance(store, &alice).await, Some(89));
+    assert_eq!(balance(store, &bob).await, Some(10));
+    assert!(matches!(store.accept_transaction(&sign(&bob, 10, 0)).await, Err(ApiError::Conflict(_))));
+    assert!(matches!(store.accept_transaction(&sign(&bob, 100, 1)).await, Err(ApiError::Conflict(_))));
+    assert!(matches!(store.accept_transaction(&sign(&miner, 10, 1)).await, Err(ApiError::NotFound(_))));
+    assert_eq!(balance(store, &alice).await, Some(89));
+    assert_eq!(store.wallet(&alice).await.unwrap().unwrap().nonce, 1);
+    assert_eq!(store.pending_count().await.unwrap(), 1);
+    // A later nonce is queued before its balance is checked, the amount and the fee must add up anyway
+    let mut overflowing = Transaction::new(alice.clone(), bob.clone(), i32::MAX, 1, 5);
+    overflowing.sign(&alice_secret).unwrap();
+    assert!(!overflowing.is_valid(store).await.unwrap());
+    // The same signature in uppercase would relay the same transaction under another txid
+    let next = sign(&bob, 10, 1);
+    assert!(next.is_valid(store).await.unwrap());
+    let mut respelt = next.clone();
+    respelt.sig = next.sig.as_deref().map(str::to_uppercase);
+    assert_ne!(respelt.txid(), next.txid());
+    assert!(matches!(respelt.is_valid(store).await, Err(ApiError::InvalidInput(_))));
+    // Balances stay within an i32, whether credited by a payment or a coinbase
+    let (rich, _) = wallet(store, i32::MAX - 5).await;
+    assert!(matches!(store.accept_transaction(&sign(&rich, 10, 1)).await, Err(ApiError::Conflict(_))));
+    assert!(matches!(store.connect_block(&block(&rich, Vec::new()), "").await, Err(ApiError::Conflict(_))));
+    assert_eq!(balance(store, &rich).await, Some(i32::MAX - 5));
+    assert_eq!(balance(store, &alice).await, Some(89));
+    assert_eq!(store.height().await.unwrap(), 1);
+
+    // Reverting gives everything back
+    assert!(store.revert_pending(rowid, &pending).await.unwrap());
+    assert_eq!(balance(store, &alice).await, Some(100));
+    assert_eq!(balance(store, &bob).await, Some(0));
+    assert_eq!(store.wallet(&alice).await.unwrap().unwrap().nonce, 0);
+    assert!(store.pending_transactions().await.unwrap().is_empty());
+
+    // A block linking a transaction that is not pending is refused as a whole
+    let tx = sign(&bob, 10, 0);
+    let refused = block(&miner, vec![tx.clone()]);
+    assert!(matches!(store.connect_block(&refused, "").await, Err(ApiError::Conflict(_))));
+    assert_eq!(store.height().await.unwrap(), 1);
+    assert_eq!(balance(store, &miner).await, None);
+
+    store.accept_transaction(&tx).await.unwrap();
+    let block = block(&miner, vec![tx.clone()]);
+    store.put_side_block(&block).await.unwrap();
+    let stored = store.connect_block(&block, "").await.unwrap();
+    assert_eq!(stored.hash, block.hash);
+    assert_eq!(store.head().await.unwrap().unwrap().hash, block.hash);
+    assert!(store.side_block(&block.hash).await.unwrap().is_none());
+    assert_eq!(balance(store, &miner).await, Some(51));
+    assert_eq!(store.pending_count().await.unwrap(), 0);
+    // Read back in the order the Merkle tree was built in
+    let in_block: Vec<String> = store.block_transactions(2).await.unwrap().iter().map(|tx| tx.txid()).collect();
+    let committed: Vec<String> = block.body.iter().map(|tx| tx.txid()).collect();
+    assert_eq!(in_block, committed);
+    let linked = store.transactions_in_blocks(1, 2).await.unwrap();
+    assert_eq!(linked.iter().map(|tx| tx.txid()).collect::<Vec<_>>(), committed);
+    assert_eq!(linked[0].created_at, block.body[0].created_at);
+    for tx in &linked {
+        assert_created_now(tx.created_at);
+    }
+    assert_eq!(store.headers_after(1, 10).await.unwrap()[0].hash, block.hash);
+
+    // Disconnecting undoes the block and its transactions, latest first
+    store.disconnect_block(&block).await.unwrap();
+    assert_eq!(store.height().await.unwrap(), 1);
+    assert!(store.side_block(&block.hash).await.unwrap().is_some());
+    assert!(store.transaction(&tx.txid()).await.unwrap().is_none());
+    assert_eq!(balance(store, &alice).await, Some(100));
+    assert_eq!(balance(store, &bob).await, Some(0));
+    assert_eq!(balance(store, &miner).await, Some(0));
+    assert_eq!(store.wallet(&alice).await.unwrap().unwrap().nonce, 0);
+    assert!(store.check_integrity().await.is_ok());
+
+    // The remaining chain reads back whole, through the streamed summaries and the blocks
+    verify_db_state_streaming(store, &ProofOfWork).await.unwrap();
+    let report = verify_chain_deep(store, &ProofOfWork).await.unwrap();
+    assert!(report.first_bad_block.is_none());
+    assert_eq!(report.balance_mismatch_count, 0);
+}
+
+#[rocket::async_test]
+async fn memory_store() {
+    check_store(&MemoryStore::new()).await;
+}
+
+#[rocket::async_test]
+async fn sqlite_store() {
+    let store = SqliteStore::connect("sqlite::memory:", 1).await.unwrap();
+    check_store(&store).await;
+
+    // Rows inserted below the store, by older nodes or other tools, default to the same unit
+    let created_at: f64 = sqlx::query_scalar(DEFAULT_CREATED_AT).fetch_one(store.pool()).await.unwrap();
+    assert_created_now(Some(created_at));
+}
+
+#[rocket::async_test]
+async fn sqlite_store_backfills_missing_txids() {
+    let path = std::env::temp_dir().join(format!("store-backfill-test-{}.sqlite", std::process::id()));
+    let url = format!("sqlite://{}?mode=rwc", path.display());
+    let store = SqliteStore::connect(&url, 1).await.unwrap();
+    let (alice, alice_secret) = wallet(&store, 100).await;
+    let (bob, _) = wallet(&store, 0).await;
+    let mut tx = Transaction::new(alice, bob, 10, 1, 0);
+    tx.sign(&alice_secret).unwrap();
+    store.accept_transaction(&tx).await.unwrap();
+
+    // As a migration leaves them, along with the same payload submitted twice before txids existed
+    sqlx::query("UPDATE transactions SET txid = NULL;").execute(store.pool()).await.unwrap();
+    sqlx::query(
+        "INSERT INTO transactions (from_address, to_address, amount, sig, added_to_block, created_at, fee, nonce) \
+         SELECT from_address, to_address, amount, sig, added_to_block, created_at, fee, nonce FROM transactions;",
+    )
+    .execute(store.pool())
+    .await
+    .unwrap();
+    store.pool().close().await;
+
+    let store = SqliteStore::connect(&url, 1).await.unwrap();
+    assert!(store.transaction(&tx.txid()).await.unwrap().is_some());
+    let missing: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM transactions WHERE txid IS NULL;").fetch_one(store.pool()).await.unwrap();
+    assert_eq!(missing, 1);
+
+    store.pool().close().await;
+    std::fs::remove_file(path).unwrap();
+}
+
+/// Runs against the server at `TEST_POSTGRES_URL` (a user allowed to create databases), in a new
+/// database dropped at the end:
+/// `TEST_POSTGRES_URL=postgres://postgres@localhost/postgres cargo test --test store -- --ignored`
+#[rocket::async_test]
+#[ignore = "needs a Postgres server at TEST_POSTGRES_URL"]
+async fn postgres_store() {
+    let admin_url = std::env::var("TEST_POSTGRES_URL").expect("TEST_POSTGRES_URL is not set");
+    let admin = PgPoolOptions::new().max_connections(1).connect(&admin_url).await.unwrap();
+    let database = format!("chain_store_test_{}", std::process::id());
+    sqlx::query(&format!("DROP DATABASE IF EXISTS {};", database)).execute(&admin).await.unwrap();
+    sqlx::query(&format!("CREATE DATABASE {};", database)).execute(&admin).await.unwrap();
+
+    let (server, _) = admin_url.rsplit_once('/').expect("TEST_POSTGRES_URL has no database");
+    let store = PostgresStore::connect(&format!("{}/{}", server, database), 2).await.unwrap();
+    check_store(&store).await;
+    let created_at: f64 = sqlx::query_scalar(DEFAULT_CREATED_AT).fetch_one(store.pool()).await.unwrap();
+    assert_created_now(Some(created_at));
+
+    // The server may still be ending the sessions of the closed pool
+    store.pool().close().await;
+    sqlx::query(&format!("DROP DATABASE {} WITH (FORCE);", database)).execute(&admin).await.unwrap();
+}
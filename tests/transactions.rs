@@ -0,0 +1,195 @@
+mod common;
+
+use common::{chain, wallet};
+use my_rust_blockchain::consensus::ProofOfWork;
+use my_rust_blockchain::crypto::generate_keypair;
+use my_rust_blockchain::merkle::verify_merkle_proof;
+use my_rust_blockchain::p2p::Network;
+use my_rust_blockchain::transactions::{accept_transaction, promote_queued, submit_transaction, Transaction, TransactionProof};
+use my_rust_blockchain::utils::ApiError;
+use rocket::http::Status;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+
+/// `address` re-encoded with another version byte, under a checksum that matches it.
+fn with_version(address: &str, version: u8) -> String {
+    let mut bytes = bs58::decode(address).into_vec().unwrap();
+    bytes[0] = version;
+    let checksum = Sha256::digest(Sha256::digest(&bytes[..21]));
+    bytes[21..].copy_from_slice(&checksum[..4]);
+    bs58::encode(bytes).into_string()
+}
+
+/// `address` with its last checksum byte changed.
+fn with_bad_checksum(address: &str) -> String {
+    let mut bytes = bs58::decode(address).into_vec().unwrap();
+    bytes[24] ^= 1;
+    bs58::encode(bytes).into_string()
+}
+
+
+#[rocket::async_test]
+async fn post_tx_rejects_transactions_not_signed_by_the_sender() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let (alice, alice_secret) = wallet(node.store.as_ref(), 100).await;
+    let (bob, _) = wallet(node.store.as_ref(), 0).await;
+    let client = node.client().await;
+
+    let unsigned = Transaction::new(alice.clone(), bob.clone(), 10, 1, 0);
+    let mut signed_by_another_key = unsigned.clone();
+    signed_by_another_key.sign(&generate_keypair().0).unwrap();
+    let mut altered = unsigned.clone();
+    altered.sign(&alice_secret).unwrap();
+    altered.amount = 90;
+
+    for tx in [unsigned, signed_by_another_key, altered] {
+        let response = client.post("/tx").json(&tx).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+    assert!(node.mempool.is_empty());
+    assert_eq!(node.balance(&alice).await, 100);
+    assert_eq!(node.balance(&bob).await, 0);
+
+    let mut tx = Transaction::new(alice.clone(), bob.clone(), 10, 1, 0);
+    tx.sign(&alice_secret).unwrap();
+    assert_eq!(client.post("/tx").json(&tx).dispatch().await.status(), Status::Ok);
+    assert_eq!(node.balance(&bob).await, 10);
+}
+
+#[rocket::async_test]
+async fn malformed_addresses_are_rejected() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let (alice, alice_secret) = wallet(node.store.as_ref(), 100).await;
+    let (bob, _) = wallet(node.store.as_ref(), 0).await;
+    let client = node.client().await;
+    assert_eq!(client.get(format!("/wallet/{}", bob)).dispatch().await.status(), Status::Ok);
+
+    for address in [with_bad_checksum(&bob), with_version(&bob, 5)] {
+        let response = client.get(format!("/wallet/{}", address)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", address);
+
+        let mut tx = Transaction::new(alice.clone(), address.clone(), 10, 1, 0);
+        tx.sign(&alice_secret).unwrap();
+        let response = client.post("/tx").json(&tx).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", address);
+    }
+    assert_eq!(node.balance(&alice).await, 100);
+}
+
+#[rocket::async_test]
+async fn get_tx_reports_the_status_and_confirmations() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let alice = wallet(node.store.as_ref(), 100).await;
+    let (bob, _) = wallet(node.store.as_ref(), 0).await;
+    let client = node.client().await;
+    let txid = node.submit(&alice, &bob, 10, 0).await.unwrap();
+
+    let info: serde_json::Value = client.get(format!("/tx/{}", txid)).dispatch().await.into_json().await.unwrap();
+    assert_eq!(info["status"], "pending");
+    assert_eq!(info["confirmations"], 0);
+    assert!(info["block_idx"].is_null());
+
+    let block = node.mine().await.unwrap();
+    node.mine().await.unwrap();
+    let info: serde_json::Value = client.get(format!("/tx/{}", txid)).dispatch().await.into_json().await.unwrap();
+    assert_eq!(info["status"], "confirmed");
+    assert_eq!(info["block_idx"], block.header.idx);
+    assert_eq!(info["confirmations"], 2);
+
+    let response = client.get(format!("/tx/{}", "ab".repeat(32))).dispatch().await;
+    assert_eq!(response.status(), Status::NotFound);
+    for malformed in [txid.to_uppercase(), txid[1..].to_string(), format!("{}g", &txid[1..])] {
+        let response = client.get(format!("/tx/{}", malformed)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", malformed);
+    }
+}
+
+#[rocket::async_test]
+async fn a_replayed_transaction_is_a_conflict() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let (alice, alice_secret) = wallet(node.store.as_ref(), 100).await;
+    let (bob, _) = wallet(node.store.as_ref(), 0).await;
+    let mut tx = Transaction::new(alice.clone(), bob.clone(), 10, 1, 0);
+    tx.sign(&alice_secret).unwrap();
+    submit_transaction(node.store.as_ref(), &node.mempool, &Network::disabled(), tx.clone()).await.unwrap();
+
+    let replayed = submit_transaction(node.store.as_ref(), &node.mempool, &Network::disabled(), tx.clone()).await;
+    assert!(matches!(replayed, Err(ApiError::Conflict(_))));
+    let client = node.client().await;
+    assert_eq!(client.post("/tx").json(&tx).dispatch().await.status(), Status::Conflict);
+
+    // Still a replay once the transaction is in a block
+    node.mine().await.unwrap();
+    let replayed = submit_transaction(node.store.as_ref(), &node.mempool, &Network::disabled(), tx).await;
+    assert!(matches!(replayed, Err(ApiError::Conflict(_))));
+    assert_eq!(node.balance(&alice).await, 89);
+    assert_eq!(node.balance(&bob).await, 10);
+}
+
+#[rocket::async_test]
+async fn a_future_nonce_waits_for_the_gap_to_be_filled() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let (alice, alice_secret) = wallet(node.store.as_ref(), 100).await;
+    let (bob, _) = wallet(node.store.as_ref(), 0).await;
+    let signed = |nonce| {
+        let mut tx = Transaction::new(alice.clone(), bob.clone(), 10, 1, nonce);
+        tx.sign(&alice_secret).unwrap();
+        tx
+    };
+
+    let later = submit_transaction(node.store.as_ref(), &node.mempool, &Network::disabled(), signed(1)).await.unwrap();
+    assert_eq!(node.mempool.queued(&later.txid()).map(|tx| tx.txid()), Some(later.txid()));
+    assert!(node.mempool.is_empty());
+    assert_eq!(node.balance(&bob).await, 0);
+    let client = node.client().await;
+    let info: serde_json::Value = client.get(format!("/tx/{}", later.txid())).dispatch().await.into_json().await.unwrap();
+    assert_eq!(info["status"], "queued");
+
+    // Filling the gap leaves the queued transaction waiting until it is promoted
+    accept_transaction(node.store.as_ref(), &node.mempool, signed(0)).await.unwrap();
+    assert!(node.mempool.queued(&later.txid()).is_some());
+    let promoted = promote_queued(node.store.as_ref(), &node.mempool, &alice).await.unwrap();
+    assert_eq!(promoted.iter().map(Transaction::txid).collect::<Vec<_>>(), vec![later.txid()]);
+    assert!(node.mempool.queued(&later.txid()).is_none());
+    assert!(node.mempool.contains(&later.txid()));
+    assert_eq!(node.balance(&bob).await, 20);
+    assert_eq!(node.store.wallet(&alice).await.unwrap().unwrap().nonce, 2);
+    let info: serde_json::Value = client.get(format!("/tx/{}", later.txid())).dispatch().await.into_json().await.unwrap();
+    assert_eq!(info["status"], "pending");
+}
+
+#[rocket::async_test]
+async fn get_tx_proof_verifies_against_the_block_header() {
+    let node = chain(Arc::new(ProofOfWork)).await;
+    let (alice, carol) = (wallet(node.store.as_ref(), 100).await, wallet(node.store.as_ref(), 100).await);
+    let (bob, _) = wallet(node.store.as_ref(), 0).await;
+    let client = node.client().await;
+    let txids = vec![
+        node.submit(&alice, &bob, 10, 0).await.unwrap(),
+        node.submit(&carol, &bob, 10, 0).await.unwrap(),
+        node.submit(&alice, &bob, 10, 1).await.unwrap(),
+    ];
+
+    // Pending transactions have no proof yet
+    assert_eq!(client.get(format!("/tx/{}/proof", txids[0])).dispatch().await.status(), Status::Conflict);
+
+    let block = node.mine().await.unwrap();
+    for txid in &txids {
+        let response = client.get(format!("/tx/{}/proof", txid)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let proof: TransactionProof = response.into_json().await.unwrap();
+        assert_eq!(&proof.txid, txid);
+        assert_eq!(proof.block_hash, block.hash);
+        assert_eq!(proof.header.merkle_root, block.header.merkle_root);
+        assert!(!proof.branch.is_empty());
+        assert!(verify_merkle_proof(&proof.leaf, &proof.branch, &proof.header.merkle_root));
+        assert!(!verify_merkle_proof(&proof.leaf, &proof.branch[1..], &proof.header.merkle_root));
+    }
+
+    assert_eq!(client.get(format!("/tx/{}/proof", "ab".repeat(32))).dispatch().await.status(), Status::NotFound);
+    for malformed in [txids[0].to_uppercase(), txids[0][2..].to_string(), "not-a-txid".to_string()] {
+        let response = client.get(format!("/tx/{}/proof", malformed)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest, "{}", malformed);
+    }
+}